@@ -0,0 +1,342 @@
+use crate::{
+    clock::Clock,
+    trash::{escape_os_str, user_name},
+    Error, Result,
+};
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use uuid::Uuid;
+
+/// Hidden directory inside the trash holding the audit log and its rotated files.
+pub(crate) const AUDIT_DIR: &str = ".audit";
+
+/// Size from which the audit log is rotated before the next entry is appended.
+pub const MAX_LOG_SIZE: u64 = 4 * 1024 * 1024;
+
+/// How many rotated logs are kept besides the current one. Older entries are dropped.
+pub const ROTATED_LOGS: usize = 4;
+
+/// What an audit log entry records.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Action {
+    /// An item was moved to the trash.
+    #[display("trash")]
+    Trash,
+    /// An item was moved out of the trash.
+    #[display("restore")]
+    Restore,
+    /// An item was permanently deleted from the trash.
+    #[display("purge")]
+    Purge,
+    /// A path was permanently deleted without going through the trash.
+    #[display("delete")]
+    Delete,
+    /// The deletion date of an item changed, or it was pinned or unpinned.
+    #[display("update")]
+    Update,
+    /// A configuration value changed.
+    #[display("config")]
+    Config,
+}
+
+/// The details of an audit log entry. Fields that do not apply to the action are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub action: Action,
+    /// Id of the trashed item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Where the item was trashed from, restored to or deleted from. Left out for
+    /// encrypted items, whose original path is kept secret.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Size of the item in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletion_date: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<bool>,
+    /// Name of the configuration key that changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// New value of the configuration key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+impl Event {
+    /// Creates an event with none of the optional details, to be filled in with struct
+    /// update syntax.
+    pub fn new(action: Action) -> Self {
+        Self {
+            action,
+            id: None,
+            path: None,
+            size: None,
+            deletion_date: None,
+            pinned: None,
+            key: None,
+            value: None,
+        }
+    }
+
+    /// Sets the path of the event, escaped like everywhere else paths are displayed.
+    pub fn with_path(self, path: &Path) -> Self {
+        Self {
+            path: Some(escape_os_str(path.as_os_str())),
+            ..self
+        }
+    }
+}
+
+/// A line of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    /// Shared by every entry written by the same run of rrm.
+    pub op_id: String,
+    /// Name of the user who ran rrm, or their uid if it has no name.
+    pub user: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Append-only log of the operations on a trash, one JSON object per line, so what
+/// happened to an item can be found out after it is gone.
+#[derive(Clone)]
+pub struct AuditLog {
+    dir: PathBuf,
+    op_id: String,
+    user: String,
+    clock: Arc<dyn Clock>,
+    max_size: u64,
+}
+
+impl AuditLog {
+    /// Creates the log of the trash at `trash_dir`, timestamping entries with `clock`.
+    pub fn in_trash(trash_dir: &Path, clock: Arc<dyn Clock>) -> Self {
+        // SAFETY: getuid cannot fail and has no preconditions
+        let uid = unsafe { libc::getuid() };
+        Self {
+            dir: trash_dir.join(AUDIT_DIR),
+            op_id: Uuid::new_v4().to_string(),
+            user: user_name(uid).unwrap_or_else(|| uid.to_string()),
+            clock,
+            max_size: MAX_LOG_SIZE,
+        }
+    }
+
+    /// Sets the size from which the log is rotated, [`MAX_LOG_SIZE`] by default.
+    pub fn with_max_size(self, max_size: u64) -> Self {
+        Self { max_size, ..self }
+    }
+
+    /// Returns the id shared by the entries this log writes.
+    pub fn op_id(&self) -> &str {
+        &self.op_id
+    }
+
+    /// Appends an entry for `event`, rotating the log first if it is full.
+    pub fn record(&self, event: Event) -> Result<()> {
+        let entry = Entry {
+            time: self.clock.now(),
+            op_id: self.op_id.clone(),
+            user: self.user.clone(),
+            event,
+        };
+        let mut line = serde_json::to_vec(&entry).map_err(io::Error::from)?;
+        line.push(b'\n');
+
+        fs::create_dir_all(&self.dir)?;
+        // Writers of other processes, which may not hold the trash lock (e.g. `config set`),
+        // would otherwise both see a full log and rotate it twice
+        let _lock = self.lock(true)?;
+        let path = self.log_path(0);
+        match fs::metadata(&path) {
+            Ok(meta) if meta.len() > 0 && meta.len() + line.len() as u64 > self.max_size => {
+                self.rotate()?
+            }
+            _ => {}
+        }
+        // A single write to a file opened for appending is not interleaved with others
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?
+            .write_all(&line)?;
+        Ok(())
+    }
+
+    /// Returns every entry still in the log, oldest first. Lines that cannot be parsed
+    /// are skipped.
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let _lock = match self.lock(false) {
+            Ok(lock) => lock,
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        for n in (0..=ROTATED_LOGS).rev() {
+            let contents = match fs::read_to_string(self.log_path(n)) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for line in contents.lines().filter(|line| !line.is_empty()) {
+                match serde_json::from_str(line) {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => warn!("Skipping invalid audit log line: {}", e),
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Locks the log directory, shared for reading the logs or exclusively for writing and
+    /// rotating them. The lock is released when the returned file is dropped.
+    fn lock(&self, exclusive: bool) -> Result<File> {
+        let dir = File::open(&self.dir)?;
+        if exclusive {
+            dir.lock()?;
+        } else {
+            dir.lock_shared()?;
+        }
+        Ok(dir)
+    }
+
+    /// Shifts every log to the next rotated file, dropping the oldest one.
+    fn rotate(&self) -> Result<()> {
+        for n in (0..ROTATED_LOGS).rev() {
+            match fs::rename(self.log_path(n), self.log_path(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the path of the current log for 0, or else of the `n`th rotated one.
+    fn log_path(&self, n: usize) -> PathBuf {
+        self.dir.join(log_name(n))
+    }
+}
+
+/// Returns the file name of the current log for 0, or else of the `n`th rotated one.
+fn log_name(n: usize) -> String {
+    match n {
+        0 => "audit.jsonl".to_string(),
+        n => format!("audit.{}.jsonl", n),
+    }
+}
+
+/// Checks whether `name` is the name of the current log or of one that is kept after
+/// rotation, rather than a file the log directory should not hold.
+pub(crate) fn is_log_name(name: &OsStr) -> bool {
+    (0..=ROTATED_LOGS).any(|n| name == log_name(n).as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::FakeClock;
+    use chrono::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn rotation_keeps_the_newest_entries() -> Result<()> {
+        let trash_dir = tempdir()?;
+        let clock = FakeClock::new(Utc::now());
+        let log = AuditLog::in_trash(trash_dir.path(), Arc::new(clock.clone())).with_max_size(1);
+
+        // Every entry fills the log, so each one is rotated out by the next
+        for n in 0..ROTATED_LOGS + 3 {
+            log.record(Event {
+                id: Some(n.to_string()),
+                ..Event::new(Action::Trash)
+            })?;
+            clock.advance(Duration::seconds(1));
+        }
+
+        let ids: Vec<String> = log
+            .entries()?
+            .into_iter()
+            .filter_map(|entry| entry.event.id)
+            .collect();
+        assert_eq!(ids, vec!["2", "3", "4", "5", "6"]);
+        assert_eq!(fs::read_dir(trash_dir.path().join(AUDIT_DIR))?.count(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_writers_rotate_full_logs_once() -> Result<()> {
+        let trash_dir = tempdir()?;
+        let clock = Arc::new(FakeClock::new(Utc::now()));
+        let entry_size = {
+            let log = AuditLog::in_trash(trash_dir.path(), clock.clone());
+            log.record(Event::new(Action::Trash))?;
+            let size = fs::metadata(log.log_path(0))?.len();
+            fs::remove_file(log.log_path(0))?;
+            size
+        };
+
+        // Entries all have the same size, so every log is rotated once it holds exactly 10
+        // of them, unless two writers append to or rotate the same full log
+        let max_size = 10 * entry_size;
+        let logs: Vec<_> = (0..8)
+            .map(|_| AuditLog::in_trash(trash_dir.path(), clock.clone()).with_max_size(max_size))
+            .collect();
+        std::thread::scope(|scope| {
+            for log in &logs {
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        log.record(Event::new(Action::Trash)).unwrap();
+                    }
+                });
+            }
+        });
+
+        for n in 0..=ROTATED_LOGS {
+            assert_eq!(fs::metadata(logs[0].log_path(n))?.len(), max_size);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn entries_are_one_json_object_per_line() -> Result<()> {
+        let trash_dir = tempdir()?;
+        let now = DateTime::parse_from_rfc3339("2030-01-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let log = AuditLog::in_trash(trash_dir.path(), Arc::new(FakeClock::new(now)));
+        log.record(
+            Event {
+                id: Some("1234".to_string()),
+                size: Some(42),
+                ..Event::new(Action::Purge)
+            }
+            .with_path(Path::new("/home/me/notes.md")),
+        )?;
+
+        let contents = fs::read_to_string(log.log_path(0))?;
+        let json: serde_json::Value = serde_json::from_str(contents.trim_end()).unwrap();
+        assert_eq!(json["time"], "2030-01-01T12:00:00Z");
+        assert_eq!(json["op_id"], log.op_id());
+        assert_eq!(json["action"], "purge");
+        assert_eq!(json["path"], "/home/me/notes.md");
+        assert_eq!(json["size"], 42);
+        assert!(json.get("deletion_date").is_none());
+        Ok(())
+    }
+}
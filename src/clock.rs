@@ -0,0 +1,81 @@
+use crate::{Error, Result};
+use chrono::{DateTime, Utc};
+use std::{env, sync::Arc};
+
+/// Environment variable fixing the current time, as an RFC 3339 date like
+/// `2024-01-31T12:00:00Z`, so runs of the CLI are reproducible in tests.
+pub const NOW_VAR: &str = "RRM_NOW";
+
+/// Where the current time comes from. Every deletion date, expiry check and relative date
+/// display goes through it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock stopped at a given time, for runs of the CLI with [`NOW_VAR`] set.
+#[derive(Debug, Clone, Copy)]
+struct FixedClock(DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// A clock that only moves when told to, for tests. Clones share the same time, so a test
+/// can keep one and advance the time seen by a [`crate::TrashManager`] holding another.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone)]
+pub struct FakeClock {
+    now: Arc<std::sync::Mutex<DateTime<Utc>>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl FakeClock {
+    /// Creates a clock stopped at `now`.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(std::sync::Mutex::new(now)),
+        }
+    }
+
+    /// Moves the clock to `now`, which may be in its past.
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner()) = now;
+    }
+
+    /// Moves the clock forward by `duration`, or back if it is negative.
+    pub fn advance(&self, duration: chrono::Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        *now += duration;
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Clock for FakeClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Returns a clock stopped at the time in [`NOW_VAR`] if it is set, or else the system
+/// clock.
+pub fn from_env() -> Result<Arc<dyn Clock>> {
+    let Some(value) = env::var_os(NOW_VAR) else {
+        return Ok(Arc::new(SystemClock));
+    };
+    let value = value.to_string_lossy();
+    let now =
+        DateTime::parse_from_rfc3339(&value).map_err(|_| Error::InvalidNow(value.to_string()))?;
+    Ok(Arc::new(FixedClock(now.with_timezone(&Utc))))
+}
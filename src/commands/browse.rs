@@ -0,0 +1,449 @@
+use clap::Args;
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style, Stylize},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    DefaultTerminal, Frame,
+};
+use rrm::{
+    clock::Clock,
+    duration::parse_duration,
+    trash::{escape_os_str, MetadataUpdate, TrashBackend, TrashItem},
+    Error, Result,
+};
+use std::{collections::HashSet, fs, io::Read, path::PathBuf};
+
+/// Maximum number of bytes read from a file for the preview pane.
+const PREVIEW_BYTES: u64 = 16 * 1024;
+
+/// Maximum number of entries shown when previewing a directory.
+const PREVIEW_ENTRIES: usize = 200;
+
+const HELP: &str =
+    "/ search  space select  r restore  t restore to  e extend grace  p purge  q quit";
+
+#[derive(Args)]
+pub struct BrowseArgs {
+    /// Initial search query.
+    pub query: Option<String>,
+}
+
+pub fn handle_browse(
+    backend: impl TrashBackend,
+    clock: &dyn Clock,
+    args: BrowseArgs,
+) -> Result<()> {
+    let mut browser = Browser::new(&backend, clock, args.query.unwrap_or_default())?;
+
+    let mut terminal = ratatui::init();
+    let result = browser.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+/// What the keyboard input is currently driving.
+enum Mode {
+    Normal,
+    Search,
+    Prompt(Action, String),
+}
+
+/// Actions that need extra input from the user before running.
+#[derive(Clone, Copy)]
+enum Action {
+    RestoreTo,
+    ExtendGrace,
+    Purge,
+}
+
+impl Action {
+    fn prompt(&self) -> &str {
+        match self {
+            Action::RestoreTo => "Restore to directory: ",
+            Action::ExtendGrace => "Extend grace period by (e.g. 12h, 7d): ",
+            Action::Purge => "Permanently delete the selection? (y/n): ",
+        }
+    }
+}
+
+struct Browser<'a, B: TrashBackend> {
+    backend: &'a B,
+    clock: &'a dyn Clock,
+    items: Vec<TrashItem>,
+    /// Indexes into `items` matching the query, best match first.
+    visible: Vec<usize>,
+    list_state: ListState,
+    selected: HashSet<String>,
+    query: String,
+    mode: Mode,
+    status: String,
+    matcher: SkimMatcherV2,
+    preview: Option<(String, Vec<String>)>,
+}
+
+impl<'a, B: TrashBackend> Browser<'a, B> {
+    fn new(backend: &'a B, clock: &'a dyn Clock, query: String) -> Result<Self> {
+        let mut browser = Self {
+            backend,
+            clock,
+            items: Vec::new(),
+            visible: Vec::new(),
+            list_state: ListState::default(),
+            selected: HashSet::new(),
+            query,
+            mode: Mode::Normal,
+            status: String::new(),
+            matcher: SkimMatcherV2::default(),
+            preview: None,
+        };
+        browser.reload()?;
+        Ok(browser)
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            // Status messages only last until the next key press
+            self.status.clear();
+            if !self.handle_key(key) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reloads the items from the trash, dropping selections of items that are gone.
+    fn reload(&mut self) -> Result<()> {
+        self.items = self.backend.list()?;
+        self.items.sort_by_key(|item| item.deletion_date());
+        let ids: HashSet<&str> = self.items.iter().map(|item| item.id()).collect();
+        self.selected.retain(|id| ids.contains(id.as_str()));
+        self.preview = None;
+        self.refilter();
+        Ok(())
+    }
+
+    /// Applies the fuzzy query to the original paths of the items.
+    fn refilter(&mut self) {
+        let mut matches: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                if self.query.is_empty() {
+                    return Some((0, i));
+                }
+                self.matcher
+                    .fuzzy_match(&item.format_original_path(), &self.query)
+                    .map(|score| (score, i))
+            })
+            .collect();
+        // Stable sort keeps the deletion date order between equal scores
+        matches.sort_by_key(|(score, _)| -score);
+        self.visible = matches.into_iter().map(|(_, i)| i).collect();
+
+        let cursor = self.list_state.selected().unwrap_or(0);
+        self.list_state.select(match self.visible.len() {
+            0 => None,
+            len => Some(cursor.min(len - 1)),
+        });
+    }
+
+    fn current(&self) -> Option<&TrashItem> {
+        self.list_state
+            .selected()
+            .and_then(|i| self.visible.get(i))
+            .map(|&i| &self.items[i])
+    }
+
+    /// The ids the actions apply to: the selection, or the item under the cursor.
+    fn targets(&self) -> Vec<String> {
+        if !self.selected.is_empty() {
+            return self.selected.iter().cloned().collect();
+        }
+        self.current()
+            .map(|item| item.id().to_string())
+            .into_iter()
+            .collect()
+    }
+
+    /// Handles a key press, returning `false` when the browser should exit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match &mut self.mode {
+            Mode::Normal => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return false,
+                KeyCode::Down | KeyCode::Char('j') => self.list_state.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.list_state.select_previous(),
+                KeyCode::Char('/') => self.mode = Mode::Search,
+                KeyCode::Char(' ') => {
+                    if let Some(id) = self.current().map(|item| item.id().to_string()) {
+                        if !self.selected.remove(&id) {
+                            self.selected.insert(id);
+                        }
+                    }
+                    self.list_state.select_next();
+                }
+                KeyCode::Char('r') => self.run_action(|backend, _, id| backend.restore(id, None)),
+                KeyCode::Char('t') => self.start_prompt(Action::RestoreTo),
+                KeyCode::Char('e') => self.start_prompt(Action::ExtendGrace),
+                KeyCode::Char('p') | KeyCode::Delete => self.start_prompt(Action::Purge),
+                _ => {}
+            },
+            Mode::Search => match key.code {
+                KeyCode::Enter | KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Down => self.list_state.select_next(),
+                KeyCode::Up => self.list_state.select_previous(),
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.refilter();
+                }
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.refilter();
+                }
+                _ => {}
+            },
+            Mode::Prompt(action, input) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    if matches!(action, Action::Purge) {
+                        let (action, input) = (*action, std::mem::take(input));
+                        self.mode = Mode::Normal;
+                        self.submit_prompt(action, input);
+                    }
+                }
+                KeyCode::Enter => {
+                    let (action, input) = (*action, std::mem::take(input));
+                    self.mode = Mode::Normal;
+                    self.submit_prompt(action, input);
+                }
+                _ => {}
+            },
+        }
+        true
+    }
+
+    fn start_prompt(&mut self, action: Action) {
+        if self.targets().is_empty() {
+            self.status = "Nothing to act on".to_string();
+            return;
+        }
+        self.mode = Mode::Prompt(action, String::new());
+    }
+
+    fn submit_prompt(&mut self, action: Action, input: String) {
+        match action {
+            Action::RestoreTo => {
+                let dir = PathBuf::from(input.trim());
+                self.run_action(|backend, _, id| {
+                    let original_path = backend.get(id)?.original_path().to_path_buf();
+                    let file_name = original_path.file_name().ok_or_else(|| {
+                        Error::InvalidOriginalPath(escape_os_str(original_path.as_os_str()))
+                    })?;
+                    backend.restore(id, Some(&dir.join(file_name)))
+                });
+            }
+            Action::ExtendGrace => {
+                let duration = match parse_duration(&input) {
+                    Ok(duration) => duration,
+                    Err(e) => {
+                        self.status = e;
+                        return;
+                    }
+                };
+                self.run_action(|backend, clock, id| {
+                    let item = backend.get(id)?;
+                    // Extending an expired item counts from now, not from the past date
+                    let base = item.deletion_date().max(clock.now());
+                    backend.update(id, MetadataUpdate::DeletionDate(base + duration))
+                });
+            }
+            Action::Purge if input.eq_ignore_ascii_case("y") => {
+                self.run_action(|backend, _, id| backend.purge(id));
+            }
+            Action::Purge => self.status = "Purge cancelled".to_string(),
+        }
+    }
+
+    /// Runs `action` on every target and reports how many succeeded.
+    fn run_action(&mut self, action: impl Fn(&B, &dyn Clock, &str) -> Result<()>) {
+        let targets = self.targets();
+        let mut failures = Vec::new();
+        for id in &targets {
+            if let Err(e) = action(self.backend, self.clock, id) {
+                failures.push(e.to_string());
+            }
+        }
+
+        self.status = match failures.first() {
+            None => format!("Done: {} item(s)", targets.len()),
+            Some(first) => format!(
+                "{} of {} item(s) failed: {}",
+                failures.len(),
+                targets.len(),
+                first
+            ),
+        };
+        self.selected.clear();
+        if let Err(e) = self.reload() {
+            self.status = e.to_string();
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [search_area, main_area, status_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(main_area);
+
+        let search_title = match self.mode {
+            Mode::Search => "Search (enter to finish)",
+            _ => "Search",
+        };
+        frame.render_widget(
+            Paragraph::new(self.query.as_str()).block(Block::bordered().title(search_title)),
+            search_area,
+        );
+
+        let now = self.clock.now();
+        let rows: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let item = &self.items[i];
+                let mark = if self.selected.contains(item.id()) {
+                    "[x]"
+                } else {
+                    "[ ]"
+                };
+                ListItem::new(format!(
+                    "{} {}  ({}, {})",
+                    mark,
+                    item.format_original_path(),
+                    item.kind(),
+                    item.format_deletion_date(now)
+                ))
+            })
+            .collect();
+        let title = format!(
+            "Trash: {}/{} items, {} selected",
+            self.visible.len(),
+            self.items.len(),
+            self.selected.len()
+        );
+        let list = List::new(rows)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        let preview: Vec<Line> = self.preview().into_iter().map(Line::from).collect();
+        frame.render_widget(
+            Paragraph::new(preview).block(Block::bordered().title("Preview")),
+            preview_area,
+        );
+
+        let status = match &self.mode {
+            Mode::Prompt(action, input) => Line::from(format!("{}{}", action.prompt(), input)),
+            _ if !self.status.is_empty() => Line::from(self.status.as_str()),
+            _ => Line::from(HELP).dim(),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    /// Returns the preview lines of the item under the cursor, caching them per item.
+    fn preview(&mut self) -> Vec<String> {
+        let Some(item) = self.current() else {
+            return Vec::new();
+        };
+        match &self.preview {
+            Some((id, lines)) if *id == item.id() => lines.clone(),
+            _ => {
+                let lines = preview_item(item);
+                self.preview = Some((item.id().to_string(), lines.clone()));
+                lines
+            }
+        }
+    }
+}
+
+/// Renders the contents of a trashed item: the entries of a directory, the target of a
+/// symlink, or the beginning of a file.
+fn preview_item(item: &TrashItem) -> Vec<String> {
+    let meta = match item.path().symlink_metadata() {
+        Ok(meta) => meta,
+        Err(e) => return vec![format!("Cannot read item: {}", e)],
+    };
+
+    if meta.file_type().is_symlink() {
+        return match fs::read_link(item.path()) {
+            Ok(target) => vec![format!("-> {}", target.display())],
+            Err(e) => vec![format!("Cannot read link: {}", e)],
+        };
+    }
+
+    if meta.is_dir() {
+        let mut entries: Vec<String> = match fs::read_dir(item.path()) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    match entry.file_type() {
+                        Ok(kind) if kind.is_dir() => format!("{}/", name),
+                        _ => name,
+                    }
+                })
+                .collect(),
+            Err(e) => return vec![format!("Cannot read directory: {}", e)],
+        };
+        entries.sort();
+        entries.truncate(PREVIEW_ENTRIES);
+        return entries;
+    }
+
+    if item.encrypted() {
+        // Decrypting on every selection change would be slow with a passphrase
+        return vec![format!("Encrypted file, {} bytes", meta.len())];
+    }
+    if let Some(compression) = item.compression() {
+        return vec![format!(
+            "Compressed with {}, {} stored for {}",
+            compression.as_str(),
+            item.format_stored_size(),
+            item.format_size()
+        )];
+    }
+
+    let mut content = Vec::new();
+    let read = fs::File::open(item.path())
+        .and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut content));
+    if let Err(e) = read {
+        return vec![format!("Cannot read file: {}", e)];
+    }
+
+    if content.contains(&0) {
+        return vec![format!("Binary file, {} bytes", meta.len())];
+    }
+    String::from_utf8_lossy(&content)
+        .lines()
+        .map(|line| line.replace('\t', "    "))
+        .collect()
+}
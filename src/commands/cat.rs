@@ -0,0 +1,20 @@
+use clap::Args;
+use rrm::{trash::TrashBackend, Result};
+use std::io::{self, Write};
+
+#[derive(Args)]
+pub struct CatArgs {
+    #[clap(help = "The ID of the trashed file to print.", required = true)]
+    pub id: String,
+}
+
+pub fn handle_cat(backend: impl TrashBackend, args: CatArgs) -> Result<()> {
+    let item = backend.get(&args.id)?;
+    let content = backend.read(&item)?;
+
+    match io::stdout().lock().write_all(&content) {
+        // The reader went away (e.g. piped into `head`), which is not an error
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
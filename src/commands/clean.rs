@@ -1,5 +1,5 @@
-use crate::{trash::TrashManager, xattr::ExtendedAttributes, Result};
 use clap::Args;
+use rrm::{trash::TrashBackend, Result};
 
 #[derive(Args)]
 pub struct CleanArgs {
@@ -10,11 +10,23 @@ pub struct CleanArgs {
         default_value = "false"
     )]
     pub immediate: bool,
+    #[clap(
+        short,
+        long,
+        help = "Also delete pinned files or directories",
+        default_value = "false"
+    )]
+    pub force: bool,
+    #[clap(
+        short,
+        long,
+        help = "Overwrite the contents of every deleted file before unlinking it",
+        default_value = "false"
+    )]
+    pub shred: bool,
 }
 
-pub fn handle_clean<T: ExtendedAttributes>(
-    trash_manager: TrashManager<T>,
-    args: CleanArgs,
-) -> Result<()> {
-    trash_manager.clean_trash(args.immediate)
+/// `--shred` is applied to the backend before it is handed over, see `configure` in main.
+pub fn handle_clean(backend: impl TrashBackend, args: CleanArgs) -> Result<()> {
+    backend.clean(args.immediate, args.force)
 }
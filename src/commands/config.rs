@@ -1,5 +1,18 @@
-use crate::{config::Config, xattr::ExtendedAttributes, Result};
 use clap::{Args, Subcommand, ValueEnum};
+use rrm::{
+    audit::{Action, AuditLog, Event},
+    config::Config,
+    duration::{format_duration, parse_duration},
+    hooks::HookEvent,
+    patterns::{format_patterns, parse_patterns},
+    trash::{escape_os_str, format_size, parse_size},
+    xattr::ExtendedAttributes,
+    Error, Result,
+};
+use std::{
+    ffi::{OsStr, OsString},
+    path::Path,
+};
 
 #[derive(Args)]
 pub struct ConfigArgs {
@@ -35,7 +48,7 @@ enum ConfigAction {
             help = "The value to set the configuration key to.",
             required = true
         )]
-        value: String,
+        value: OsString,
     },
 }
 
@@ -43,32 +56,273 @@ enum ConfigAction {
 enum ConfigKey {
     #[clap(help = "The directory where deleted files are moved.")]
     TrashDir,
-    #[clap(help = "The number of days to wait before deleting the item permanently.")]
+    #[clap(help = "How long to wait before deleting the item permanently, e.g. 12h, 7d or 2w.")]
     GracePeriod,
+    #[clap(help = "Whether every permanently deleted file is shredded (true or false).")]
+    Shred,
+    #[clap(help = "How many times file contents are overwritten when shredding.")]
+    ShredPasses,
+    #[clap(help = "Comma separated globs of files that are always shredded, e.g. '*.pem,.env'.")]
+    ShredPatterns,
+    #[clap(
+        help = "Compress items of at least this size when they are trashed, e.g. 100M, or 'off'."
+    )]
+    CompressThreshold,
+    #[clap(
+        help = "Compress items once they have been in the trash this long, e.g. 24h, or 'off'."
+    )]
+    CompressAfter,
+    #[clap(
+        help = "Whether identical file contents are stored only once in the trash (true or false)."
+    )]
+    Dedup,
+    #[clap(help = "Whether every trashed item is encrypted (true or false).")]
+    Encrypt,
+    #[clap(
+        help = "Comma separated globs of files that are encrypted in the trash, e.g. '*.pem,.env'."
+    )]
+    EncryptPatterns,
+    #[clap(
+        help = "The file holding the encryption key, generated if it does not exist. Empty to use RRM_PASSPHRASE instead."
+    )]
+    EncryptionKeyFile,
+    #[clap(help = "Shell command run before an item is trashed, which can refuse it by failing.")]
+    PreTrashHook,
+    #[clap(help = "Shell command run after an item is trashed.")]
+    PostTrashHook,
+    #[clap(help = "Shell command run before an item is restored, which can refuse it by failing.")]
+    PreRestoreHook,
+    #[clap(help = "Shell command run after an item is restored.")]
+    PostRestoreHook,
+    #[clap(
+        help = "Shell command run before an item is permanently deleted, which can refuse it by failing."
+    )]
+    PrePurgeHook,
+    #[clap(help = "Shell command run after an item is permanently deleted.")]
+    PostPurgeHook,
 }
 
-pub fn handle_config<T: ExtendedAttributes>(config: Config<T>, args: ConfigArgs) -> Result<()> {
+pub fn handle_config<T: ExtendedAttributes>(
+    config: Config<T>,
+    audit_log: Option<&AuditLog>,
+    args: ConfigArgs,
+) -> Result<()> {
     match args.subcommand {
         ConfigAction::Get { key } => match key {
-            ConfigKey::TrashDir => println!("Trash directory: {}", config.trash_dir.display()),
+            ConfigKey::TrashDir => println!(
+                "Trash directory: {}",
+                escape_os_str(config.trash_dir.as_os_str())
+            ),
             ConfigKey::GracePeriod => {
-                println!("Grace period in days: {}", config.grace_period_in_days)
+                println!("Grace period: {}", format_duration(config.grace_period))
             }
+            ConfigKey::Shred => println!("Shred: {}", config.shred_policy.always),
+            ConfigKey::ShredPasses => println!("Shred passes: {}", config.shred_policy.passes),
+            ConfigKey::ShredPatterns => println!(
+                "Shred patterns: {}",
+                format_patterns(&config.shred_policy.patterns)
+            ),
+            ConfigKey::CompressThreshold => match config.compression_policy.threshold {
+                Some(threshold) => println!("Compress threshold: {}", format_size(threshold)),
+                None => println!("Compress threshold: off"),
+            },
+            ConfigKey::CompressAfter => match config.compression_policy.after {
+                Some(after) => println!("Compress after: {}", format_duration(after)),
+                None => println!("Compress after: off"),
+            },
+            ConfigKey::Dedup => println!("Dedup: {}", config.dedup),
+            ConfigKey::Encrypt => println!("Encrypt: {}", config.encryption_policy.always),
+            ConfigKey::EncryptPatterns => println!(
+                "Encrypt patterns: {}",
+                format_patterns(&config.encryption_policy.patterns)
+            ),
+            ConfigKey::EncryptionKeyFile => match &config.encryption_key_file {
+                Some(path) => println!("Encryption key file: {}", escape_os_str(path.as_os_str())),
+                None => println!("Encryption key file: none"),
+            },
+            ConfigKey::PreTrashHook => print_hook(&config, HookEvent::PreTrash),
+            ConfigKey::PostTrashHook => print_hook(&config, HookEvent::PostTrash),
+            ConfigKey::PreRestoreHook => print_hook(&config, HookEvent::PreRestore),
+            ConfigKey::PostRestoreHook => print_hook(&config, HookEvent::PostRestore),
+            ConfigKey::PrePurgeHook => print_hook(&config, HookEvent::PrePurge),
+            ConfigKey::PostPurgeHook => print_hook(&config, HookEvent::PostPurge),
         },
-        ConfigAction::Set { key, value } => match key {
-            ConfigKey::TrashDir => {
-                config.set_trash_dir(value.as_str())?;
-                println!("Set trash directory to {}", value);
+        ConfigAction::Set { key, value } => {
+            set_value(&config, key.clone(), &value)?;
+            let event = Event {
+                key: key
+                    .to_possible_value()
+                    .map(|key| key.get_name().to_string()),
+                value: Some(escape_os_str(&value)),
+                ..Event::new(Action::Config)
+            };
+            if let Some(Err(e)) = audit_log.map(|audit_log| audit_log.record(event)) {
+                eprintln!("Failed to write to the audit log: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets the configuration key `key` to `value`, failing if the value is invalid.
+fn set_value<T: ExtendedAttributes>(
+    config: &Config<T>,
+    key: ConfigKey,
+    value: &OsStr,
+) -> Result<()> {
+    match key {
+        ConfigKey::TrashDir => {
+            let path = Path::new(value);
+            config.set_trash_dir(path)?;
+            println!("Set trash directory to {}", escape_os_str(path.as_os_str()));
+        }
+        ConfigKey::GracePeriod => match parse_duration(&value.to_string_lossy()) {
+            Ok(value) => {
+                config.set_grace_period(value)?;
+                println!("Set grace period to {}", format_duration(value));
             }
-            ConfigKey::GracePeriod => match value.parse::<u32>() {
-                Ok(value) => {
-                    config.set_grace_period(value)?;
-                    println!("Set grace period in days to {}", value);
+            Err(e) => return Err(Error::InvalidConfig(e)),
+        },
+        ConfigKey::Shred => match value.to_string_lossy().parse::<bool>() {
+            Ok(shred) => {
+                config.set_shred(shred)?;
+                println!("Set shred to {}", shred);
+            }
+            Err(_) => return Err(not_a_bool(value)),
+        },
+        ConfigKey::ShredPasses => match value.to_string_lossy().parse::<u32>() {
+            Ok(passes) if passes > 0 => {
+                config.set_shred_passes(passes)?;
+                println!("Set shred passes to {}", passes);
+            }
+            _ => {
+                return Err(Error::InvalidConfig(format!(
+                    "'{}' is not a positive number of passes",
+                    value.to_string_lossy()
+                )))
+            }
+        },
+        ConfigKey::ShredPatterns => {
+            let value = value.to_string_lossy();
+            match parse_patterns(&value) {
+                Ok(_) => {
+                    config.set_shred_patterns(&value)?;
+                    println!("Set shred patterns to {}", value);
                 }
-                Err(_) => eprintln!("Grace period must be a positive integer."),
-            },
+                Err(e) => return Err(Error::InvalidPattern(e)),
+            }
+        }
+        ConfigKey::CompressThreshold if is_off(value) => {
+            config.set_compress_threshold(None)?;
+            println!("Disabled compression of large items");
+        }
+        ConfigKey::CompressThreshold => match parse_size(&value.to_string_lossy()) {
+            Ok(threshold) => {
+                config.set_compress_threshold(Some(threshold))?;
+                println!("Set compress threshold to {}", format_size(threshold));
+            }
+            Err(e) => return Err(Error::InvalidConfig(e)),
         },
+        ConfigKey::CompressAfter if is_off(value) => {
+            config.set_compress_after(None)?;
+            println!("Disabled compression of old items");
+        }
+        ConfigKey::CompressAfter => match parse_duration(&value.to_string_lossy()) {
+            Ok(after) => {
+                config.set_compress_after(Some(after))?;
+                println!("Set compress after to {}", format_duration(after));
+            }
+            Err(e) => return Err(Error::InvalidConfig(e)),
+        },
+        ConfigKey::Dedup => match value.to_string_lossy().parse::<bool>() {
+            Ok(dedup) => {
+                config.set_dedup(dedup)?;
+                println!("Set dedup to {}", dedup);
+            }
+            Err(_) => return Err(not_a_bool(value)),
+        },
+        ConfigKey::Encrypt => match value.to_string_lossy().parse::<bool>() {
+            Ok(encrypt) => {
+                config.set_encrypt(encrypt)?;
+                println!("Set encrypt to {}", encrypt);
+            }
+            Err(_) => return Err(not_a_bool(value)),
+        },
+        ConfigKey::EncryptPatterns => {
+            let value = value.to_string_lossy();
+            match parse_patterns(&value) {
+                Ok(_) => {
+                    config.set_encrypt_patterns(&value)?;
+                    println!("Set encrypt patterns to {}", value);
+                }
+                Err(e) => return Err(Error::InvalidPattern(e)),
+            }
+        }
+        ConfigKey::EncryptionKeyFile if value.is_empty() => {
+            config.set_encryption_key_file(None)?;
+            println!("Unset encryption key file, the key is derived from RRM_PASSPHRASE");
+        }
+        ConfigKey::EncryptionKeyFile => {
+            let path = std::path::absolute(Path::new(value))?;
+            if config.set_encryption_key_file(Some(&path))? {
+                println!(
+                        "Generated a new encryption key in {}, keep a backup of it: trashed items cannot be restored without it",
+                        escape_os_str(path.as_os_str())
+                    );
+            }
+            println!(
+                "Set encryption key file to {}",
+                escape_os_str(path.as_os_str())
+            );
+        }
+        ConfigKey::PreTrashHook => set_hook(config, HookEvent::PreTrash, value)?,
+        ConfigKey::PostTrashHook => set_hook(config, HookEvent::PostTrash, value)?,
+        ConfigKey::PreRestoreHook => set_hook(config, HookEvent::PreRestore, value)?,
+        ConfigKey::PostRestoreHook => set_hook(config, HookEvent::PostRestore, value)?,
+        ConfigKey::PrePurgeHook => set_hook(config, HookEvent::PrePurge, value)?,
+        ConfigKey::PostPurgeHook => set_hook(config, HookEvent::PostPurge, value)?,
+    }
+    Ok(())
+}
+
+fn print_hook<T: ExtendedAttributes>(config: &Config<T>, event: HookEvent) {
+    match config.hooks.command(event) {
+        Some(command) => println!("The {} hook: {}", event, command),
+        None => println!("The {} hook: none", event),
     }
+}
 
+/// Sets the shell command of a hook, or removes it if `value` is empty.
+fn set_hook<T: ExtendedAttributes>(
+    config: &Config<T>,
+    event: HookEvent,
+    value: &OsStr,
+) -> Result<()> {
+    let command = value.to_str().ok_or_else(|| {
+        Error::InvalidConfig(format!(
+            "the {} hook '{}' is not valid UTF-8",
+            event,
+            escape_os_str(value)
+        ))
+    })?;
+    config.set_hook(event, command)?;
+    if command.is_empty() {
+        println!("Removed the {} hook", event);
+    } else {
+        println!("Set the {} hook to {}", event, command);
+    }
     Ok(())
 }
+
+fn not_a_bool(value: &OsStr) -> Error {
+    Error::InvalidConfig(format!(
+        "'{}' is not true or false",
+        value.to_string_lossy()
+    ))
+}
+
+/// Returns whether a value disables an optional setting.
+fn is_off(value: &OsStr) -> bool {
+    value.is_empty() || value == "off"
+}
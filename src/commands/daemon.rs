@@ -0,0 +1,82 @@
+use log::{error, info};
+use notify::{Event, RecursiveMode, Watcher};
+use rrm::{clock::Clock, trash::TrashBackend, Result};
+use std::{
+    path::Path,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+/// Upper bound on a single sleep, so suspends and clock changes are caught up on.
+const MAX_SLEEP: Duration = Duration::from_secs(60 * 60);
+
+/// Delay before retrying items that are past their deletion date but could not be deleted.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Time given to a burst of changes in the trash (e.g. `rrm rm *`) to settle.
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// Runs in the foreground, cleaning each item as soon as its grace period is over.
+/// Pinned items are never cleaned by the daemon.
+pub fn handle_daemon(
+    backend: impl TrashBackend,
+    clock: &dyn Clock,
+    trash_dir: &Path,
+) -> Result<()> {
+    // Any change in the trash (new items, updated metadata) may move the next deletion date
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    watcher.watch(trash_dir, RecursiveMode::NonRecursive)?;
+    println!("Watching {} for expired items", trash_dir.display());
+
+    loop {
+        // A failed clean is retried on the next wake up rather than stopping the daemon
+        if let Err(e) = backend.clean(false, false) {
+            error!("Failed to clean the trash: {}", e);
+            eprintln!("Failed to clean the trash: {}", e);
+        }
+
+        let timeout = match backend.next_deletion_date() {
+            Ok(Some(date)) => {
+                info!("Next item expires at {}", date.to_rfc3339());
+                // A date in the past means the clean above failed to delete the item
+                (date - clock.now())
+                    .to_std()
+                    .unwrap_or(RETRY_DELAY)
+                    .min(MAX_SLEEP)
+            }
+            Ok(None) => MAX_SLEEP,
+            Err(e) => {
+                error!("Failed to list the trash: {}", e);
+                MAX_SLEEP
+            }
+        };
+
+        if !wait_for_change(&rx, timeout) {
+            return Ok(());
+        }
+    }
+}
+
+/// Waits until `timeout` elapses or the trash changes, returning `false` once the watcher
+/// is gone. Access events are ignored, since listing the trash produces them too.
+fn wait_for_change(rx: &Receiver<notify::Result<Event>>, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(remaining) {
+            Ok(Ok(event)) if event.kind.is_access() => continue,
+            Ok(event) => {
+                if let Err(e) = event {
+                    error!("Failed to watch the trash: {}", e);
+                }
+                // Coalesce the rest of the burst into a single wake up
+                std::thread::sleep(SETTLE_DELAY);
+                while rx.try_recv().is_ok() {}
+                return true;
+            }
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use clap::Args;
+use rrm::{
+    trash::{escape_os_str, TrashBackend},
+    Result,
+};
+use similar::TextDiff;
+use std::{fs, io, path::PathBuf};
+
+#[derive(Args)]
+pub struct DiffArgs {
+    #[clap(help = "The ID of the trashed file to compare.", required = true)]
+    pub id: String,
+    #[clap(
+        help = "A path or the ID of another trashed file to compare against. Defaults to the file currently at the original path, or to an empty file if nothing is there anymore."
+    )]
+    pub other: Option<PathBuf>,
+}
+
+pub fn handle_diff(backend: impl TrashBackend, args: DiffArgs) -> Result<()> {
+    let item = backend.get(&args.id)?;
+    let old = backend.read(&item)?;
+    let old_label = format!("trash:{} ({})", item.id(), item.format_original_path());
+
+    let (new, new_label) = match args.other {
+        // Paths on disk win over IDs, the trash is only searched when nothing exists there,
+        // not even a dangling symlink
+        Some(other) if other.symlink_metadata().is_err() => {
+            let other_id = other.to_string_lossy();
+            let other_item = backend.get(&other_id)?;
+            let label = format!(
+                "trash:{} ({})",
+                other_item.id(),
+                other_item.format_original_path()
+            );
+            (backend.read(&other_item)?, label)
+        }
+        Some(other) => (fs::read(&other)?, escape_os_str(other.as_os_str())),
+        // Like `diff -N`, a file that is gone compares as empty instead of failing
+        None => match fs::read(item.original_path()) {
+            Ok(content) => (content, item.format_original_path()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (Vec::new(), "/dev/null".to_string()),
+            Err(e) => return Err(e.into()),
+        },
+    };
+
+    if old == new {
+        return Ok(());
+    }
+
+    if old.contains(&0) || new.contains(&0) {
+        println!("Binary files {} and {} differ", old_label, new_label);
+        return Ok(());
+    }
+
+    let old = String::from_utf8_lossy(&old);
+    let new = String::from_utf8_lossy(&new);
+    let diff = TextDiff::from_lines(old.as_ref(), new.as_ref());
+    print!("{}", diff.unified_diff().header(&old_label, &new_label));
+    Ok(())
+}
@@ -0,0 +1,23 @@
+use clap::Args;
+use rrm::{
+    clock::Clock,
+    trash::{MetadataUpdate, TrashBackend},
+    Result,
+};
+
+#[derive(Args)]
+pub struct ExpireArgs {
+    #[clap(help = "The ID of the file or directory to expire.", required = true)]
+    pub id: String,
+}
+
+pub fn handle_expire(
+    backend: impl TrashBackend,
+    clock: &dyn Clock,
+    args: ExpireArgs,
+) -> Result<()> {
+    // Unpinned too, so the next clean picks the item up
+    backend.update(&args.id, MetadataUpdate::Reschedule(clock.now()))?;
+    println!("Item {} will be deleted on the next clean", args.id);
+    Ok(())
+}
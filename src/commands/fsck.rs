@@ -0,0 +1,108 @@
+use clap::{Args, ValueEnum};
+use comfy_table::{modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL, Table};
+use rrm::{
+    clock::Clock,
+    config::Config,
+    duration::date_after,
+    trash::{Repair, TrashBackend},
+    xattr::ExtendedAttributes,
+    Error, ItemFailure, Result,
+};
+use std::{env, path::PathBuf};
+
+#[derive(Args)]
+pub struct FsckArgs {
+    #[clap(long, value_enum, help = "Repair the issues found")]
+    pub repair: Option<RepairMode>,
+    #[clap(
+        long,
+        value_name = "DIR",
+        help = "Directory adopted items are restored to when their original path is unknown. Defaults to the current directory"
+    )]
+    pub adopt_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum RepairMode {
+    #[clap(help = "Fill in the missing metadata, orphans expire after the grace period")]
+    Adopt,
+    #[clap(help = "Move the entries into the quarantine directory of the trash")]
+    Quarantine,
+    #[clap(help = "Permanently delete the entries")]
+    Delete,
+}
+
+pub fn handle_fsck<T: ExtendedAttributes>(
+    config: Config<T>,
+    backend: impl TrashBackend,
+    clock: &dyn Clock,
+    args: FsckArgs,
+) -> Result<()> {
+    let issues = backend.check()?;
+    if issues.is_empty() {
+        println!("No issues found in the trash.");
+        return Ok(());
+    }
+
+    let repair = match args.repair {
+        Some(RepairMode::Adopt) => {
+            let original_dir = match args.adopt_dir {
+                Some(dir) => dir,
+                None => env::current_dir()?,
+            };
+            Some(Repair::Adopt {
+                original_dir,
+                deletion_date: date_after(clock.now(), config.grace_period)?,
+            })
+        }
+        Some(RepairMode::Quarantine) => Some(Repair::Quarantine),
+        Some(RepairMode::Delete) => Some(Repair::Delete),
+        None => None,
+    };
+
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS);
+    match repair {
+        Some(_) => table.set_header(vec!["ID", "Problem", "Repair"]),
+        None => table.set_header(vec!["ID", "Problem"]),
+    };
+
+    let mut unrepaired = 0;
+    let mut failures = Vec::new();
+    for issue in &issues {
+        let mut row = vec![issue.id.clone(), issue.kind.to_string()];
+        if let Some(repair) = &repair {
+            row.push(match backend.repair(issue, repair) {
+                Ok(true) => "Repaired".to_string(),
+                Ok(false) => {
+                    unrepaired += 1;
+                    "Skipped, use quarantine or delete".to_string()
+                }
+                Err(error) => {
+                    let row = format!("Failed: {}", error);
+                    failures.push(ItemFailure {
+                        item: issue.id.clone(),
+                        error,
+                    });
+                    row
+                }
+            });
+        } else {
+            unrepaired += 1;
+        }
+        table.add_row(row);
+    }
+
+    println!("{}", table);
+    if repair.is_none() {
+        println!("Run with --repair <adopt|quarantine|delete> to fix them.");
+    }
+    // Failed repairs take precedence, the issues they leave are listed with them
+    Error::from_failures(failures, issues.len())?;
+    match unrepaired {
+        0 => Ok(()),
+        unrepaired => Err(Error::IssuesFound(unrepaired)),
+    }
+}
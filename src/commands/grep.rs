@@ -0,0 +1,72 @@
+use clap::Args;
+use log::warn;
+use regex::bytes::{Regex, RegexBuilder};
+use rrm::{
+    trash::{escape_os_str, TrashBackend},
+    Result,
+};
+
+#[derive(Args)]
+pub struct GrepArgs {
+    #[clap(help = "The regular expression to search for.", required = true)]
+    pub pattern: String,
+    #[clap(short, long, help = "Ignore case distinctions in the pattern")]
+    pub ignore_case: bool,
+    #[clap(
+        short = 'l',
+        long,
+        help = "Only print the items and files that contain a match"
+    )]
+    pub files_with_matches: bool,
+}
+
+pub fn handle_grep(backend: impl TrashBackend, args: GrepArgs) -> Result<()> {
+    let pattern = RegexBuilder::new(&args.pattern)
+        .case_insensitive(args.ignore_case)
+        .build()
+        .map_err(|e| rrm::Error::InvalidPattern(e.to_string()))?;
+
+    let mut items = backend.list()?;
+    items.sort_by_key(|item| item.deletion_date());
+
+    for item in items {
+        backend.for_each_file(&item, &mut |original_path, content| {
+            let prefix = format!("{}:{}", item.id(), escape_os_str(original_path.as_os_str()));
+            match content {
+                Ok(content) => grep_file(&prefix, &content, &pattern, args.files_with_matches),
+                Err(e) => warn!("Failed to read '{}': {}", prefix, e),
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Prints the lines of `content` matching `pattern`, or only `prefix` if
+/// `files_with_matches` is set.
+fn grep_file(prefix: &str, content: &[u8], pattern: &Regex, files_with_matches: bool) {
+    if content.contains(&0) {
+        match pattern.is_match(content) {
+            true if files_with_matches => println!("{}", prefix),
+            true => println!("{}: binary file matches", prefix),
+            false => {}
+        }
+        return;
+    }
+
+    for (number, line) in content.split(|&b| b == b'\n').enumerate() {
+        if !pattern.is_match(line) {
+            continue;
+        }
+        if files_with_matches {
+            println!("{}", prefix);
+            break;
+        }
+        println!(
+            "{}:{}:{}",
+            prefix,
+            number + 1,
+            String::from_utf8_lossy(line)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use clap::Args;
+use rrm::{
+    duration::{format_duration, parse_duration},
+    Error, Result,
+};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Name shared by the systemd units and used to tag the crontab entry.
+const UNIT_NAME: &str = "rrm-clean";
+
+#[derive(Args)]
+pub struct InstallTimerArgs {
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "1h",
+        help = "How often to clean the trash, e.g. 30m, 1h or 1d"
+    )]
+    pub interval: chrono::Duration,
+    #[clap(
+        long,
+        help = "Install a crontab entry instead of systemd user units",
+        default_value = "false"
+    )]
+    pub cron: bool,
+}
+
+pub fn handle_install_timer(args: InstallTimerArgs) -> Result<()> {
+    if args.interval.num_minutes() < 1 {
+        return Err(Error::InvalidDuration(
+            "The interval must be at least one minute".to_string(),
+        ));
+    }
+
+    let bin = env::current_exe()?;
+    if args.cron {
+        install_crontab(&bin, args.interval)
+    } else {
+        install_systemd(&bin, args.interval)
+    }
+}
+
+/// Writes a oneshot service running `rrm clean` and a timer triggering it periodically.
+fn install_systemd(bin: &Path, interval: chrono::Duration) -> Result<()> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .ok_or_else(|| io::Error::other("Neither XDG_CONFIG_HOME nor HOME is set"))?;
+    let unit_dir = config_home.join("systemd").join("user");
+    fs::create_dir_all(&unit_dir)?;
+
+    let service = format!(
+        "[Unit]\n\
+         Description=Clean expired items from the rrm trash\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         ExecStart=\"{}\" clean\n",
+        bin.display()
+    );
+    let timer = format!(
+        "[Unit]\n\
+         Description=Periodically clean expired items from the rrm trash\n\
+         \n\
+         [Timer]\n\
+         OnBootSec=5min\n\
+         OnUnitActiveSec={}s\n\
+         Persistent=true\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        interval.num_seconds()
+    );
+
+    let service_path = unit_dir.join(format!("{}.service", UNIT_NAME));
+    let timer_path = unit_dir.join(format!("{}.timer", UNIT_NAME));
+    fs::write(&service_path, service)?;
+    fs::write(&timer_path, timer)?;
+
+    println!("Wrote {}", service_path.display());
+    println!("Wrote {}", timer_path.display());
+    println!(
+        "Enable it with: systemctl --user daemon-reload && systemctl --user enable --now {}.timer",
+        UNIT_NAME
+    );
+    Ok(())
+}
+
+/// Adds (or replaces) an entry running `rrm clean` in the user's crontab.
+fn install_crontab(bin: &Path, interval: chrono::Duration) -> Result<()> {
+    let schedule = cron_schedule(interval).ok_or_else(|| {
+        Error::InvalidDuration(format!(
+            "An interval of {} cannot be expressed in cron, use minutes or hours that divide an hour or a day evenly, 1d or 1w, or the systemd timer",
+            format_duration(interval)
+        ))
+    })?;
+    let tag = format!("# {}", UNIT_NAME);
+    let entry = format!("{} \"{}\" clean {}", schedule, bin.display(), tag);
+
+    // A missing crontab makes `crontab -l` fail, which is the same as an empty one
+    let current = Command::new("crontab").arg("-l").output()?;
+    let current = if current.status.success() {
+        String::from_utf8_lossy(&current.stdout).to_string()
+    } else {
+        String::new()
+    };
+
+    let mut lines: Vec<&str> = current
+        .lines()
+        .filter(|line| !line.ends_with(&tag))
+        .collect();
+    lines.push(&entry);
+
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(format!("{}\n", lines.join("\n")).as_bytes())?;
+    }
+    if !child.wait()?.success() {
+        return Err(io::Error::other("Failed to update the crontab").into());
+    }
+
+    println!("Installed crontab entry: {}", entry);
+    Ok(())
+}
+
+/// Converts an interval into a cron schedule, if cron can express it. Steps restart with
+/// every hour, day or month (e.g. `0 0 */3 * *` runs on the 31st and again on the 1st),
+/// so only steps dividing the next larger unit are regular intervals. Days do not divide
+/// months, so a day and a week are the only intervals in days. The systemd timer, using
+/// `OnUnitActiveSec`, has no such limits.
+fn cron_schedule(interval: chrono::Duration) -> Option<String> {
+    let minutes = interval.num_minutes();
+    if interval.num_seconds() % 60 != 0 {
+        return None;
+    }
+
+    match minutes {
+        m if m < 60 && 60 % m == 0 => Some(format!("*/{} * * * *", m)),
+        m if m % 60 == 0 && m < 24 * 60 && (24 * 60) % m == 0 => {
+            Some(format!("0 */{} * * *", m / 60))
+        }
+        m if m == 24 * 60 => Some("0 0 * * *".to_string()),
+        m if m == 7 * 24 * 60 => Some("0 0 * * 0".to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intervals_become_regular_cron_schedules() {
+        let cases = [
+            ("1m", Some("*/1 * * * *")),
+            ("15m", Some("*/15 * * * *")),
+            ("45m", None),
+            ("90s", None),
+            ("1h", Some("0 */1 * * *")),
+            ("6h", Some("0 */6 * * *")),
+            ("5h", None),
+            ("36h", None),
+            ("1d", Some("0 0 * * *")),
+            ("3d", None),
+            ("1w", Some("0 0 * * 0")),
+            ("2w", None),
+        ];
+        for (interval, schedule) in cases {
+            let interval = parse_duration(interval).unwrap();
+            assert_eq!(
+                cron_schedule(interval).as_deref(),
+                schedule,
+                "{}",
+                format_duration(interval)
+            );
+        }
+    }
+}
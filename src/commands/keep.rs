@@ -0,0 +1,50 @@
+use chrono::Local;
+use clap::Args;
+use rrm::{
+    clock::Clock,
+    duration::{date_after, parse_duration},
+    trash::{MetadataUpdate, TrashBackend},
+    Result,
+};
+
+#[derive(Args)]
+pub struct KeepArgs {
+    #[clap(help = "The ID of the file or directory to keep.", required = true)]
+    pub id: String,
+    #[clap(
+        long = "for",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Keep the item for this long from now, e.g. 12h, 30d or 1mo",
+        required_unless_present = "forever"
+    )]
+    pub duration: Option<chrono::Duration>,
+    #[clap(
+        long,
+        help = "Pin the item so it is never cleaned, unless the clean is forced",
+        conflicts_with = "duration",
+        default_value = "false"
+    )]
+    pub forever: bool,
+}
+
+pub fn handle_keep(backend: impl TrashBackend, clock: &dyn Clock, args: KeepArgs) -> Result<()> {
+    if args.forever {
+        backend.update(&args.id, MetadataUpdate::Pinned(true))?;
+        println!("Pinned item {}", args.id);
+        return Ok(());
+    }
+
+    let duration = args.duration.unwrap_or_default();
+    let deletion_date = date_after(clock.now(), duration)?;
+
+    backend.update(&args.id, MetadataUpdate::Reschedule(deletion_date))?;
+    println!(
+        "Item {} will be kept until {}",
+        args.id,
+        deletion_date
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+    );
+    Ok(())
+}
@@ -1,30 +1,31 @@
-use crate::{
-    trash::{TrashItem, TrashManager},
-    xattr::ExtendedAttributes,
-    Result,
-};
 use clap::Args;
 use comfy_table::{modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL, Table};
+use rrm::{
+    clock::Clock,
+    trash::{escape_os_str, TrashBackend, TrashItem},
+    Result,
+};
+use std::{ffi::OsString, os::unix::ffi::OsStrExt};
 
 #[derive(Args)]
 pub struct ListArgs {
     /// Filter by original path substring.
     #[clap(short, long)]
-    pub filter_path: Option<String>,
+    pub filter_path: Option<OsString>,
 }
 
-pub fn handle_list<T: ExtendedAttributes>(
-    trash_manager: TrashManager<T>,
-    args: ListArgs,
-) -> Result<()> {
+pub fn handle_list(backend: impl TrashBackend, clock: &dyn Clock, args: ListArgs) -> Result<()> {
     // Get all entries in the trash and filter them
-    let mut items: Vec<TrashItem> = trash_manager
-        .list_items()?
+    let mut items: Vec<TrashItem> = backend
+        .list()?
         .into_iter()
         .filter(|entry| {
             // TODO: Implement date filtering
             if let Some(path) = args.filter_path.as_ref() {
-                if !entry.original_path.contains(path) {
+                if !contains_bytes(
+                    entry.original_path().as_os_str().as_bytes(),
+                    path.as_bytes(),
+                ) {
                     return false;
                 }
             }
@@ -32,7 +33,8 @@ pub fn handle_list<T: ExtendedAttributes>(
         })
         .collect();
 
-    match (args.filter_path.as_ref(), items.is_empty()) {
+    let filter_path = args.filter_path.as_deref().map(escape_os_str);
+    match (filter_path, items.is_empty()) {
         (Some(filter_path), false) => {
             println!(
                 "Items in the trash matching the path filter: '{}'",
@@ -53,22 +55,32 @@ pub fn handle_list<T: ExtendedAttributes>(
     }
 
     // Sort by deletion date
-    items.sort_by_key(|entry| entry.deletion_date);
+    items.sort_by_key(|entry| entry.deletion_date());
 
     // Print the items in a table
     let mut table = Table::new();
     table
-        .set_header(vec!["Original Path", "ID", "Kind", "Deletion Date"])
+        .set_header(vec![
+            "Original Path",
+            "ID",
+            "Kind",
+            "Size",
+            "Stored",
+            "Deletion Date",
+        ])
         .load_preset(UTF8_FULL)
         .apply_modifier(UTF8_ROUND_CORNERS);
 
+    let now = clock.now();
     for item in items {
-        let deletion_date_display = item.format_deletion_date();
+        let deletion_date_display = item.format_deletion_date(now);
         let kind = item.kind().to_string();
         table.add_row(vec![
-            item.original_path,
-            item.id,
+            item.format_original_path(),
+            item.id().to_string(),
             kind,
+            item.format_size(),
+            item.format_stored_size(),
             deletion_date_display,
         ]);
     }
@@ -76,3 +88,12 @@ pub fn handle_list<T: ExtendedAttributes>(
     println!("{}", table);
     Ok(())
 }
+
+/// Checks whether `needle` appears in `haystack`, comparing raw bytes so filters work on
+/// paths that are not valid UTF-8.
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty()
+        || haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+}
@@ -0,0 +1,154 @@
+use chrono::{DateTime, Duration, Local, Utc};
+use clap::Args;
+use comfy_table::{modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL, Table};
+use rrm::{
+    audit::{Action, AuditLog, Entry},
+    clock::Clock,
+    duration::parse_duration,
+    trash::{escape_os_str, format_size},
+    Error, Result,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+#[derive(Args)]
+pub struct LogArgs {
+    #[clap(
+        long,
+        help = "Only show entries about this path, paths inside it or the items trashed from there"
+    )]
+    pub path: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "WHEN",
+        value_parser = parse_since,
+        help = "Only show entries from this long ago on, e.g. 7d, or since a date like 2024-01-31T12:00:00Z"
+    )]
+    pub since: Option<Since>,
+    #[clap(long, help = "Print the entries as JSON lines, as they are stored")]
+    pub json: bool,
+}
+
+/// Start of the entries to show.
+#[derive(Clone)]
+pub enum Since {
+    Ago(Duration),
+    Date(DateTime<Utc>),
+}
+
+fn parse_since(value: &str) -> std::result::Result<Since, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Since::Date(date.with_timezone(&Utc)));
+    }
+    parse_duration(value).map(Since::Ago).map_err(|_| {
+        format!(
+            "'{}' is neither a duration like 7d nor a date like 2024-01-31T12:00:00Z",
+            value
+        )
+    })
+}
+
+/// Prints the audit log entries matching the filters, oldest first.
+pub fn handle_log(audit_log: &AuditLog, clock: &dyn Clock, args: LogArgs) -> Result<()> {
+    let since = args.since.map(|since| match since {
+        Since::Ago(duration) => clock.now() - duration,
+        Since::Date(date) => date,
+    });
+    // Paths are logged absolute and escaped, compare them the same way
+    let path = match &args.path {
+        Some(path) => Some(escape_os_str(std::path::absolute(path)?.as_os_str())),
+        None => None,
+    };
+
+    let entries = audit_log.entries()?;
+    let matches_path = |entry: &Entry| match (&path, &entry.event.path) {
+        (Some(path), Some(entry_path)) => Path::new(entry_path).starts_with(path),
+        _ => false,
+    };
+    // Entries without a path, like deletion date changes, follow the items they are about
+    let ids: HashSet<String> = entries
+        .iter()
+        .filter(|entry| matches_path(entry))
+        .filter_map(|entry| entry.event.id.clone())
+        .collect();
+    let entries: Vec<Entry> = entries
+        .into_iter()
+        .filter(|entry| since.is_none_or(|since| entry.time >= since))
+        .filter(|entry| {
+            path.is_none()
+                || matches_path(entry)
+                || entry.event.id.as_ref().is_some_and(|id| ids.contains(id))
+        })
+        .collect();
+
+    if args.json {
+        for entry in &entries {
+            println!(
+                "{}",
+                serde_json::to_string(entry).map_err(|e| Error::Io(e.into()))?
+            );
+        }
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("No audit log entries found.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table
+        .set_header(vec!["Time", "Action", "User", "Path", "ID", "Details"])
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS);
+    for entry in entries {
+        let details = details(&entry);
+        let event = entry.event;
+        table.add_row(vec![
+            format_date(entry.time),
+            event.action.to_string(),
+            entry.user,
+            event.path.unwrap_or_default(),
+            event.id.unwrap_or_default(),
+            details,
+        ]);
+    }
+    println!("{}", table);
+    Ok(())
+}
+
+/// Describes what an entry changed, besides the item and its path.
+fn details(entry: &Entry) -> String {
+    let event = &entry.event;
+    let size = event.size.map(format_size);
+    match event.action {
+        Action::Trash => match event.deletion_date {
+            Some(date) => format!(
+                "{}, until {}",
+                size.unwrap_or_else(|| "-".to_string()),
+                format_date(date)
+            ),
+            None => size.unwrap_or_default(),
+        },
+        Action::Update => match (event.deletion_date, event.pinned) {
+            (Some(date), Some(false)) => format!("until {}, unpinned", format_date(date)),
+            (Some(date), _) => format!("until {}", format_date(date)),
+            (None, Some(true)) => "pinned".to_string(),
+            (None, Some(false)) => "unpinned".to_string(),
+            (None, None) => String::new(),
+        },
+        Action::Config => format!(
+            "{} = {}",
+            event.key.as_deref().unwrap_or_default(),
+            event.value.as_deref().unwrap_or_default()
+        ),
+        _ => size.unwrap_or_default(),
+    }
+}
+
+fn format_date(date: DateTime<Utc>) -> String {
+    date.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
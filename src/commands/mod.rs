@@ -1,5 +1,17 @@
+pub mod browse;
+pub mod cat;
 pub mod clean;
 pub mod config;
+pub mod daemon;
+pub mod diff;
+pub mod expire;
+pub mod fsck;
+pub mod grep;
+pub mod install_timer;
+pub mod keep;
 pub mod list;
+pub mod log;
 pub mod restore;
 pub mod rm;
+pub mod stats;
+pub mod verify;
@@ -1,5 +1,6 @@
-use crate::{trash::TrashManager, xattr::ExtendedAttributes, Result};
 use clap::Args;
+use rrm::{trash::TrashBackend, Result};
+use std::ffi::OsString;
 
 #[derive(Args)]
 pub struct RestoreArgs {
@@ -10,12 +11,20 @@ pub struct RestoreArgs {
         long,
         help = "Rename the item to the specified name after restoring it."
     )]
-    pub rename: Option<String>,
+    pub rename: Option<OsString>,
+    #[clap(
+        long,
+        help = "Restore the item even if it does not match the checksums recorded when it was trashed"
+    )]
+    pub no_verify: bool,
 }
 
-pub fn handle_restore<T: ExtendedAttributes>(
-    trash_manager: TrashManager<T>,
-    args: RestoreArgs,
-) -> Result<()> {
-    trash_manager.restore_item_by_id(&args.id, args.rename)
+/// `--no-verify` is applied to the backend before it is handed over, see `configure` in
+/// main.
+pub fn handle_restore(backend: impl TrashBackend, args: RestoreArgs) -> Result<()> {
+    let destination = match args.rename {
+        Some(name) => Some(backend.get(&args.id)?.original_path().with_file_name(name)),
+        None => None,
+    };
+    backend.restore(&args.id, destination.as_deref())
 }
@@ -1,8 +1,15 @@
-use crate::{config::Config, trash::TrashManager, xattr::ExtendedAttributes, Result};
 use clap::Args;
 use log::info;
+use rrm::{
+    clock::Clock,
+    config::Config,
+    duration::{date_after, parse_duration},
+    trash::{escape_os_str, path_exists, TrashBackend},
+    xattr::ExtendedAttributes,
+    Error, ItemFailure, Result,
+};
 use std::{
-    fs,
+    io,
     path::{Path, PathBuf},
 };
 
@@ -10,7 +17,7 @@ use std::{
 pub struct RmArgs {
     /// Files or directories to remove.
     #[clap(required = true)]
-    pub paths: Vec<String>,
+    pub paths: Vec<PathBuf>,
     #[clap(
         short,
         long,
@@ -25,72 +32,85 @@ pub struct RmArgs {
         default_value = "false"
     )]
     pub auto_clean: bool,
-    /// The number of days to wait before deleting the item permanently.
+    /// How long to wait before deleting the item permanently.
+    #[clap(
+        short,
+        long,
+        alias = "grace-period-in-days",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "How long to wait before deleting the files or directories permanently, e.g. 12h, 7d or 2w (a bare number is days)"
+    )]
+    pub grace_period: Option<chrono::Duration>,
+    #[clap(
+        short,
+        long,
+        help = "Overwrite the contents of the files before unlinking them, immediately with --immediate or else when they are cleaned from the trash",
+        default_value = "false"
+    )]
+    pub shred: bool,
     #[clap(
         short,
         long,
-        help = "The number of days to wait before deleting the files or directories permanently"
+        help = "Encrypt the files while they are in the trash, with the configured key or RRM_PASSPHRASE",
+        default_value = "false",
+        conflicts_with = "immediate"
     )]
-    pub grace_period_in_days: Option<u32>,
+    pub encrypt: bool,
 }
 
+/// `--shred` and `--encrypt` are applied to the backend before it is handed over, see
+/// `configure` in main.
 pub fn handle_rm<T: ExtendedAttributes>(
     config: Config<T>,
-    trash_manager: TrashManager<T>,
+    backend: impl TrashBackend,
+    clock: &dyn Clock,
     args: RmArgs,
 ) -> Result<()> {
     if args.immediate {
-        return delete_paths(args.paths);
+        return backend.delete_paths(args.paths);
     }
 
-    let now = chrono::Utc::now();
-    let grace_period_in_days = args
-        .grace_period_in_days
-        .map(|d| d as i64)
-        .unwrap_or(config.grace_period_in_days as i64);
+    let grace_period = args.grace_period.unwrap_or(config.grace_period);
+    let deletion_date = date_after(clock.now(), grace_period)?;
 
-    let deletion_date = now
-        .checked_add_signed(chrono::Duration::days(grace_period_in_days))
-        .expect("Failed to add grace period to current time");
+    // Missing paths are reported along with the items that failed to be trashed
+    let total = args.paths.len();
+    let mut paths = Vec::new();
+    let mut failures = Vec::new();
+    for path in args.paths {
+        if path_exists(&path) {
+            paths.push(path);
+        } else {
+            failures.push(not_found(&path));
+        }
+    }
 
-    let paths: Vec<PathBuf> = args
-        .paths
+    let batch = paths
         .iter()
-        .filter_map(|p| {
-            let path = PathBuf::from(p);
-            if path.exists() {
-                Some(path)
-            } else {
-                eprintln!("{}: No such file or directory", path.display());
-                None
-            }
-        })
-        .collect();
-
-    trash_manager.trash_items(paths, deletion_date)?;
+        .map(|path| escape_os_str(path.as_os_str()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    match backend.put_all(paths, deletion_date) {
+        Ok(()) => {}
+        Err(Error::ItemsFailed { failures: more, .. }) => failures.extend(more),
+        Err(e) if failures.is_empty() => return Err(e),
+        // Nothing was trashed, e.g. the trash could not be locked
+        Err(error) => failures.push(ItemFailure { item: batch, error }),
+    }
     if args.auto_clean {
         info!(
             "Automatically cleaning trash..items that have passed the grace period will be deleted"
         );
-        trash_manager.clean_trash(false)?;
+        backend.clean(false, false)?;
     }
 
-    Ok(())
+    Error::from_failures(failures, total)
 }
 
-fn delete_paths(paths: Vec<String>) -> Result<()> {
-    for path in paths {
-        let path = Path::new(&path);
-        if !path.exists() {
-            eprintln!("{}: No such file or directory", path.display());
-            continue;
-        }
-
-        if path.is_dir() {
-            fs::remove_dir_all(path)?;
-        } else {
-            fs::remove_file(path)?;
-        }
+fn not_found(path: &Path) -> ItemFailure {
+    ItemFailure {
+        item: escape_os_str(path.as_os_str()),
+        error: io::Error::from_raw_os_error(libc::ENOENT).into(),
     }
-    Ok(())
 }
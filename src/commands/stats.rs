@@ -0,0 +1,345 @@
+use chrono::{DateTime, Duration, Utc};
+use clap::Args;
+use comfy_table::{modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL, Table};
+use rrm::{
+    clock::Clock,
+    trash::{format_size, user_name, TrashBackend, TrashItem},
+    Result,
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    env,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::Path,
+};
+
+/// Age buckets, by how long items have been in the trash.
+const AGE_BUCKETS: [(&str, i64); 4] = [
+    ("Less than a day", 1),
+    ("1 to 7 days", 7),
+    ("1 to 4 weeks", 28),
+    ("More than 4 weeks", i64::MAX),
+];
+
+#[derive(Args)]
+pub struct StatsArgs {
+    #[clap(long, help = "Print the statistics as JSON")]
+    pub json: bool,
+    #[clap(
+        long,
+        value_name = "N",
+        default_value = "10",
+        help = "Number of largest items to show"
+    )]
+    pub top: usize,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    items: usize,
+    size: u64,
+    stored_size: u64,
+    dedup_savings: u64,
+    by_directory: Vec<Group>,
+    by_extension: Vec<Group>,
+    by_user: Vec<Group>,
+    by_age: Vec<Group>,
+    expiring_within_a_day: Group,
+    expiring_within_a_week: Group,
+    largest: Vec<LargeItem>,
+}
+
+/// Items sharing a directory, extension, owner or age.
+#[derive(Serialize)]
+struct Group {
+    name: String,
+    items: usize,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct LargeItem {
+    id: String,
+    original_path: String,
+    size: u64,
+}
+
+/// Prints how much the trash holds, broken down by where items came from, their type,
+/// owner and age, along with what expires soon and the largest items.
+pub fn handle_stats(backend: impl TrashBackend, clock: &dyn Clock, args: StatsArgs) -> Result<()> {
+    let items = backend.list()?;
+    let dedup = backend.dedup_stats()?;
+    let mut stats = compute_stats(&items, clock.now(), args.top);
+    // Deduplicated contents are shared, so they only count once for the whole trash
+    stats.stored_size += dedup.stored_size;
+    stats.dedup_savings = dedup.savings();
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&stats).map_err(std::io::Error::from)?
+        );
+        return Ok(());
+    }
+
+    println!("Items: {}", stats.items);
+    println!("Total size: {}", format_size(stats.size));
+    println!("Stored size: {}", format_size(stats.stored_size));
+    if dedup.items > 0 {
+        println!(
+            "Deduplicated: {} item(s), {} of file contents stored as {} in {} blob(s), saving {}",
+            dedup.items,
+            format_size(dedup.logical_size),
+            format_size(dedup.stored_size),
+            dedup.blobs,
+            format_size(dedup.savings())
+        );
+    }
+    if stats.items == 0 {
+        return Ok(());
+    }
+
+    println!(
+        "Expiring within a day: {} item(s), {}",
+        stats.expiring_within_a_day.items,
+        format_size(stats.expiring_within_a_day.size)
+    );
+    println!(
+        "Expiring within a week: {} item(s), {}",
+        stats.expiring_within_a_week.items,
+        format_size(stats.expiring_within_a_week.size)
+    );
+    print_groups("Directory", &stats.by_directory);
+    print_groups("Extension", &stats.by_extension);
+    print_groups("User", &stats.by_user);
+    print_groups("Age", &stats.by_age);
+
+    let mut table = new_table(vec!["Largest Items", "ID", "Size"]);
+    for item in &stats.largest {
+        table.add_row(vec![
+            item.original_path.clone(),
+            item.id.clone(),
+            format_size(item.size),
+        ]);
+    }
+    println!("{}", table);
+    Ok(())
+}
+
+fn compute_stats(items: &[TrashItem], now: DateTime<Utc>, top: usize) -> Stats {
+    let home = env::var_os("HOME");
+    let home = home.as_deref().map(Path::new);
+    let size = |item: &TrashItem| item.size().unwrap_or_else(|| item.stored_size());
+    let group_by = |key: &dyn Fn(&TrashItem) -> String| {
+        let mut groups: HashMap<String, Group> = HashMap::new();
+        for item in items {
+            let name = key(item);
+            let group = groups.entry(name.clone()).or_insert(Group {
+                name,
+                items: 0,
+                size: 0,
+            });
+            group.items += 1;
+            group.size += size(item);
+        }
+        let mut groups: Vec<Group> = groups.into_values().collect();
+        groups.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        groups
+    };
+    let expiring = |name: &str, within: Duration| {
+        let expiring: Vec<_> = items
+            .iter()
+            .filter(|item| {
+                // Items past their deletion date are waiting for a clean, not expiring
+                let deletion_date = item.deletion_date();
+                !item.pinned() && deletion_date > now && deletion_date <= now + within
+            })
+            .collect();
+        Group {
+            name: name.to_string(),
+            items: expiring.len(),
+            size: expiring.iter().map(|item| size(item)).sum(),
+        }
+    };
+
+    let mut by_age = group_by(&|item| age_bucket(item, now).to_string());
+    by_age.sort_by_key(|group| {
+        AGE_BUCKETS
+            .iter()
+            .position(|(name, _)| *name == group.name)
+            .unwrap_or(AGE_BUCKETS.len())
+    });
+
+    let mut largest: Vec<_> = items.iter().collect();
+    largest.sort_by_key(|item| std::cmp::Reverse(size(item)));
+
+    Stats {
+        items: items.len(),
+        size: items.iter().map(size).sum(),
+        stored_size: items.iter().map(|item| item.stored_size()).sum(),
+        dedup_savings: 0,
+        by_directory: group_by(&|item| top_level_dir(item.original_path(), home)),
+        by_extension: group_by(&extension),
+        by_user: group_by(&owner),
+        by_age,
+        expiring_within_a_day: expiring("day", Duration::days(1)),
+        expiring_within_a_week: expiring("week", Duration::weeks(1)),
+        largest: largest
+            .into_iter()
+            .take(top)
+            .map(|item| LargeItem {
+                id: item.id().to_string(),
+                original_path: item.format_original_path(),
+                size: size(item),
+            })
+            .collect(),
+    }
+}
+
+fn print_groups(title: &str, groups: &[Group]) {
+    let mut table = new_table(vec![title, "Items", "Size"]);
+    for group in groups {
+        table.add_row(vec![
+            group.name.clone(),
+            group.items.to_string(),
+            format_size(group.size),
+        ]);
+    }
+    println!("{}", table);
+}
+
+fn new_table(header: Vec<&str>) -> Table {
+    let mut table = Table::new();
+    table
+        .set_header(header)
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS);
+    table
+}
+
+/// Returns the directory an item was trashed from, at the top level: the first directory
+/// inside the home directory (e.g. `~/projects`), or else the first directory from the root
+/// (e.g. `/tmp`).
+fn top_level_dir(original_path: &Path, home: Option<&Path>) -> String {
+    let (root, prefix, relative) = match home.and_then(|home| original_path.strip_prefix(home).ok())
+    {
+        Some(relative) => ("~", "~/", relative),
+        None => (
+            "/",
+            "/",
+            original_path.strip_prefix("/").unwrap_or(original_path),
+        ),
+    };
+    let mut components = relative.components();
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => format!(
+            "{}{}",
+            prefix,
+            String::from_utf8_lossy(first.as_os_str().as_bytes())
+        ),
+        // The item itself is at the top level
+        _ => root.to_string(),
+    }
+}
+
+fn extension(item: &TrashItem) -> String {
+    match item.kind() {
+        "Directory" => "(directory)".to_string(),
+        "Symlink" => "(symlink)".to_string(),
+        _ => match item.original_path().extension() {
+            Some(extension) => format!(".{}", extension.to_string_lossy().to_lowercase()),
+            None => "(none)".to_string(),
+        },
+    }
+}
+
+fn age_bucket(item: &TrashItem, now: DateTime<Utc>) -> &'static str {
+    let Some(trash_date) = item.trash_date() else {
+        return "Unknown";
+    };
+    let age = now - trash_date;
+    AGE_BUCKETS
+        .iter()
+        .find(|(_, days)| *days == i64::MAX || age < Duration::days(*days))
+        .map(|(name, _)| *name)
+        .unwrap_or("Unknown")
+}
+
+/// Returns the name of the user owning a trashed item, or its uid if it has no name.
+fn owner(item: &TrashItem) -> String {
+    let Ok(meta) = item.path().symlink_metadata() else {
+        return "Unknown".to_string();
+    };
+    user_name(meta.uid()).unwrap_or_else(|| meta.uid().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rrm::{
+        clock::FakeClock, filesystem::MemoryFileSystem, trash::MetadataUpdate, TrashManager,
+    };
+    use std::{path::PathBuf, sync::Arc};
+
+    #[test]
+    fn stats_group_items_by_age_and_expiry() -> Result<()> {
+        let now = Utc::now();
+        // Name, time in the trash, time left until deletion and whether it is pinned
+        let cases = [
+            ("fresh.txt", Duration::hours(2), Duration::hours(6), false),
+            ("pinned.txt", Duration::hours(3), Duration::hours(6), true),
+            ("days.txt", Duration::days(3), Duration::days(4), false),
+            ("weeks.txt", Duration::days(10), Duration::days(20), false),
+            ("months.txt", Duration::days(60), Duration::days(-1), false),
+        ];
+
+        let file_system = MemoryFileSystem::new();
+        file_system.add_dir("/home/me");
+        file_system.add_dir("/trash");
+        let clock = FakeClock::new(now);
+        let trash_manager = TrashManager::new(PathBuf::from("/trash"), file_system.clone())
+            .with_file_system(file_system.clone())
+            .with_clock(Arc::new(clock.clone()));
+        for (name, age, left, pinned) in cases {
+            let path = Path::new("/home/me").join(name);
+            file_system.add_file(&path, name);
+            clock.set(now - age);
+            let id = trash_manager.put(&path, now + left)?;
+            if pinned {
+                trash_manager.update(&id, MetadataUpdate::Pinned(true))?;
+            }
+        }
+
+        let stats = compute_stats(&trash_manager.list()?, now, 10);
+        assert_eq!(stats.items, cases.len());
+        let by_age: Vec<_> = stats
+            .by_age
+            .iter()
+            .map(|group| (group.name.as_str(), group.items))
+            .collect();
+        assert_eq!(
+            by_age,
+            [
+                ("Less than a day", 2),
+                ("1 to 7 days", 1),
+                ("1 to 4 weeks", 1),
+                ("More than 4 weeks", 1),
+            ]
+        );
+        // Pinned items and those already past their deletion date are not expiring
+        assert_eq!(stats.expiring_within_a_day.items, 1);
+        assert_eq!(stats.expiring_within_a_day.size, "fresh.txt".len() as u64);
+        assert_eq!(stats.expiring_within_a_week.items, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn top_level_dirs() {
+        let home = Some(Path::new("/home/me"));
+        let dir = |path: &str| top_level_dir(Path::new(path), home);
+        assert_eq!(dir("/home/me/projects/app/target"), "~/projects");
+        assert_eq!(dir("/home/me/notes.md"), "~");
+        assert_eq!(dir("/tmp/scratch/file"), "/tmp");
+        assert_eq!(dir("/file"), "/");
+    }
+}
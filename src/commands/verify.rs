@@ -0,0 +1,44 @@
+use clap::Args;
+use rrm::{trash::TrashBackend, Error, Result};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    #[clap(help = "The ID of the item to verify. Every item is verified if omitted.")]
+    pub id: Option<String>,
+}
+
+/// Checks trashed items against the checksums recorded when they were trashed, printing
+/// every file that changed. Fails if any item does not match.
+pub fn handle_verify(backend: impl TrashBackend, args: VerifyArgs) -> Result<()> {
+    let mut items = match args.id {
+        Some(id) => vec![backend.get(&id)?],
+        None => backend.list()?,
+    };
+    items.sort_by_key(|item| item.deletion_date());
+
+    let mut failed = 0;
+    for item in &items {
+        let prefix = format!("{} {}", item.id(), item.format_original_path());
+        match backend.verify(item) {
+            Ok(None) => println!("{}: no checksums recorded", prefix),
+            Ok(Some(mismatches)) if mismatches.is_empty() => println!("{}: OK", prefix),
+            Ok(Some(mismatches)) => {
+                failed += 1;
+                println!("{}: FAILED", prefix);
+                for mismatch in mismatches {
+                    println!("  {}", mismatch);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{}: FAILED", prefix);
+                println!("  {}", e);
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::VerificationFailed(failed)),
+    }
+}
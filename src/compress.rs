@@ -0,0 +1,222 @@
+use crate::Result;
+use chrono::{DateTime, Duration, Utc};
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// zstd compression level, a good trade-off between speed and ratio.
+const LEVEL: i32 = 3;
+
+/// Name under which a trashed directory is stored inside its archive.
+const ARCHIVE_ROOT: &str = "item";
+
+/// How a trashed item is compressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// A single file compressed with zstd.
+    Zstd,
+    /// A directory stored as a zstd-compressed tar archive.
+    TarZstd,
+}
+
+impl Compression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::TarZstd => "tar+zstd",
+        }
+    }
+
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        match value {
+            b"zstd" => Some(Compression::Zstd),
+            b"tar+zstd" => Some(Compression::TarZstd),
+            _ => None,
+        }
+    }
+}
+
+/// Decides which items are compressed: large items as soon as they are trashed, and every
+/// item once it has been in the trash for a while.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionPolicy {
+    /// Compress items of at least this many bytes when they are trashed.
+    pub threshold: Option<u64>,
+    /// Compress items that have been in the trash for longer than this when the trash is
+    /// cleaned.
+    pub after: Option<Duration>,
+}
+
+impl CompressionPolicy {
+    /// Returns whether any item can be compressed at all.
+    pub fn is_enabled(&self) -> bool {
+        self.threshold.is_some() || self.after.is_some()
+    }
+
+    /// Returns whether an item of `size` bytes is compressed when it is trashed.
+    pub fn on_trash(&self, size: u64) -> bool {
+        self.threshold.is_some_and(|threshold| size >= threshold)
+    }
+
+    /// Returns whether an item trashed at `trash_date` is due for compression at `now`.
+    pub fn is_due(&self, trash_date: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.after
+            .and_then(|after| trash_date.checked_add_signed(after))
+            .is_some_and(|due| due <= now)
+    }
+}
+
+/// Compresses the file or directory at `path` into a new file at `output`, which keeps the
+/// permissions and modification time of a compressed file.
+pub fn compress(path: &Path, output: &Path) -> Result<Compression> {
+    let meta = path.symlink_metadata()?;
+    let file = fs::File::create_new(output)?;
+    if meta.is_dir() {
+        let mut builder = tar::Builder::new(zstd::Encoder::new(file, LEVEL)?);
+        builder.follow_symlinks(false);
+        builder.append_dir_all(ARCHIVE_ROOT, path)?;
+        builder.into_inner()?.finish()?.sync_all()?;
+        return Ok(Compression::TarZstd);
+    }
+
+    zstd::stream::copy_encode(fs::File::open(path)?, &file, LEVEL)?;
+    file.set_permissions(meta.permissions())?;
+    file.set_modified(meta.modified()?)?;
+    file.sync_all()?;
+    Ok(Compression::Zstd)
+}
+
+/// Decompresses an item compressed by [`compress`] into `destination`, which must not exist.
+pub fn decompress(path: &Path, compression: Compression, destination: &Path) -> Result<()> {
+    match compression {
+        Compression::Zstd => {
+            let meta = path.metadata()?;
+            let file = fs::File::create_new(destination)?;
+            let result = (|| -> Result<()> {
+                zstd::stream::copy_decode(fs::File::open(path)?, &file)?;
+                file.set_permissions(meta.permissions())?;
+                file.set_modified(meta.modified()?)?;
+                Ok(())
+            })();
+            if result.is_err() {
+                let _ = fs::remove_file(destination);
+            }
+            result
+        }
+        Compression::TarZstd => {
+            // Unpacked next to the destination first, so the archive root can be renamed
+            let parent = destination.parent().unwrap_or(Path::new("."));
+            let temp_dir = parent.join(format!(".rrm-restoring-{}", Uuid::new_v4()));
+            fs::create_dir(&temp_dir)?;
+            let result = (|| -> Result<()> {
+                let mut archive = tar::Archive::new(zstd::Decoder::new(fs::File::open(path)?)?);
+                archive.set_preserve_permissions(true);
+                archive.set_preserve_mtime(true);
+                archive.unpack(&temp_dir)?;
+                fs::rename(temp_dir.join(ARCHIVE_ROOT), destination)?;
+                Ok(())
+            })();
+            let _ = fs::remove_dir_all(&temp_dir);
+            result
+        }
+    }
+}
+
+/// Opens a compressed file, decompressing it as it is read.
+pub fn open_file(path: &Path) -> Result<Box<dyn Read>> {
+    Ok(Box::new(zstd::Decoder::new(fs::File::open(path)?)?))
+}
+
+/// Calls `f` with the path each regular file of a compressed directory had before being
+/// trashed, and a reader of its contents, decompressed as they are read.
+pub fn for_each_file(
+    path: &Path,
+    original_path: &Path,
+    mut f: impl FnMut(PathBuf, &mut dyn Read),
+) -> Result<()> {
+    let mut archive = tar::Archive::new(zstd::Decoder::new(fs::File::open(path)?)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let entry_path = entry.path()?.into_owned();
+        let relative = entry_path.strip_prefix(ARCHIVE_ROOT).unwrap_or(&entry_path);
+        f(original_path.join(relative), &mut entry);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn compress_and_decompress_directory() -> Result<()> {
+        let dir = tempdir()?;
+        let item = dir.path().join("logs");
+        fs::create_dir_all(item.join("nested"))?;
+        fs::write(item.join("app.log"), "line\n".repeat(1000))?;
+        fs::write(item.join("nested/run.sh"), "#!/bin/sh\n")?;
+        fs::set_permissions(
+            item.join("nested/run.sh"),
+            fs::Permissions::from_mode(0o755),
+        )?;
+
+        let archive = dir.path().join("archive");
+        assert_eq!(compress(&item, &archive)?, Compression::TarZstd);
+        assert!(archive.metadata()?.len() < 5000);
+
+        let mut files = Vec::new();
+        for_each_file(&archive, Path::new("/var/logs"), |path, reader| {
+            files.push((path, std::io::copy(reader, &mut std::io::sink()).ok()))
+        })?;
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                (PathBuf::from("/var/logs/app.log"), Some(5000)),
+                (PathBuf::from("/var/logs/nested/run.sh"), Some(10)),
+            ]
+        );
+
+        let restored = dir.path().join("restored");
+        decompress(&archive, Compression::TarZstd, &restored)?;
+        assert_eq!(
+            fs::read(restored.join("app.log"))?,
+            "line\n".repeat(1000).as_bytes()
+        );
+        let mode = restored
+            .join("nested/run.sh")
+            .metadata()?
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        Ok(())
+    }
+
+    #[test]
+    fn compress_and_decompress_file() -> Result<()> {
+        let dir = tempdir()?;
+        let item = dir.path().join("data.csv");
+        fs::write(&item, "a,b,c\n".repeat(100))?;
+
+        let compressed = dir.path().join("compressed");
+        assert_eq!(compress(&item, &compressed)?, Compression::Zstd);
+        let mut contents = Vec::new();
+        open_file(&compressed)?.read_to_end(&mut contents)?;
+        assert_eq!(contents, "a,b,c\n".repeat(100).as_bytes());
+
+        let restored = dir.path().join("restored.csv");
+        decompress(&compressed, Compression::Zstd, &restored)?;
+        assert_eq!(fs::read(&restored)?, fs::read(&item)?);
+        Ok(())
+    }
+}
@@ -1,66 +1,257 @@
-use crate::{xattr::ExtendedAttributes, Error, Result};
-use std::{env, path::PathBuf};
+use crate::{
+    compress::CompressionPolicy,
+    crypto::{generate_key_file, generate_salt, EncryptionPolicy, KeySource, KEY_SIZE},
+    dry_run::{absolute, Plan, PlannedAction},
+    hooks::{HookEvent, Hooks},
+    patterns::parse_patterns,
+    shred::ShredPolicy,
+    xattr::ExtendedAttributes,
+    Error, Result,
+};
+use chrono::Duration;
+use log::warn;
+use std::{
+    env,
+    ffi::OsString,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
 
 // Constants used to store the trash directory path and grace period in the extended attributes.
 const TRASH_DIR_ATTR: &str = "trash_dir";
-const GRACE_PERIOD_ATTR: &str = "grace_period_in_days";
+const GRACE_PERIOD_ATTR: &str = "grace_period_in_seconds";
+const SHRED_ATTR: &str = "shred";
+const SHRED_PASSES_ATTR: &str = "shred_passes";
+const SHRED_PATTERNS_ATTR: &str = "shred_patterns";
+const COMPRESS_THRESHOLD_ATTR: &str = "compress_threshold";
+const COMPRESS_AFTER_ATTR: &str = "compress_after_in_seconds";
+const DEDUP_ATTR: &str = "dedup";
+const ENCRYPT_ATTR: &str = "encrypt";
+const ENCRYPT_PATTERNS_ATTR: &str = "encrypt_patterns";
+const ENCRYPTION_KEY_FILE_ATTR: &str = "encryption_key_file";
+const ENCRYPTION_SALT_ATTR: &str = "encryption_salt";
+/// Prefix of the attributes holding the hook commands, followed by the event with
+/// underscores, e.g. `hook_pre_purge`.
+const HOOK_ATTR_PREFIX: &str = "hook_";
+
+/// Environment variable holding the passphrase the encryption key is derived from, used
+/// when no key file is configured.
+const PASSPHRASE_VAR: &str = "RRM_PASSPHRASE";
+
+/// Attribute used by older versions, which only supported whole days. It is migrated to
+/// `GRACE_PERIOD_ATTR` the first time the configuration is loaded.
+const LEGACY_GRACE_PERIOD_ATTR: &str = "grace_period_in_days";
 
 /// Name of the default directory used to store trashed items in the user's home directory.
-const TRASH_DIR_NAME: &str = concat!(env!("HOME"), "/.tmp_trash");
+const TRASH_DIR_NAME: &str = ".tmp_trash";
 
 /// Default grace period in days before permanently deleting trashed items.
-const DEFAULT_GRACE_PERIOD_IN_DAYS: u32 = 7;
+const DEFAULT_GRACE_PERIOD_IN_DAYS: i64 = 7;
 
+/// The settings of rrm, stored in extended attributes of the trash directory, apart
+/// from the trash directory itself which is stored on the `rrm` binary.
 #[derive(Debug)]
 pub struct Config<T: ExtendedAttributes> {
-    pub grace_period_in_days: u32,
+    /// How long items stay in the trash by default.
+    pub grace_period: Duration,
+    /// Where trashed items are stored.
     pub trash_dir: PathBuf,
+    /// Which files are shredded when they are permanently deleted.
+    pub shred_policy: ShredPolicy,
+    /// Which items are encrypted in the trash, and with which key.
+    pub encryption_policy: EncryptionPolicy,
+    /// Which items are compressed in the trash.
+    pub compression_policy: CompressionPolicy,
+    /// Whether the contents of trashed files are stored only once across the trash.
+    pub dedup: bool,
+    /// Key file used to encrypt items, if one is configured.
+    pub encryption_key_file: Option<PathBuf>,
+    /// Commands run before and after items are trashed, restored and purged.
+    pub hooks: Hooks,
     xattr_manager: T,
     bin_path: PathBuf,
+    plan: Option<Plan>,
 }
 
 impl<T: ExtendedAttributes> Config<T> {
+    /// Loads the configuration, creating the trash directory if needed. The trash
+    /// directory is looked up on the running executable, see [`Self::load_from`] for
+    /// programs other than `rrm` itself.
     pub fn load(xattr_manager: T) -> Result<Self> {
-        // Get the path to the binarys
-        let bin = env::current_exe()?
-            .to_str()
-            .ok_or_else(|| {
-                Error::InvalidBinaryPath("Failed to convert binary path to string".to_string())
-            })?
-            .to_string();
-
-        let bin_path = PathBuf::from(&bin);
+        Self::load_from(xattr_manager, current_bin()?)
+    }
+
+    /// Loads the configuration like [`Self::load`], looking up the trash directory on the
+    /// `rrm` binary at `bin_path`, so a program shares the trash configured for the CLI.
+    pub fn load_from(xattr_manager: T, bin_path: PathBuf) -> Result<Self> {
+        Self::load_with(xattr_manager, bin_path, None)
+    }
+
+    /// Loads the configuration like [`Self::load`] for a dry run: creating a missing trash
+    /// directory, and later changes that do not go through `T` like generating a key file,
+    /// go into `plan` instead. Setting values is only planned if `T` is a
+    /// [`crate::dry_run::DryRunAttributes`] sharing the same plan.
+    pub fn load_dry_run(xattr_manager: T, plan: Plan) -> Result<Self> {
+        Self::load_with(xattr_manager, current_bin()?, Some(plan))
+    }
+
+    fn load_with(xattr_manager: T, bin_path: PathBuf, plan: Option<Plan>) -> Result<Self> {
         let trash_path = match xattr_manager.get_attr(&bin_path, TRASH_DIR_ATTR)? {
             // If the value is not empty, use it as the trash directory path.
-            Some(val) if !val.is_empty() => val,
-            _ => TRASH_DIR_NAME.to_string(),
+            Some(val) if !val.is_empty() => PathBuf::from(OsString::from_vec(val)),
+            _ => default_trash_dir()?,
+        };
+
+        let trash_dir = ensure_trash_folder(&trash_path, plan.as_ref())?;
+        migrate_grace_period(&xattr_manager, &trash_dir);
+        let grace_period = match xattr_manager.get_attr(&trash_dir, GRACE_PERIOD_ATTR)? {
+            // If the value is not a valid number (empty is included), use the default grace period.
+            Some(val) => std::str::from_utf8(&val)
+                .ok()
+                .and_then(|val| val.parse::<i64>().ok())
+                .and_then(Duration::try_seconds)
+                .unwrap_or(Duration::days(DEFAULT_GRACE_PERIOD_IN_DAYS)),
+            None => Duration::days(DEFAULT_GRACE_PERIOD_IN_DAYS),
         };
 
-        let trash_dir = ensure_trash_folder(&trash_path)?;
-        let grace_period_in_days: u32 =
-            match xattr_manager.get_attr(&trash_dir, GRACE_PERIOD_ATTR)? {
-                // If the value is not a valid number (empty is included), use the default grace period.
-                Some(val) => val.parse().unwrap_or(DEFAULT_GRACE_PERIOD_IN_DAYS),
-                None => DEFAULT_GRACE_PERIOD_IN_DAYS,
+        let shred_policy = load_shred_policy(&xattr_manager, &trash_dir)?;
+        let encryption_key_file =
+            match xattr_manager.get_attr(&trash_dir, ENCRYPTION_KEY_FILE_ATTR)? {
+                Some(val) if !val.is_empty() => Some(PathBuf::from(OsString::from_vec(val))),
+                _ => None,
             };
+        let encryption_policy =
+            load_encryption_policy(&xattr_manager, &trash_dir, encryption_key_file.clone())?;
+
+        let compression_policy = CompressionPolicy {
+            threshold: read_number(&xattr_manager, &trash_dir, COMPRESS_THRESHOLD_ATTR)?,
+            after: read_number(&xattr_manager, &trash_dir, COMPRESS_AFTER_ATTR)?
+                .and_then(Duration::try_seconds),
+        };
+        let dedup =
+            matches!(xattr_manager.get_attr(&trash_dir, DEDUP_ATTR)?, Some(val) if val == b"true");
+        let hooks = load_hooks(&xattr_manager, &trash_dir)?;
 
         Ok(Self {
             trash_dir,
-            grace_period_in_days,
+            grace_period,
+            shred_policy,
+            encryption_policy,
+            compression_policy,
+            dedup,
+            encryption_key_file,
+            hooks,
             bin_path,
             xattr_manager,
+            plan,
         })
     }
 
-    /// Sets the grace period (in days) before permanently deleting items.
-    /// The grace period is stored in the extended attributes of the trash folder.
+    /// Sets the grace period before permanently deleting items.
+    /// The grace period is stored in seconds in the extended attributes of the trash folder.
     ///
     /// # Arguments
     ///
-    /// * `days` - The number of days to wait before deleting the item permanently.
-    pub fn set_grace_period(&self, days: u32) -> Result<()> {
+    /// * `grace_period` - How long to wait before deleting the item permanently.
+    pub fn set_grace_period(&self, grace_period: Duration) -> Result<()> {
+        self.xattr_manager.set_attr(
+            &self.trash_dir,
+            GRACE_PERIOD_ATTR,
+            grace_period.num_seconds().to_string().as_bytes(),
+        )
+    }
+
+    /// Sets whether every permanently deleted file is shredded, not only those matching
+    /// the shred patterns.
+    pub fn set_shred(&self, shred: bool) -> Result<()> {
+        self.xattr_manager
+            .set_attr(&self.trash_dir, SHRED_ATTR, shred.to_string().as_bytes())
+    }
+
+    /// Sets how many times file contents are overwritten when shredding.
+    pub fn set_shred_passes(&self, passes: u32) -> Result<()> {
+        self.xattr_manager.set_attr(
+            &self.trash_dir,
+            SHRED_PASSES_ATTR,
+            passes.to_string().as_bytes(),
+        )
+    }
+
+    /// Sets the comma separated globs selecting the files that are always shredded,
+    /// e.g. `*.pem,.env`.
+    pub fn set_shred_patterns(&self, patterns: &str) -> Result<()> {
+        self.xattr_manager
+            .set_attr(&self.trash_dir, SHRED_PATTERNS_ATTR, patterns.as_bytes())
+    }
+
+    /// Sets the size in bytes from which items are compressed when they are trashed, or
+    /// disables it.
+    pub fn set_compress_threshold(&self, threshold: Option<u64>) -> Result<()> {
+        self.set_optional_number(COMPRESS_THRESHOLD_ATTR, threshold)
+    }
+
+    /// Sets how long items stay in the trash before they are compressed, or disables it.
+    pub fn set_compress_after(&self, after: Option<Duration>) -> Result<()> {
+        self.set_optional_number(COMPRESS_AFTER_ATTR, after.map(|after| after.num_seconds()))
+    }
+
+    /// Stores a number on the trash folder, or an empty value to unset it.
+    fn set_optional_number(&self, key: &str, value: Option<impl ToString>) -> Result<()> {
+        let value = value.map(|value| value.to_string()).unwrap_or_default();
+        self.xattr_manager
+            .set_attr(&self.trash_dir, key, value.as_bytes())
+    }
+
+    /// Sets whether the contents of trashed files are deduplicated, storing identical
+    /// contents only once.
+    pub fn set_dedup(&self, dedup: bool) -> Result<()> {
+        self.xattr_manager
+            .set_attr(&self.trash_dir, DEDUP_ATTR, dedup.to_string().as_bytes())
+    }
+
+    /// Sets whether every trashed item is encrypted, not only those matching the encryption
+    /// patterns.
+    pub fn set_encrypt(&self, encrypt: bool) -> Result<()> {
+        self.xattr_manager.set_attr(
+            &self.trash_dir,
+            ENCRYPT_ATTR,
+            encrypt.to_string().as_bytes(),
+        )
+    }
+
+    /// Sets the comma separated globs selecting the files that are encrypted in the trash,
+    /// e.g. `*.pem,.env`.
+    pub fn set_encrypt_patterns(&self, patterns: &str) -> Result<()> {
+        self.xattr_manager
+            .set_attr(&self.trash_dir, ENCRYPT_PATTERNS_ATTR, patterns.as_bytes())
+    }
+
+    /// Sets the file holding the encryption key, generating a new key if the file does
+    /// not exist yet. Returns whether a key was generated. Without a key file, the key is
+    /// derived from `RRM_PASSPHRASE`.
+    pub fn set_encryption_key_file(&self, path: Option<&Path>) -> Result<bool> {
+        let generated = path.is_some_and(|path| !path.exists());
+        match (path.filter(|_| generated), &self.plan) {
+            (Some(path), Some(plan)) => plan.record(PlannedAction::Write {
+                path: path.to_path_buf(),
+                size: KEY_SIZE as u64,
+            }),
+            (Some(path), None) => generate_key_file(path)?,
+            (None, _) => {}
+        }
+        self.xattr_manager.set_attr(
+            &self.trash_dir,
+            ENCRYPTION_KEY_FILE_ATTR,
+            path.map(|path| path.as_os_str().as_bytes())
+                .unwrap_or_default(),
+        )?;
+        Ok(generated)
+    }
+
+    /// Sets the shell command run on `event`, or removes it if `command` is empty.
+    pub fn set_hook(&self, event: HookEvent, command: &str) -> Result<()> {
         self.xattr_manager
-            .set_attr(&self.trash_dir, GRACE_PERIOD_ATTR, &days.to_string())
+            .set_attr(&self.trash_dir, &hook_attr(event), command.as_bytes())
     }
 
     /// Sets the directory where trashed items are stored.
@@ -69,17 +260,184 @@ impl<T: ExtendedAttributes> Config<T> {
     /// # Arguments
     ///
     /// * `path` - The path to the directory where trashed items should be stored.
-    pub fn set_trash_dir(&self, path: &str) -> Result<()> {
-        // let trash_xattr = xattr::XAttrManager::new(&self.bin_path);
+    pub fn set_trash_dir(&self, path: &Path) -> Result<()> {
         self.xattr_manager
-            .set_attr(&self.bin_path, TRASH_DIR_ATTR, path)
+            .set_attr(&self.bin_path, TRASH_DIR_ATTR, path.as_os_str().as_bytes())
+    }
+}
+
+/// Returns the default trash directory in the home directory of the user running rrm.
+fn default_trash_dir() -> Result<PathBuf> {
+    match env::var_os("HOME") {
+        Some(home) if !home.is_empty() => Ok(PathBuf::from(home).join(TRASH_DIR_NAME)),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "HOME is not set, configure the trash directory with `rrm config set --key trash-dir`",
+        )
+        .into()),
+    }
+}
+
+/// Returns the attribute holding the command of the `event` hook.
+fn hook_attr(event: HookEvent) -> String {
+    format!(
+        "{}{}",
+        HOOK_ATTR_PREFIX,
+        event.to_string().replace('-', "_")
+    )
+}
+
+/// Loads the hook commands, along with the executables of the default hooks directory.
+fn load_hooks<T: ExtendedAttributes>(xattr_manager: &T, trash_dir: &Path) -> Result<Hooks> {
+    let mut hooks = Hooks::default();
+    if let Some(dir) = Hooks::default_dir() {
+        hooks = hooks.with_dir(dir);
     }
+    for event in HookEvent::ALL {
+        match xattr_manager.get_attr(trash_dir, &hook_attr(event))? {
+            Some(val) if !val.is_empty() => {
+                hooks = hooks.with_command(event, String::from_utf8_lossy(&val));
+            }
+            _ => {}
+        }
+    }
+    Ok(hooks)
 }
 
-fn ensure_trash_folder(path: &str) -> Result<PathBuf> {
-    let trash_dir = PathBuf::from(path);
+/// Returns the path of the running executable, which holds the trash directory.
+fn current_bin() -> Result<PathBuf> {
+    let bin = env::current_exe()?;
+    if bin.to_str().is_none() {
+        return Err(Error::InvalidBinaryPath(
+            "Failed to convert binary path to string".to_string(),
+        ));
+    }
+    Ok(bin)
+}
+
+/// Creates the trash directory if it does not exist yet, or only plans it in a dry run.
+fn ensure_trash_folder(path: &Path, plan: Option<&Plan>) -> Result<PathBuf> {
+    let trash_dir = path.to_path_buf();
     if !trash_dir.exists() {
-        std::fs::create_dir(&trash_dir)?;
+        match plan {
+            Some(plan) => plan.record(PlannedAction::CreateDir {
+                path: absolute(&trash_dir),
+            }),
+            None => std::fs::create_dir(&trash_dir)?,
+        }
     }
     Ok(trash_dir)
 }
+
+/// Reads a number from the attributes of the trash folder. Missing, empty and invalid
+/// values are all unset.
+fn read_number<T: ExtendedAttributes, N: std::str::FromStr>(
+    xattr_manager: &T,
+    trash_dir: &Path,
+    key: &str,
+) -> Result<Option<N>> {
+    Ok(xattr_manager
+        .get_attr(trash_dir, key)?
+        .and_then(|val| std::str::from_utf8(&val).ok()?.parse().ok()))
+}
+
+fn load_shred_policy<T: ExtendedAttributes>(
+    xattr_manager: &T,
+    trash_dir: &Path,
+) -> Result<ShredPolicy> {
+    let read = |key| -> Result<Option<String>> {
+        Ok(xattr_manager
+            .get_attr(trash_dir, key)?
+            .and_then(|val| String::from_utf8(val).ok()))
+    };
+
+    let mut policy = ShredPolicy {
+        always: read(SHRED_ATTR)?.is_some_and(|val| val == "true"),
+        ..ShredPolicy::default()
+    };
+    if let Some(passes) = read(SHRED_PASSES_ATTR)?.and_then(|val| val.parse().ok()) {
+        policy.passes = passes;
+    }
+    if let Some(patterns) = read(SHRED_PATTERNS_ATTR)? {
+        // Patterns are validated when they are set, a broken value is only logged
+        match parse_patterns(&patterns) {
+            Ok(patterns) => policy.patterns = patterns,
+            Err(e) => warn!("Ignoring invalid '{SHRED_PATTERNS_ATTR}': {}", e),
+        }
+    }
+    Ok(policy)
+}
+
+/// Loads the encryption settings. The key comes from the configured key file, or else
+/// from the passphrase in `RRM_PASSPHRASE`; the salt for the passphrase is created with the
+/// trash the first time it is needed.
+fn load_encryption_policy<T: ExtendedAttributes>(
+    xattr_manager: &T,
+    trash_dir: &Path,
+    key_file: Option<PathBuf>,
+) -> Result<EncryptionPolicy> {
+    let read = |key| -> Result<Option<String>> {
+        Ok(xattr_manager
+            .get_attr(trash_dir, key)?
+            .and_then(|val| String::from_utf8(val).ok()))
+    };
+
+    let always = read(ENCRYPT_ATTR)?.is_some_and(|val| val == "true");
+    let patterns = match read(ENCRYPT_PATTERNS_ATTR)? {
+        Some(patterns) => parse_patterns(&patterns).unwrap_or_else(|e| {
+            warn!("Ignoring invalid '{ENCRYPT_PATTERNS_ATTR}': {}", e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+
+    let key_source = match (key_file, env::var(PASSPHRASE_VAR)) {
+        (Some(path), _) => KeySource::File(path),
+        (None, Ok(passphrase)) if !passphrase.is_empty() => {
+            let salt = match xattr_manager.get_attr(trash_dir, ENCRYPTION_SALT_ATTR)? {
+                Some(salt) if !salt.is_empty() => salt,
+                _ => {
+                    let salt = generate_salt();
+                    xattr_manager.set_attr(trash_dir, ENCRYPTION_SALT_ATTR, &salt)?;
+                    salt
+                }
+            };
+            KeySource::Passphrase { passphrase, salt }
+        }
+        _ => KeySource::None,
+    };
+
+    Ok(EncryptionPolicy::new(always, patterns, key_source))
+}
+
+/// Converts the day-based grace period of older versions into seconds. Failures are only
+/// logged, the legacy value is then simply ignored.
+fn migrate_grace_period<T: ExtendedAttributes>(xattr_manager: &T, trash_dir: &Path) {
+    let grace_period = match xattr_manager.get_attr(trash_dir, LEGACY_GRACE_PERIOD_ATTR) {
+        Ok(Some(val)) => std::str::from_utf8(&val)
+            .ok()
+            .and_then(|val| val.parse::<i64>().ok())
+            .and_then(Duration::try_days),
+        _ => return,
+    };
+
+    let migrated = match grace_period {
+        Some(grace_period) => xattr_manager
+            .get_attr(trash_dir, GRACE_PERIOD_ATTR)
+            .and_then(|current| match current {
+                // A value already stored in seconds takes precedence
+                Some(_) => Ok(()),
+                None => xattr_manager.set_attr(
+                    trash_dir,
+                    GRACE_PERIOD_ATTR,
+                    grace_period.num_seconds().to_string().as_bytes(),
+                ),
+            }),
+        None => Ok(()),
+    }
+    .and_then(|_| xattr_manager.remove_attr(trash_dir, LEGACY_GRACE_PERIOD_ATTR));
+
+    if let Err(e) = migrated {
+        warn!("Failed to migrate '{LEGACY_GRACE_PERIOD_ATTR}': {}", e);
+    }
+}
@@ -0,0 +1,513 @@
+use crate::{
+    patterns::{matches_any, matching_files},
+    Error, Result,
+};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+        Aead, AeadCore, KeyInit, OsRng,
+    },
+    Key, XChaCha20Poly1305, XNonce,
+};
+use glob::Pattern;
+use std::{
+    cell::OnceCell,
+    fs,
+    io::{self, Read, Write},
+    os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Written at the start of every encrypted file or attribute, so encrypted data can be told
+/// apart from plain data.
+const MAGIC: &[u8; 8] = b"RRMENC1\n";
+
+/// Files are encrypted in chunks, so they never have to fit in memory.
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+/// The STREAM construction uses 5 bytes of the 24-byte XChaCha20 nonce for its counter.
+const STREAM_NONCE_SIZE: usize = 19;
+
+pub const KEY_SIZE: usize = 32;
+pub const SALT_SIZE: usize = 16;
+
+/// Where the encryption key comes from.
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum KeySource {
+    /// No key is configured, encrypting or decrypting fails.
+    None,
+    /// A file holding the raw key.
+    File(PathBuf),
+    /// A passphrase stretched with Argon2, using a salt stored with the trash.
+    Passphrase { passphrase: String, salt: Vec<u8> },
+}
+
+/// Decides which items are encrypted when they enter the trash, and holds the key used to
+/// encrypt and decrypt them.
+#[derive(Clone)]
+pub struct EncryptionPolicy {
+    /// Encrypt every item, not only those matching `patterns`.
+    pub always: bool,
+    /// Globs like `*.pem` or `.env`, matched like the shred patterns.
+    pub patterns: Vec<Pattern>,
+    key_source: KeySource,
+    /// The key is only loaded when needed, deriving it from a passphrase is slow on purpose.
+    key: OnceCell<Key>,
+}
+
+impl std::fmt::Debug for EncryptionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the key or the passphrase
+        let key_source = match &self.key_source {
+            KeySource::None => "none",
+            KeySource::File(_) => "key file",
+            KeySource::Passphrase { .. } => "passphrase",
+        };
+        f.debug_struct("EncryptionPolicy")
+            .field("always", &self.always)
+            .field("patterns", &self.patterns)
+            .field("key_source", &key_source)
+            .finish()
+    }
+}
+
+impl Default for EncryptionPolicy {
+    fn default() -> Self {
+        Self::new(false, Vec::new(), KeySource::None)
+    }
+}
+
+impl EncryptionPolicy {
+    pub fn new(always: bool, patterns: Vec<Pattern>, key_source: KeySource) -> Self {
+        Self {
+            always,
+            patterns,
+            key_source,
+            key: OnceCell::new(),
+        }
+    }
+
+    /// Returns the same policy, but encrypting every item.
+    pub fn encrypt_all(self) -> Self {
+        Self {
+            always: true,
+            ..self
+        }
+    }
+
+    /// Returns whether any item can be encrypted at all.
+    pub fn is_enabled(&self) -> bool {
+        self.always || !self.patterns.is_empty()
+    }
+
+    /// Returns whether a file that was originally at `original_path` must be encrypted.
+    pub fn matches(&self, original_path: &Path) -> bool {
+        self.always || matches_any(&self.patterns, original_path)
+    }
+
+    /// Returns the key, loading it on first use.
+    pub fn key(&self) -> Result<&Key> {
+        if let Some(key) = self.key.get() {
+            return Ok(key);
+        }
+
+        let key = match &self.key_source {
+            KeySource::None => {
+                return Err(Error::Encryption(
+                    "no key configured, set 'encryption-key-file' or RRM_PASSPHRASE".to_string(),
+                ))
+            }
+            KeySource::File(path) => {
+                let bytes = fs::read(path)?;
+                if bytes.len() != KEY_SIZE {
+                    return Err(Error::Encryption(format!(
+                        "the key file '{}' must hold exactly {} bytes",
+                        path.display(),
+                        KEY_SIZE
+                    )));
+                }
+                *Key::from_slice(&bytes)
+            }
+            KeySource::Passphrase { passphrase, salt } => {
+                let mut key = Key::default();
+                Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                    .map_err(|e| Error::Encryption(e.to_string()))?;
+                key
+            }
+        };
+        Ok(self.key.get_or_init(|| key))
+    }
+
+    /// Encrypts a small value like an attribute at once.
+    pub fn encrypt_bytes(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = XChaCha20Poly1305::new(self.key()?);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(aead_error)?;
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&nonce);
+        data.extend(ciphertext);
+        Ok(data)
+    }
+
+    /// Decrypts a value encrypted by [`Self::encrypt_bytes`].
+    pub fn decrypt_bytes(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let nonce_size = XNonce::default().len();
+        let data = data
+            .strip_prefix(MAGIC)
+            .filter(|data| data.len() >= nonce_size)
+            .ok_or_else(|| Error::Encryption("the value is not encrypted".to_string()))?;
+
+        let (nonce, ciphertext) = data.split_at(nonce_size);
+        XChaCha20Poly1305::new(self.key()?)
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(aead_error)
+    }
+
+    /// Encrypts the regular files of a trashed item that match the policy, in place.
+    /// `original_path` is where the item lived before being trashed and is what the policy
+    /// is matched against.
+    ///
+    /// The ciphertext is written over the plaintext, which it is always longer than, so the
+    /// plaintext blocks are not left behind on filesystems that overwrite in place. Files
+    /// with other hard links are skipped, as the other links would see the ciphertext;
+    /// their paths are returned.
+    pub fn encrypt_tree(
+        &self,
+        path: &Path,
+        original_path: &Path,
+        temp_dir: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        let mut skipped = Vec::new();
+        for (file, original) in
+            matching_files(path, original_path, |original| self.matches(original))?
+        {
+            let meta = file.symlink_metadata()?;
+            if meta.nlink() > 1 {
+                skipped.push(original);
+                continue;
+            }
+
+            let temp_path = temp_dir.join(format!(".encrypting-{}", uuid::Uuid::new_v4()));
+            let result = (|| -> Result<()> {
+                let mut temp = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&temp_path)?;
+                self.encrypt_stream(fs::File::open(&file)?, &mut temp)?;
+                temp.sync_all()?;
+
+                // Read-only files are made writable for as long as they are overwritten
+                let permissions = meta.permissions();
+                fs::set_permissions(&file, fs::Permissions::from_mode(0o600))?;
+                let mut target = fs::OpenOptions::new().write(true).open(&file)?;
+                io::copy(&mut fs::File::open(&temp_path)?, &mut target)?;
+                target.set_modified(meta.modified()?)?;
+                target.sync_all()?;
+                fs::set_permissions(&file, permissions)?;
+                Ok(())
+            })();
+            let _ = fs::remove_file(&temp_path);
+            result?;
+        }
+        Ok(skipped)
+    }
+
+    /// Decrypts every encrypted file of a restored item in place, keeping the permissions
+    /// and modification times of the files.
+    pub fn decrypt_tree(&self, path: &Path) -> Result<()> {
+        // The files are listed up front, as temporary files are created next to them
+        for (file, _) in matching_files(path, path, |_| true)? {
+            if !is_encrypted_file(&file)? {
+                continue;
+            }
+
+            let file = file.as_path();
+            let meta = file.metadata()?;
+            let mut temp_name = file.file_name().unwrap_or_default().to_os_string();
+            temp_name.push(".rrm-decrypting");
+            let temp_path = file.with_file_name(temp_name);
+
+            let result = (|| -> Result<()> {
+                let mut temp = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&temp_path)?;
+                self.decrypt_stream(fs::File::open(file)?, &mut temp)?;
+                temp.set_permissions(meta.permissions())?;
+                temp.set_modified(meta.modified()?)?;
+                temp.sync_all()?;
+                fs::rename(&temp_path, file)?;
+                Ok(())
+            })();
+            if result.is_err() {
+                let _ = fs::remove_file(&temp_path);
+            }
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Reads a file of a trashed item, decrypting it if it is encrypted.
+    pub fn read_file(&self, path: &Path) -> Result<Vec<u8>> {
+        let data = fs::read(path)?;
+        if !data.starts_with(MAGIC) {
+            return Ok(data);
+        }
+
+        let mut plaintext = Vec::new();
+        self.decrypt_stream(data.as_slice(), &mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    fn encrypt_stream(&self, mut reader: impl Read, mut writer: impl Write) -> Result<()> {
+        let mut nonce = [0; STREAM_NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&nonce)?;
+
+        let mut encryptor =
+            EncryptorBE32::from_aead(XChaCha20Poly1305::new(self.key()?), &nonce.into());
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut next = vec![0; CHUNK_SIZE];
+        let mut len = read_full(&mut reader, &mut chunk)?;
+        loop {
+            // A full chunk is only known to be the last one once the next read is empty
+            let next_len = if len == CHUNK_SIZE {
+                read_full(&mut reader, &mut next)?
+            } else {
+                0
+            };
+            if next_len == 0 {
+                let ciphertext = encryptor.encrypt_last(&chunk[..len]).map_err(aead_error)?;
+                writer.write_all(&ciphertext)?;
+                return Ok(());
+            }
+
+            let ciphertext = encryptor.encrypt_next(&chunk[..len]).map_err(aead_error)?;
+            writer.write_all(&ciphertext)?;
+            std::mem::swap(&mut chunk, &mut next);
+            len = next_len;
+        }
+    }
+
+    fn decrypt_stream(&self, reader: impl Read, mut writer: impl Write) -> Result<()> {
+        let mut plaintext = self.decrypting(reader)?;
+        match io::copy(&mut plaintext, &mut writer) {
+            Err(_) if plaintext.corrupted => Err(aead_error(chacha20poly1305::aead::Error)),
+            result => Ok(result.map(|_| ())?),
+        }
+    }
+
+    /// Opens a file of a trashed item, decrypting it as it is read if it is encrypted, so
+    /// large files never have to fit in memory.
+    pub fn open_file(&self, path: &Path) -> Result<Box<dyn Read>> {
+        let file = fs::File::open(path)?;
+        if !is_encrypted_file(path)? {
+            return Ok(Box::new(file));
+        }
+        Ok(Box::new(self.decrypting(file)?))
+    }
+
+    /// Starts decrypting what `reader` reads, checking the header of the encrypted data.
+    fn decrypting<R: Read>(&self, mut reader: R) -> Result<Decrypting<R>> {
+        let mut header = [0; MAGIC.len() + STREAM_NONCE_SIZE];
+        reader.read_exact(&mut header)?;
+        if !header.starts_with(MAGIC) {
+            return Err(Error::Encryption("the file is not encrypted".to_string()));
+        }
+
+        let nonce: [u8; STREAM_NONCE_SIZE] = header[MAGIC.len()..].try_into().unwrap_or_default();
+        let decryptor =
+            DecryptorBE32::from_aead(XChaCha20Poly1305::new(self.key()?), &nonce.into());
+        let mut chunk = vec![0; CHUNK_SIZE + TAG_SIZE];
+        let len = read_full(&mut reader, &mut chunk)?;
+        Ok(Decrypting {
+            reader,
+            decryptor: Some(decryptor),
+            chunk,
+            next: vec![0; CHUNK_SIZE + TAG_SIZE],
+            len,
+            plaintext: Vec::new(),
+            pos: 0,
+            corrupted: false,
+        })
+    }
+}
+
+/// Decrypts the chunks of an encrypted file as they are read.
+struct Decrypting<R> {
+    reader: R,
+    /// Gone once the last chunk is decrypted.
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    /// The next chunk to decrypt, `len` bytes long, and the one after it.
+    chunk: Vec<u8>,
+    next: Vec<u8>,
+    len: usize,
+    /// The decrypted chunk being read, up to `pos`.
+    plaintext: Vec<u8>,
+    pos: usize,
+    /// A chunk failed to decrypt, because of a wrong key or corrupted data.
+    corrupted: bool,
+}
+
+impl<R: Read> Read for Decrypting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plaintext.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            // A full chunk is only known to be the last one once the next read is empty
+            let next_len = if self.len == self.chunk.len() {
+                read_full(&mut self.reader, &mut self.next)?
+            } else {
+                0
+            };
+            let chunk = &self.chunk[..self.len];
+            let plaintext = match self.decryptor.take() {
+                Some(decryptor) if next_len == 0 => decryptor.decrypt_last(chunk),
+                Some(mut decryptor) => {
+                    let plaintext = decryptor.decrypt_next(chunk);
+                    self.decryptor = Some(decryptor);
+                    plaintext
+                }
+                None => return Ok(0),
+            };
+            self.plaintext = plaintext.map_err(|_| {
+                self.corrupted = true;
+                self.decryptor = None;
+                io::Error::new(io::ErrorKind::InvalidData, "wrong key or corrupted data")
+            })?;
+            self.pos = 0;
+            std::mem::swap(&mut self.chunk, &mut self.next);
+            self.len = next_len;
+        }
+
+        let len = buf.len().min(self.plaintext.len() - self.pos);
+        buf[..len].copy_from_slice(&self.plaintext[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Returns whether an attribute value was encrypted by [`EncryptionPolicy::encrypt_bytes`].
+pub fn is_encrypted(value: &[u8]) -> bool {
+    value.starts_with(MAGIC)
+}
+
+/// Returns whether the regular file at `path` was encrypted by rrm.
+pub fn is_encrypted_file(path: &Path) -> Result<bool> {
+    let mut header = [0; MAGIC.len()];
+    let len = read_full(&mut fs::File::open(path)?, &mut header)?;
+    Ok(len == MAGIC.len() && &header == MAGIC)
+}
+
+/// Creates a new key file readable only by its owner.
+pub fn generate_key_file(path: &Path) -> Result<()> {
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(&key)?;
+    Ok(file.sync_all()?)
+}
+
+/// Returns a new random salt for deriving a key from a passphrase.
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/// Reads until `buffer` is full or the end of the input, returning the number of bytes read.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+fn aead_error(_: chacha20poly1305::aead::Error) -> Error {
+    Error::Encryption("wrong key or corrupted data".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn policy_with_key(dir: &Path) -> Result<EncryptionPolicy> {
+        let key_file = dir.join("key");
+        generate_key_file(&key_file)?;
+        Ok(EncryptionPolicy::new(
+            true,
+            Vec::new(),
+            KeySource::File(key_file),
+        ))
+    }
+
+    #[test]
+    fn encrypt_and_decrypt_tree() -> Result<()> {
+        let dir = tempdir()?;
+        let policy = policy_with_key(dir.path())?;
+        let item = dir.path().join("item");
+        fs::create_dir(&item)?;
+        // Spans several chunks, with a partial last one
+        let large: Vec<u8> = (0..CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        fs::write(item.join("large.bin"), &large)?;
+        fs::write(item.join("empty"), "")?;
+
+        let skipped = policy.encrypt_tree(&item, Path::new("/work/item"), dir.path())?;
+        assert!(skipped.is_empty());
+        assert!(is_encrypted_file(&item.join("large.bin"))?);
+        assert!(is_encrypted_file(&item.join("empty"))?);
+        assert_eq!(policy.read_file(&item.join("large.bin"))?, large);
+        let mut streamed = Vec::new();
+        policy
+            .open_file(&item.join("large.bin"))?
+            .read_to_end(&mut streamed)?;
+        assert_eq!(streamed, large);
+
+        policy.decrypt_tree(&item)?;
+        assert_eq!(fs::read(item.join("large.bin"))?, large);
+        assert_eq!(fs::read(item.join("empty"))?, b"");
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_with_wrong_key_fails() -> Result<()> {
+        let dir = tempdir()?;
+        let policy = policy_with_key(dir.path())?;
+        let encrypted = policy.encrypt_bytes(b"/home/me/.env")?;
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(policy.decrypt_bytes(&encrypted)?, b"/home/me/.env");
+
+        let other = EncryptionPolicy::new(
+            true,
+            Vec::new(),
+            KeySource::Passphrase {
+                passphrase: "hunter2".to_string(),
+                salt: generate_salt(),
+            },
+        );
+        assert!(matches!(
+            other.decrypt_bytes(&encrypted),
+            Err(Error::Encryption(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,575 @@
+use crate::{
+    filesystem::{FileKind, FileSystem, Lock, Metadata},
+    trash::{escape_os_str, format_size},
+    xattr::{ExtendedAttributes, XAttrError},
+    Result,
+};
+use serde::{Serialize, Serializer};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A change a dry run would have made.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum PlannedAction {
+    /// A file, directory or symlink moved to another path.
+    Move {
+        #[serde(serialize_with = "escaped")]
+        from: PathBuf,
+        #[serde(serialize_with = "escaped")]
+        to: PathBuf,
+    },
+    /// A copy made of a file, directory or symlink, e.g. to move it across filesystems.
+    Copy {
+        #[serde(serialize_with = "escaped")]
+        from: PathBuf,
+        #[serde(serialize_with = "escaped")]
+        to: PathBuf,
+    },
+    /// A file, directory or symlink deleted, freeing `size` bytes.
+    Remove {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+        size: u64,
+    },
+    /// A file created or replaced with `size` bytes of contents.
+    Write {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+        size: u64,
+    },
+    CreateDir {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+    },
+    /// An extended attribute set. Values that are not text, like encrypted paths, are
+    /// shown by their length.
+    SetAttr {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+        key: String,
+        value: String,
+    },
+    RemoveAttr {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+        key: String,
+    },
+    /// A trashed item replaced with a compressed copy.
+    Compress {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+    },
+    /// A compressed item written back uncompressed to `to`.
+    Decompress {
+        #[serde(serialize_with = "escaped")]
+        from: PathBuf,
+        #[serde(serialize_with = "escaped")]
+        to: PathBuf,
+    },
+    Encrypt {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+    },
+    Decrypt {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+    },
+    /// The contents of a trashed item moved to the shared blob store.
+    Deduplicate {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+    },
+    /// A deduplicated item rebuilt at `to` from the blob store.
+    Rebuild {
+        #[serde(serialize_with = "escaped")]
+        from: PathBuf,
+        #[serde(serialize_with = "escaped")]
+        to: PathBuf,
+    },
+    /// A file, directory or symlink overwritten before being deleted, freeing `size` bytes.
+    Shred {
+        #[serde(serialize_with = "escaped")]
+        path: PathBuf,
+        size: u64,
+    },
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = |path: &Path| escape_os_str(path.as_os_str());
+        match self {
+            PlannedAction::Move { from, to } => write!(f, "move {} to {}", path(from), path(to)),
+            PlannedAction::Copy { from, to } => write!(f, "copy {} to {}", path(from), path(to)),
+            PlannedAction::Remove { path: p, size } => {
+                write!(f, "remove {} ({})", path(p), format_size(*size))
+            }
+            PlannedAction::Write { path: p, size } => {
+                write!(f, "write {} ({})", path(p), format_size(*size))
+            }
+            PlannedAction::CreateDir { path: p } => write!(f, "create directory {}", path(p)),
+            PlannedAction::SetAttr {
+                path: p,
+                key,
+                value,
+            } => {
+                write!(f, "set {} = {} on {}", key, value, path(p))
+            }
+            PlannedAction::RemoveAttr { path: p, key } => {
+                write!(f, "remove {} from {}", key, path(p))
+            }
+            PlannedAction::Compress { path: p } => write!(f, "compress {}", path(p)),
+            PlannedAction::Decompress { from, to } => {
+                write!(f, "decompress {} to {}", path(from), path(to))
+            }
+            PlannedAction::Encrypt { path: p } => write!(f, "encrypt {}", path(p)),
+            PlannedAction::Decrypt { path: p } => write!(f, "decrypt {}", path(p)),
+            PlannedAction::Deduplicate { path: p } => write!(f, "deduplicate {}", path(p)),
+            PlannedAction::Rebuild { from, to } => {
+                write!(f, "rebuild {} at {}", path(from), path(to))
+            }
+            PlannedAction::Shred { path: p, size } => {
+                write!(f, "shred {} ({})", path(p), format_size(*size))
+            }
+        }
+    }
+}
+
+fn escaped<S: Serializer>(path: &Path, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&escape_os_str(path.as_os_str()))
+}
+
+/// The actions collected by a dry run, shared by the [`DryRunFileSystem`] and the
+/// [`DryRunAttributes`] of a [`crate::TrashManager`] so later steps see what earlier ones
+/// would have done. Clones share the same plan.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    actions: Vec<PlannedAction>,
+    /// How the planned actions change the filesystem, oldest first.
+    changes: Vec<Change>,
+    /// Attributes set or removed by the plan, by the real path they would be on.
+    attrs: HashMap<(PathBuf, String), Option<Vec<u8>>>,
+}
+
+#[derive(Debug)]
+enum Change {
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Copied {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Removed(PathBuf),
+    Created {
+        path: PathBuf,
+        kind: FileKind,
+        contents: Vec<u8>,
+    },
+}
+
+/// Where a path would be found once the planned changes are made.
+enum Resolved {
+    /// At this path of the real filesystem, possibly moved from there by the plan.
+    Real(PathBuf),
+    /// Created by the plan.
+    Created(Metadata, Vec<u8>),
+    /// Moved away or removed by the plan.
+    Gone,
+}
+
+impl Plan {
+    /// Creates an empty plan.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an action to the plan. Paths it moves or removes are seen as such by the
+    /// filesystem and attributes of the dry run from then on.
+    pub fn record(&self, action: PlannedAction) {
+        let change = match &action {
+            PlannedAction::Move { from, to }
+            | PlannedAction::Decompress { from, to }
+            | PlannedAction::Rebuild { from, to } => Some(Change::Moved {
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            PlannedAction::Copy { from, to } => Some(Change::Copied {
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            PlannedAction::Remove { path, .. } | PlannedAction::Shred { path, .. } => {
+                Some(Change::Removed(path.clone()))
+            }
+            PlannedAction::CreateDir { path } => Some(Change::Created {
+                path: path.clone(),
+                kind: FileKind::Directory,
+                contents: Vec::new(),
+            }),
+            _ => None,
+        };
+        let mut state = self.state();
+        state.changes.extend(change);
+        state.actions.push(action);
+    }
+
+    /// Returns the planned actions, in the order they would have been made.
+    pub fn actions(&self) -> Vec<PlannedAction> {
+        self.state().actions.clone()
+    }
+
+    /// Returns how many bytes the removed and shredded paths take up.
+    pub fn reclaimed(&self) -> u64 {
+        self.state()
+            .actions
+            .iter()
+            .map(|action| match action {
+                PlannedAction::Remove { size, .. } | PlannedAction::Shred { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) {
+        let mut state = self.state();
+        state.actions.push(PlannedAction::Write {
+            path: path.to_path_buf(),
+            size: contents.len() as u64,
+        });
+        state.changes.push(Change::Created {
+            path: path.to_path_buf(),
+            kind: FileKind::File,
+            contents: contents.to_vec(),
+        });
+    }
+
+    fn resolve(&self, path: &Path) -> Resolved {
+        let mut path = path.to_path_buf();
+        for change in self.state().changes.iter().rev() {
+            match change {
+                Change::Moved { from, to } => {
+                    if let Ok(rest) = path.strip_prefix(to) {
+                        path = join(from, rest);
+                    } else if path.starts_with(from) {
+                        return Resolved::Gone;
+                    }
+                }
+                Change::Copied { from, to } => {
+                    if let Ok(rest) = path.strip_prefix(to) {
+                        path = join(from, rest);
+                    }
+                }
+                Change::Removed(removed) if path.starts_with(removed) => return Resolved::Gone,
+                Change::Created {
+                    path: created,
+                    kind,
+                    contents,
+                } if *created == path => {
+                    let len = contents.len() as u64;
+                    return Resolved::Created(Metadata { kind: *kind, len }, contents.clone());
+                }
+                _ => {}
+            }
+        }
+        Resolved::Real(path)
+    }
+
+    /// Returns the paths the plan moves, copies or creates directly inside `dir`.
+    fn added_to(&self, dir: &Path) -> Vec<PathBuf> {
+        self.state()
+            .changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Moved { to: path, .. }
+                | Change::Copied { to: path, .. }
+                | Change::Created { path, .. } => Some(path),
+                Change::Removed(_) => None,
+            })
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the path the attributes of `path` are kept on for the plan, which stays
+    /// the same when the plan moves it.
+    fn attrs_key(&self, path: &Path, key: &str) -> (PathBuf, String) {
+        let path = match self.resolve(path) {
+            Resolved::Real(real) => real,
+            Resolved::Created(..) | Resolved::Gone => path.to_path_buf(),
+        };
+        (path, key.to_string())
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Makes relative paths absolute, so they are planned and shown the same way however
+/// they were given.
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Joins a path relative to `base`, where an empty path is `base` itself.
+fn join(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relative)
+    }
+}
+
+/// A filesystem that reads through to another one but only records the changes it is
+/// asked to make in a [`Plan`]. Reads see the planned changes, so e.g. an item the plan
+/// moves to the trash is listed there.
+pub struct DryRunFileSystem {
+    inner: Box<dyn FileSystem>,
+    plan: Plan,
+}
+
+impl DryRunFileSystem {
+    pub fn new(inner: Box<dyn FileSystem>, plan: Plan) -> Self {
+        Self { inner, plan }
+    }
+
+    /// Returns the number of bytes used by the files below `path`, as planned.
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        let meta = self.metadata(path)?;
+        if meta.kind != FileKind::Directory {
+            return Ok(meta.len);
+        }
+        let mut size = 0;
+        for child in self.read_dir(path)? {
+            size += self.size(&child)?;
+        }
+        Ok(size)
+    }
+}
+
+impl FileSystem for DryRunFileSystem {
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (&absolute(from), &absolute(to));
+        self.metadata(from)?;
+        self.plan.record(PlannedAction::Move {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let path = &absolute(path);
+        let size = self.size(path)?;
+        self.plan.record(PlannedAction::Remove {
+            path: path.to_path_buf(),
+            size,
+        });
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = &absolute(path);
+        match self.plan.resolve(path) {
+            Resolved::Real(real) => self.inner.metadata(&real),
+            Resolved::Created(meta, _) => Ok(meta),
+            Resolved::Gone => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let path = &absolute(path);
+        let mut entries = BTreeSet::new();
+        match self.plan.resolve(path) {
+            Resolved::Real(real) => {
+                let added = self.plan.added_to(path);
+                match self.inner.read_dir(&real) {
+                    // A directory the plan creates is not on the real filesystem
+                    Err(e) if e.kind() == io::ErrorKind::NotFound && !added.is_empty() => {}
+                    result => entries.extend(
+                        result?
+                            .into_iter()
+                            .filter_map(|entry| Some(path.join(entry.file_name()?))),
+                    ),
+                }
+                entries.extend(added);
+            }
+            Resolved::Created(..) => entries.extend(self.plan.added_to(path)),
+            Resolved::Gone => return Err(io::ErrorKind::NotFound.into()),
+        }
+        // Entries the plan moved away or removed are gone
+        Ok(entries
+            .into_iter()
+            .filter(|entry| self.exists(entry))
+            .collect())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (from, to) = (&absolute(from), &absolute(to));
+        self.metadata(from)?;
+        self.plan.record(PlannedAction::Copy {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        let path = &absolute(path);
+        match self.plan.resolve(path) {
+            Resolved::Real(real) => self.inner.open(&real),
+            Resolved::Created(_, contents) => Ok(Box::new(Cursor::new(contents))),
+            Resolved::Gone => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let path = &absolute(path);
+        self.plan.write(path, contents);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let path = &absolute(path);
+        if !self.exists(path) {
+            self.plan.record(PlannedAction::CreateDir {
+                path: path.to_path_buf(),
+            });
+        }
+        Ok(())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = &absolute(path);
+        match self.plan.resolve(path) {
+            Resolved::Real(real) if &real == path => self.inner.canonicalize(path),
+            Resolved::Real(_) | Resolved::Created(..) => Ok(path.to_path_buf()),
+            Resolved::Gone => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn lock(&self, dir: &Path, _exclusive: bool) -> io::Result<Lock> {
+        // Nothing is changed, so a dry run does not need to keep others out, and there
+        // is nobody to keep out of a directory that is only planned
+        match self.plan.resolve(&absolute(dir)) {
+            Resolved::Real(real) => self.inner.lock(&real, false),
+            Resolved::Created(..) => Ok(Lock::default()),
+            Resolved::Gone => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+/// Extended attributes read from another implementation, with the changes only recorded
+/// in a [`Plan`]. Reads see the planned changes, including on paths the plan moves.
+#[derive(Debug, Clone)]
+pub struct DryRunAttributes<T: ExtendedAttributes> {
+    inner: T,
+    plan: Plan,
+}
+
+impl<T: ExtendedAttributes> DryRunAttributes<T> {
+    pub fn new(inner: T, plan: Plan) -> Self {
+        Self { inner, plan }
+    }
+}
+
+impl<T: ExtendedAttributes> ExtendedAttributes for DryRunAttributes<T> {
+    fn set_attr(&self, path: &Path, key: &str, value: &[u8]) -> Result<()> {
+        let path = &absolute(path);
+        let attrs_key = self.plan.attrs_key(path, key);
+        self.plan.record(PlannedAction::SetAttr {
+            path: path.to_path_buf(),
+            key: key.to_string(),
+            value: match std::str::from_utf8(value) {
+                Ok(value) => value.to_string(),
+                Err(_) => format!("<{} bytes>", value.len()),
+            },
+        });
+        self.plan
+            .state()
+            .attrs
+            .insert(attrs_key, Some(value.to_vec()));
+        Ok(())
+    }
+
+    fn get_attr(&self, path: &Path, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = &absolute(path);
+        let attrs_key = self.plan.attrs_key(path, key);
+        if let Some(value) = self.plan.state().attrs.get(&attrs_key) {
+            return Ok(value.clone());
+        }
+        match self.plan.resolve(path) {
+            Resolved::Real(real) => self.inner.get_attr(&real, key),
+            Resolved::Created(..) => Ok(None),
+            Resolved::Gone => Err(XAttrError::GetAttr {
+                attr: key.to_string(),
+                path: path.to_path_buf(),
+                source: io::ErrorKind::NotFound.into(),
+            }
+            .into()),
+        }
+    }
+
+    fn remove_attr(&self, path: &Path, key: &str) -> Result<()> {
+        let path = &absolute(path);
+        let attrs_key = self.plan.attrs_key(path, key);
+        self.plan.record(PlannedAction::RemoveAttr {
+            path: path.to_path_buf(),
+            key: key.to_string(),
+        });
+        self.plan.state().attrs.insert(attrs_key, None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filesystem::MemoryFileSystem;
+
+    #[test]
+    fn planned_changes_are_seen_by_later_reads() -> Result<()> {
+        let memory = MemoryFileSystem::new();
+        memory.add_file("/home/me/notes.md", "notes");
+        memory.add_dir("/trash");
+        memory.set_attr(Path::new("/home/me/notes.md"), "kept", b"yes")?;
+        let plan = Plan::new();
+        let fs = DryRunFileSystem::new(Box::new(memory.clone()), plan.clone());
+        let attrs = DryRunAttributes::new(memory.clone(), plan.clone());
+
+        attrs.set_attr(Path::new("/home/me/notes.md"), "id", b"1234")?;
+        fs.rename(Path::new("/home/me/notes.md"), Path::new("/trash/1234"))?;
+
+        // The file only moved in the plan, along with its attributes
+        assert!(memory.exists(Path::new("/home/me/notes.md")));
+        assert!(!fs.exists(Path::new("/home/me/notes.md")));
+        assert_eq!(
+            fs.read_dir(Path::new("/trash"))?,
+            vec![PathBuf::from("/trash/1234")]
+        );
+        assert_eq!(fs.read(Path::new("/trash/1234"))?, b"notes");
+        let trashed = Path::new("/trash/1234");
+        assert_eq!(attrs.get_attr(trashed, "id")?, Some(b"1234".to_vec()));
+        assert_eq!(attrs.get_attr(trashed, "kept")?, Some(b"yes".to_vec()));
+        assert_eq!(memory.get_attr(Path::new("/home/me/notes.md"), "id")?, None);
+
+        fs.remove(trashed)?;
+        assert!(!fs.exists(trashed));
+        assert!(fs.remove(trashed).is_err());
+        assert_eq!(plan.reclaimed(), 5);
+        assert_eq!(plan.actions().len(), 3);
+        Ok(())
+    }
+}
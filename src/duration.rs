@@ -0,0 +1,201 @@
+use crate::{Error, Result};
+use chrono::{DateTime, Duration, Utc};
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+const WEEK: i64 = 7 * DAY;
+/// Months and years are calendar-independent approximations.
+const MONTH: i64 = 30 * DAY;
+const YEAR: i64 = 365 * DAY;
+
+/// Parses a human duration like `30m`, `12h`, `7d`, `2w`, `1mo` or `1y`, a combination
+/// like `1d12h`, or an ISO-8601 duration like `PT12H` or `P1M2D`. A bare number is a
+/// number of days, to stay compatible with the old day-based grace periods.
+pub fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+    let trimmed = value.trim();
+    let seconds = if let Ok(days) = trimmed.parse::<i64>() {
+        days.checked_mul(DAY)
+    } else if let Some(iso) = trimmed.strip_prefix(['P', 'p']) {
+        parse_iso8601(iso)
+    } else {
+        parse_human(trimmed)
+    };
+
+    match seconds {
+        Some(seconds) if seconds >= 0 => Duration::try_seconds(seconds),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        format!(
+            "'{}' is not a valid duration (e.g. 30m, 12h, 7d, 2w, 1mo or PT12H)",
+            value
+        )
+    })
+}
+
+/// Returns the date `duration` after `date`, failing if it is too far in the future to be
+/// represented.
+pub fn date_after(date: DateTime<Utc>, duration: Duration) -> Result<DateTime<Utc>> {
+    date.checked_add_signed(duration).ok_or_else(|| {
+        Error::InvalidDuration(format!(
+            "{} from {} is too far in the future",
+            format_duration(duration),
+            date.format("%Y-%m-%d")
+        ))
+    })
+}
+
+/// Formats a duration with the largest unit that represents it exactly, e.g. `2w` or `36h`.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds();
+    let units = [(WEEK, "w"), (DAY, "d"), (HOUR, "h"), (MINUTE, "m")];
+    for (unit, suffix) in units {
+        if seconds != 0 && seconds % unit == 0 {
+            return format!("{}{}", seconds / unit, suffix);
+        }
+    }
+    format!("{}s", seconds)
+}
+
+/// Parses a sequence of `<number><unit>` pairs, e.g. `1d12h`.
+fn parse_human(value: &str) -> Option<i64> {
+    if value.is_empty() {
+        return None;
+    }
+
+    let mut total: i64 = 0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let number: i64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = match &rest[..unit_len] {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => MINUTE,
+            "h" | "hr" | "hrs" | "hour" | "hours" => HOUR,
+            "d" | "day" | "days" => DAY,
+            "w" | "week" | "weeks" => WEEK,
+            "mo" | "month" | "months" => MONTH,
+            "y" | "year" | "years" => YEAR,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+        total = total.checked_add(number.checked_mul(unit)?)?;
+    }
+    Some(total)
+}
+
+/// Parses the part of an ISO-8601 duration after the leading `P`, e.g. `1M2DT12H`.
+fn parse_iso8601(value: &str) -> Option<i64> {
+    if value.is_empty() {
+        return None;
+    }
+
+    let (date, time) = match value.split_once(['T', 't']) {
+        Some((date, time)) if !time.is_empty() => (date, Some(time)),
+        Some(_) => return None,
+        None => (value, None),
+    };
+
+    let date_units = |unit: char| match unit.to_ascii_uppercase() {
+        'Y' => Some(YEAR),
+        'M' => Some(MONTH),
+        'W' => Some(WEEK),
+        'D' => Some(DAY),
+        _ => None,
+    };
+    let time_units = |unit: char| match unit.to_ascii_uppercase() {
+        'H' => Some(HOUR),
+        'M' => Some(MINUTE),
+        'S' => Some(1),
+        _ => None,
+    };
+
+    let mut total = parse_iso8601_part(date, date_units)?;
+    if let Some(time) = time {
+        total = total.checked_add(parse_iso8601_part(time, time_units)?)?;
+    }
+    Some(total)
+}
+
+fn parse_iso8601_part(value: &str, units: impl Fn(char) -> Option<i64>) -> Option<i64> {
+    let mut total: i64 = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let amount: i64 = number.parse().ok()?;
+        total = total.checked_add(amount.checked_mul(units(c)?)?)?;
+        number.clear();
+    }
+    // A trailing number without a unit is invalid
+    number.is_empty().then_some(total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_human_durations() {
+        assert_eq!(parse_duration("30m"), Ok(Duration::minutes(30)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::hours(12)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::weeks(2)));
+        assert_eq!(parse_duration("1mo"), Ok(Duration::days(30)));
+        assert_eq!(parse_duration("1d12h"), Ok(Duration::hours(36)));
+        // Bare numbers are days
+        assert_eq!(parse_duration("7"), Ok(Duration::days(7)));
+    }
+
+    #[test]
+    fn parse_iso8601_durations() {
+        assert_eq!(parse_duration("PT12H"), Ok(Duration::hours(12)));
+        assert_eq!(parse_duration("P1W"), Ok(Duration::weeks(1)));
+        assert_eq!(
+            parse_duration("P1M2DT30M"),
+            Ok(Duration::minutes(32 * 24 * 60 + 30))
+        );
+    }
+
+    #[test]
+    fn parse_invalid_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("12x").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("-1").is_err());
+        assert!(parse_duration("P").is_err());
+        assert!(parse_duration("PT").is_err());
+        assert!(parse_duration("P1").is_err());
+        // Too long to be represented
+        assert!(parse_duration("999999999999d").is_err());
+    }
+
+    #[test]
+    fn dates_too_far_in_the_future_are_errors() {
+        let now = Utc::now();
+        assert_eq!(
+            date_after(now, Duration::days(1)).ok(),
+            Some(now + Duration::days(1))
+        );
+        let far = parse_duration("10000000y").unwrap();
+        assert!(matches!(
+            date_after(now, far),
+            Err(Error::InvalidDuration(_))
+        ));
+    }
+
+    #[test]
+    fn format_durations() {
+        assert_eq!(format_duration(Duration::days(14)), "2w");
+        assert_eq!(format_duration(Duration::hours(36)), "36h");
+        assert_eq!(format_duration(Duration::seconds(90)), "90s");
+        assert_eq!(format_duration(Duration::zero()), "0s");
+    }
+}
@@ -1,8 +1,13 @@
+use crate::{shred::ShredWarning, trash::escape_os_str};
 use derive_more::{Display, From};
+use std::{io, path::PathBuf};
 
+/// Result type of every fallible operation of rrm.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Everything that can go wrong while trashing, restoring or cleaning items.
 #[derive(Debug, Display, From)]
+#[non_exhaustive]
 pub enum Error {
     #[display("Failed to load binary path executable: {}", _0)]
     InvalidBinaryPath(String),
@@ -10,6 +15,9 @@ pub enum Error {
     #[display("The attribute '{}' is missing from the item '{}'", attr, id)]
     MissingAttribute { attr: String, id: String },
 
+    #[display("The attribute '{}' of the item '{}' is invalid", attr, id)]
+    InvalidAttribute { attr: String, id: String },
+
     #[display("Invalid original path: '{}'", _0)]
     InvalidOriginalPath(String),
 
@@ -19,8 +27,247 @@ pub enum Error {
     #[display("Item {} not found in the trash", _0)]
     ItemNotFound(String),
 
+    #[display("'{}' is not a regular file", _0)]
+    NotAFile(String),
+
+    #[display("Invalid duration: {}", _0)]
+    InvalidDuration(String),
+
+    #[display(
+        "Invalid {} '{}', expected a date like 2024-01-31T12:00:00Z",
+        crate::clock::NOW_VAR,
+        _0
+    )]
+    InvalidNow(String),
+
+    #[display("Invalid pattern: {}", _0)]
+    InvalidPattern(String),
+
+    #[display(
+        "Item {} does not match the checksums recorded when it was trashed ({}), use --no-verify to restore it anyway",
+        id,
+        problems
+    )]
+    Corrupted { id: String, problems: String },
+
+    #[display("{} item(s) failed verification", _0)]
+    VerificationFailed(usize),
+
+    #[display("{} issue(s) found in the trash", _0)]
+    IssuesFound(usize),
+
+    #[display("Encryption error: {}", _0)]
+    Encryption(String),
+
+    #[display("Invalid configuration value: {}", _0)]
+    InvalidConfig(String),
+
+    #[display("The {} command cannot be run with --dry-run", _0)]
+    DryRunUnsupported(String),
+
+    #[display("The {} hook '{}' failed with {}", event, hook, status)]
+    HookFailed {
+        event: String,
+        hook: String,
+        status: String,
+    },
+
+    /// A directory was copied to another filesystem but only partly removed afterwards,
+    /// leaving the copy as the only complete one.
+    #[display(
+        "Copied '{}' to '{}' but could not remove all of it: {}",
+        from,
+        to,
+        source
+    )]
+    IncompleteMove {
+        from: String,
+        to: String,
+        source: io::Error,
+    },
+
+    /// Some of the items an operation was given failed, the others went through.
+    #[display("{}", format_failures(failures, *total))]
+    ItemsFailed {
+        failures: Vec<ItemFailure>,
+        total: usize,
+    },
+
     #[from]
     XAttr(crate::xattr::XAttrError),
     #[from]
     Io(std::io::Error),
+    #[from]
+    Watch(notify::Error),
+}
+
+/// The class of an [`Error`], which decides the exit code of the CLI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An item or path that does not exist.
+    NotFound,
+    /// Something already exists where an item would be moved.
+    Conflict,
+    /// The user is not allowed to read, move or delete something.
+    Permission,
+    /// Some of the items given to an operation failed.
+    PartialFailure,
+    /// An invalid configuration value, duration, pattern or environment variable.
+    InvalidConfig,
+    /// The filesystem does not support what the trash needs, like extended attributes.
+    UnsupportedFileSystem,
+    /// A pre-hook refused the operation.
+    HookFailed,
+    /// A check of the trash found issues that were not repaired.
+    IssuesFound,
+    /// Anything else.
+    Other,
+}
+
+impl ErrorKind {
+    /// Returns the exit code of the CLI for errors of this kind. 2 is left to invalid
+    /// command line arguments.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::NotFound => 3,
+            ErrorKind::Conflict => 4,
+            ErrorKind::Permission => 5,
+            ErrorKind::PartialFailure => 6,
+            ErrorKind::InvalidConfig => 7,
+            ErrorKind::UnsupportedFileSystem => 8,
+            ErrorKind::HookFailed => 9,
+            ErrorKind::IssuesFound => 10,
+        }
+    }
+
+    /// Returns the name of the kind, as printed in JSON errors.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::Conflict => "conflict",
+            ErrorKind::Permission => "permission",
+            ErrorKind::PartialFailure => "partial_failure",
+            ErrorKind::InvalidConfig => "invalid_config",
+            ErrorKind::UnsupportedFileSystem => "unsupported_filesystem",
+            ErrorKind::HookFailed => "hook_failed",
+            ErrorKind::IssuesFound => "issues_found",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    fn of_io(error: &io::Error) -> ErrorKind {
+        if error.raw_os_error() == Some(libc::ENOTSUP) {
+            return ErrorKind::UnsupportedFileSystem;
+        }
+        match error.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::AlreadyExists | io::ErrorKind::DirectoryNotEmpty => ErrorKind::Conflict,
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+                ErrorKind::Permission
+            }
+            io::ErrorKind::Unsupported => ErrorKind::UnsupportedFileSystem,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Something that went wrong without failing the operation it happened in, passed to the
+/// handler set with [`crate::TrashManager::with_warning_handler`].
+#[derive(Debug, Display)]
+#[non_exhaustive]
+pub enum Warning {
+    /// The operation happened but could not be written to the audit log.
+    #[display("Failed to write to the audit log: {}", _0)]
+    AuditLog(Error),
+
+    /// A post-hook failed after its operation happened.
+    #[display("{}", _0)]
+    PostHook(Error),
+
+    /// A file of an item was trashed without being encrypted.
+    #[display(
+        "{}: not encrypted, the file has other hard links sharing its contents",
+        escape_os_str(_0.as_os_str())
+    )]
+    NotEncrypted(PathBuf),
+
+    /// An item was trashed but could not be deduplicated or compressed, it is stored as
+    /// it was.
+    #[display("{}: stored as it was: {}", escape_os_str(_0.as_os_str()), _1)]
+    StoredAsIs(PathBuf, Error),
+
+    /// A file was deleted but may still be recoverable.
+    #[display("{}", _0)]
+    Shred(ShredWarning),
+}
+
+/// The failure of one of the items given to an operation, named by its path or id.
+#[derive(Debug, Display)]
+#[display("{}: {}", item, error)]
+pub struct ItemFailure {
+    pub item: String,
+    pub error: Error,
+}
+
+impl Error {
+    /// Returns the class of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::ItemNotFound(_) => ErrorKind::NotFound,
+            Error::PathAlreadyExists(_) => ErrorKind::Conflict,
+            Error::InvalidBinaryPath(_)
+            | Error::InvalidDuration(_)
+            | Error::InvalidNow(_)
+            | Error::InvalidPattern(_)
+            | Error::InvalidConfig(_) => ErrorKind::InvalidConfig,
+            Error::VerificationFailed(_) => ErrorKind::PartialFailure,
+            Error::IssuesFound(_) => ErrorKind::IssuesFound,
+            Error::HookFailed { .. } => ErrorKind::HookFailed,
+            // A single item keeps the kind of its failure, as if it had been run alone
+            Error::ItemsFailed { failures, total } => match failures.as_slice() {
+                [failure] if *total == 1 => failure.error.kind(),
+                _ => ErrorKind::PartialFailure,
+            },
+            Error::XAttr(crate::xattr::XAttrError::UnsupportedPlatform) => {
+                ErrorKind::UnsupportedFileSystem
+            }
+            Error::XAttr(
+                crate::xattr::XAttrError::SetAttr { source, .. }
+                | crate::xattr::XAttrError::GetAttr { source, .. }
+                | crate::xattr::XAttrError::RemoveAttr { source, .. },
+            ) => ErrorKind::of_io(source),
+            Error::Io(e) | Error::IncompleteMove { source: e, .. } => ErrorKind::of_io(e),
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Combines the failures of an operation on `total` items: `Ok` if there are none,
+    /// or else [`Error::ItemsFailed`].
+    pub fn from_failures(failures: Vec<ItemFailure>, total: usize) -> Result<()> {
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::ItemsFailed { failures, total })
+        }
+    }
+}
+
+fn format_failures(failures: &[ItemFailure], total: usize) -> String {
+    match failures {
+        [failure] if total == 1 => failure.to_string(),
+        _ => format!("{} of {} items failed", failures.len(), total),
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::XAttr(e) => Some(e),
+            Error::Io(e) | Error::IncompleteMove { source: e, .. } => Some(e),
+            Error::Watch(e) => Some(e),
+            _ => None,
+        }
+    }
 }
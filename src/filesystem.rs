@@ -0,0 +1,194 @@
+use crate::{parallel::Workers, xattr::XATTR_NAMESPACE};
+use std::{
+    fs,
+    io::{self, Read},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+#[cfg(any(test, feature = "testing"))]
+mod memory;
+#[cfg(any(test, feature = "testing"))]
+pub use memory::{MemoryFileSystem, Operation};
+
+/// What a path points to. Symlinks are never followed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// The metadata of a path, as returned by [`FileSystem::metadata`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metadata {
+    pub kind: FileKind,
+    /// Size in bytes of a file's contents, or of a symlink's target path.
+    pub len: u64,
+}
+
+/// A lock on a directory, released when dropped.
+#[derive(Debug, Default)]
+pub struct Lock {
+    _file: Option<fs::File>,
+}
+
+impl Lock {
+    /// Wraps a file holding a `flock`, which is released when the file is closed.
+    pub fn new(file: fs::File) -> Self {
+        Self { _file: Some(file) }
+    }
+}
+
+/// The filesystem operations [`crate::TrashManager`] uses to move items in and out of the
+/// trash, list them and delete them.
+///
+/// [`StdFileSystem`] is the real filesystem and the default; tests use an in-memory one,
+/// available with the `testing` feature, to simulate failures.
+///
+/// Compression, encryption, deduplication and shredding are the exception: they rewrite
+/// files in place, hard-link them into the blob store and restore their permissions and
+/// modification times, which this trait does not cover, so they always work on the real
+/// filesystem. A manager given another filesystem with [`crate::TrashManager::with_file_system`]
+/// refuses to change the trash while one of their policies is enabled.
+pub trait FileSystem: Send + Sync {
+    /// Renames `from` to `to`, failing with [`io::ErrorKind::CrossesDevices`] if they are
+    /// on different filesystems.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Removes a file, symlink or directory tree, without following symlinks.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Returns the metadata of `path`, without following symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Returns the paths of the entries of a directory, sorted by name.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Copies a file, symlink or directory tree to `to`, which must not exist, keeping
+    /// permissions, modification times and extended attributes. Symlinks are copied as
+    /// links.
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Opens a file for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>>;
+
+    /// Creates or replaces a file with `contents`.
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Creates a directory and any missing parent.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Returns the absolute form of an existing path.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Locks a directory, shared for reading or exclusively for changing it.
+    fn lock(&self, dir: &Path, exclusive: bool) -> io::Result<Lock>;
+
+    /// Reads the whole contents of a file.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.open(path)?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+
+    /// Checks whether a path exists, counting dangling symlinks.
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+}
+
+/// The real filesystem, through `std::fs`. Directory trees are removed and copied by a
+/// pool of [`Workers`].
+#[derive(Debug, Clone, Default)]
+pub struct StdFileSystem {
+    workers: Workers,
+}
+
+impl StdFileSystem {
+    /// Creates the real filesystem, removing and copying trees with `workers`.
+    pub fn new(workers: Workers) -> Self {
+        Self { workers }
+    }
+}
+
+impl FileSystem for StdFileSystem {
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.workers.remove(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let meta = path.symlink_metadata()?;
+        let kind = if meta.file_type().is_symlink() {
+            FileKind::Symlink
+        } else if meta.is_dir() {
+            FileKind::Directory
+        } else {
+            FileKind::File
+        };
+        Ok(Metadata {
+            kind,
+            len: meta.len(),
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.workers.copy(from, to)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn lock(&self, dir: &Path, exclusive: bool) -> io::Result<Lock> {
+        let file = fs::File::open(dir)?;
+        if exclusive {
+            file.lock()?;
+        } else {
+            file.lock_shared()?;
+        }
+        Ok(Lock::new(file))
+    }
+}
+
+/// Gives the copy `to` of a file or directory the user extended attributes, permissions
+/// and modification time of `from`, whose metadata is `meta`. Directories must be
+/// complete, in case they do not allow writing.
+pub(crate) fn copy_attributes(from: &Path, to: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    for name in xattr::list(from)? {
+        if !name.as_bytes().starts_with(XATTR_NAMESPACE.as_bytes()) {
+            // System attributes, e.g. SELinux labels, belong to the destination
+            continue;
+        }
+        if let Some(value) = xattr::get(from, &name)? {
+            xattr::set(to, &name, &value)?;
+        }
+    }
+    fs::set_permissions(to, meta.permissions())?;
+    fs::File::open(to)?.set_modified(meta.modified()?)
+}
@@ -0,0 +1,415 @@
+use super::{FileKind, FileSystem, Lock, Metadata};
+use crate::{
+    xattr::{ExtendedAttributes, XAttrError},
+    Result,
+};
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor, Read},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A filesystem operation, to make it fail with [`MemoryFileSystem::fail`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Operation {
+    Rename,
+    Remove,
+    Metadata,
+    ReadDir,
+    Copy,
+    Open,
+    Write,
+    CreateDir,
+}
+
+/// A filesystem kept in memory, along with the extended attributes of its paths, for tests.
+///
+/// Clones share the same contents, so one clone can be given to a
+/// [`crate::TrashManager`] as its filesystem and another as its attribute storage. Paths
+/// must be absolute and are not resolved through symlinks. Besides failing operations on
+/// chosen paths with [`Self::fail`], separate devices can be mounted with [`Self::mount`]:
+/// renames between devices fail like they do on a real system, and a device with a
+/// capacity runs out of space once its files hold that many bytes.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    nodes: BTreeMap<PathBuf, Node>,
+    /// Mount points and their capacity in bytes, if limited.
+    devices: BTreeMap<PathBuf, Option<u64>>,
+    failures: Vec<(Operation, PathBuf, io::ErrorKind)>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    content: Content,
+    xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    File(Vec<u8>),
+    Directory,
+    Symlink(PathBuf),
+}
+
+impl MemoryFileSystem {
+    /// Creates an empty filesystem, with only the root directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a file with `contents`, along with its missing parent directories.
+    pub fn add_file(&self, path: impl AsRef<Path>, contents: impl Into<Vec<u8>>) {
+        let path = path.as_ref();
+        self.add_parents(path);
+        self.state().insert(path, Content::File(contents.into()));
+    }
+
+    /// Creates a directory, along with its missing parent directories.
+    pub fn add_dir(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        self.add_parents(path);
+        self.state().insert(path, Content::Directory);
+    }
+
+    /// Creates a symlink pointing to `target`, along with its missing parent directories.
+    pub fn add_symlink(&self, path: impl AsRef<Path>, target: impl Into<PathBuf>) {
+        let path = path.as_ref();
+        self.add_parents(path);
+        self.state().insert(path, Content::Symlink(target.into()));
+    }
+
+    /// Makes `path` and everything below it a separate device, holding at most `capacity`
+    /// bytes of file contents if given.
+    pub fn mount(&self, path: impl Into<PathBuf>, capacity: Option<u64>) {
+        self.state().devices.insert(path.into(), capacity);
+    }
+
+    /// Makes every `operation` on `path` or below it fail with `kind`, e.g.
+    /// [`io::ErrorKind::PermissionDenied`].
+    pub fn fail(&self, operation: Operation, path: impl Into<PathBuf>, kind: io::ErrorKind) {
+        self.state().failures.push((operation, path.into(), kind));
+    }
+
+    fn add_parents(&self, path: &Path) {
+        let mut state = self.state();
+        for ancestor in path.ancestors().skip(1) {
+            if ancestor.parent().is_some() && !state.nodes.contains_key(ancestor) {
+                state.insert(ancestor, Content::Directory);
+            }
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // A test that panicked while holding the lock already failed
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl State {
+    fn insert(&mut self, path: &Path, content: Content) {
+        let xattrs = self
+            .nodes
+            .remove(path)
+            .map(|node| node.xattrs)
+            .unwrap_or_default();
+        self.nodes
+            .insert(path.to_path_buf(), Node { content, xattrs });
+    }
+
+    fn check(&self, operation: Operation, paths: &[&Path]) -> io::Result<()> {
+        for (failing, prefix, kind) in &self.failures {
+            if *failing == operation && paths.iter().any(|path| path.starts_with(prefix)) {
+                return Err(io::Error::from(*kind));
+            }
+        }
+        for path in paths {
+            if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: paths must be absolute and normalized", path.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn node(&self, path: &Path) -> io::Result<&Node> {
+        self.nodes
+            .get(path)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.parent().is_none()
+            || matches!(
+                self.nodes.get(path),
+                Some(Node {
+                    content: Content::Directory,
+                    ..
+                })
+            )
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.parent().is_none() || self.nodes.contains_key(path)
+    }
+
+    /// Fails unless the parent of `path` is an existing directory and `path` does not exist.
+    fn check_new(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if self.is_dir(parent) => {}
+            Some(parent) if self.exists(parent) => return Err(io::ErrorKind::NotADirectory.into()),
+            _ => return Err(io::ErrorKind::NotFound.into()),
+        }
+        if self.exists(path) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        Ok(())
+    }
+
+    /// Returns `path` and every path below it, parents first.
+    fn tree(&self, path: &Path) -> Vec<PathBuf> {
+        self.nodes
+            .keys()
+            .filter(|key| key.starts_with(path))
+            .cloned()
+            .collect()
+    }
+
+    fn device(&self, path: &Path) -> Option<&PathBuf> {
+        self.devices
+            .keys()
+            .filter(|mount| path.starts_with(mount))
+            .max_by_key(|mount| mount.components().count())
+    }
+
+    /// Fails with [`io::ErrorKind::StorageFull`] if `added` more bytes do not fit on the
+    /// device of `path`.
+    fn reserve(&self, path: &Path, added: u64) -> io::Result<()> {
+        let Some(mount) = self.device(path) else {
+            return Ok(());
+        };
+        let Some(capacity) = self.devices[mount] else {
+            return Ok(());
+        };
+        let used: u64 = self
+            .nodes
+            .iter()
+            .filter(|(key, _)| self.device(key) == Some(mount))
+            .map(|(_, node)| match &node.content {
+                Content::File(contents) => contents.len() as u64,
+                _ => 0,
+            })
+            .sum();
+        if used + added > capacity {
+            return Err(io::ErrorKind::StorageFull.into());
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.check(Operation::Rename, &[from, to])?;
+        state.node(from)?;
+        if state.device(from) != state.device(to) {
+            return Err(io::ErrorKind::CrossesDevices.into());
+        }
+        if to.starts_with(from) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        if state.exists(to) {
+            if state.tree(to).len() > 1 {
+                return Err(io::ErrorKind::DirectoryNotEmpty.into());
+            }
+            state.nodes.remove(to);
+        }
+        state.check_new(to)?;
+
+        for path in state.tree(from) {
+            if let Some(node) = state.nodes.remove(&path) {
+                let relative = path.strip_prefix(from).unwrap_or(Path::new(""));
+                state.nodes.insert(to.join(relative), node);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.check(Operation::Remove, &[path])?;
+        state.node(path)?;
+        for path in state.tree(path) {
+            state.nodes.remove(&path);
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let state = self.state();
+        state.check(Operation::Metadata, &[path])?;
+        if path.parent().is_none() {
+            return Ok(Metadata {
+                kind: FileKind::Directory,
+                len: 0,
+            });
+        }
+        Ok(match &state.node(path)?.content {
+            Content::File(contents) => Metadata {
+                kind: FileKind::File,
+                len: contents.len() as u64,
+            },
+            Content::Directory => Metadata {
+                kind: FileKind::Directory,
+                len: 0,
+            },
+            Content::Symlink(target) => Metadata {
+                kind: FileKind::Symlink,
+                len: target.as_os_str().len() as u64,
+            },
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.state();
+        state.check(Operation::ReadDir, &[path])?;
+        if !state.is_dir(path) {
+            return match state.exists(path) {
+                true => Err(io::ErrorKind::NotADirectory.into()),
+                false => Err(io::ErrorKind::NotFound.into()),
+            };
+        }
+        Ok(state
+            .nodes
+            .keys()
+            .filter(|key| key.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.check(Operation::Copy, &[from, to])?;
+        state.node(from)?;
+        state.check_new(to)?;
+        // Entries are copied one by one, so running out of space leaves a partial copy
+        for path in state.tree(from) {
+            let node = state.nodes[&path].clone();
+            let relative = path.strip_prefix(from).unwrap_or(Path::new(""));
+            let target = to.join(relative);
+            if let Content::File(contents) = &node.content {
+                state.reserve(&target, contents.len() as u64)?;
+            }
+            state.nodes.insert(target, node);
+        }
+        Ok(())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        let state = self.state();
+        state.check(Operation::Open, &[path])?;
+        match &state.node(path)?.content {
+            Content::File(contents) => Ok(Box::new(Cursor::new(contents.clone()))),
+            Content::Directory => Err(io::ErrorKind::IsADirectory.into()),
+            Content::Symlink(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "symlinks are not followed",
+            )),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut state = self.state();
+        state.check(Operation::Write, &[path])?;
+        match state.nodes.get(path).map(|node| &node.content) {
+            Some(Content::File(old)) => {
+                let old = old.len() as u64;
+                state.reserve(path, (contents.len() as u64).saturating_sub(old))?;
+            }
+            Some(_) => return Err(io::ErrorKind::IsADirectory.into()),
+            None => {
+                state.check_new(path)?;
+                state.reserve(path, contents.len() as u64)?;
+            }
+        }
+        state.insert(path, Content::File(contents.to_vec()));
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        state.check(Operation::CreateDir, &[path])?;
+        let mut ancestors: Vec<&Path> = path.ancestors().collect();
+        ancestors.reverse();
+        for ancestor in ancestors {
+            if state.is_dir(ancestor) {
+                continue;
+            }
+            if state.exists(ancestor) {
+                return Err(io::ErrorKind::NotADirectory.into());
+            }
+            state.insert(ancestor, Content::Directory);
+        }
+        Ok(())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let state = self.state();
+        state.check(Operation::Metadata, &[path])?;
+        if !state.exists(path) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(path.to_path_buf())
+    }
+
+    fn lock(&self, _dir: &Path, _exclusive: bool) -> io::Result<Lock> {
+        // Everything happens under the state mutex, there is no other process to exclude
+        Ok(Lock::default())
+    }
+}
+
+impl ExtendedAttributes for MemoryFileSystem {
+    fn set_attr(&self, path: &Path, key: &str, value: &[u8]) -> Result<()> {
+        let mut state = self.state();
+        let node = state
+            .nodes
+            .get_mut(path)
+            .ok_or_else(|| XAttrError::SetAttr {
+                attr: key.to_string(),
+                path: path.to_path_buf(),
+                source: io::ErrorKind::NotFound.into(),
+            })?;
+        node.xattrs.insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn get_attr(&self, path: &Path, key: &str) -> Result<Option<Vec<u8>>> {
+        let state = self.state();
+        let node = state.nodes.get(path).ok_or_else(|| XAttrError::GetAttr {
+            attr: key.to_string(),
+            path: path.to_path_buf(),
+            source: io::ErrorKind::NotFound.into(),
+        })?;
+        Ok(node.xattrs.get(key).cloned())
+    }
+
+    fn remove_attr(&self, path: &Path, key: &str) -> Result<()> {
+        let mut state = self.state();
+        let error = || XAttrError::RemoveAttr {
+            attr: key.to_string(),
+            path: path.to_path_buf(),
+            source: io::ErrorKind::NotFound.into(),
+        };
+        let node = state.nodes.get_mut(path).ok_or_else(error)?;
+        node.xattrs.remove(key).ok_or_else(error)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{trash::escape_os_str, Error, Result};
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use log::{info, warn};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// When a hook runs. Pre-hooks run before the operation and can refuse it by exiting
+/// with a non-zero status, post-hooks run once it succeeded.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum HookEvent {
+    #[display("pre-trash")]
+    PreTrash,
+    #[display("post-trash")]
+    PostTrash,
+    #[display("pre-restore")]
+    PreRestore,
+    #[display("post-restore")]
+    PostRestore,
+    #[display("pre-purge")]
+    PrePurge,
+    #[display("post-purge")]
+    PostPurge,
+}
+
+impl HookEvent {
+    /// Every event, in the order of the operations.
+    pub const ALL: [HookEvent; 6] = [
+        HookEvent::PreTrash,
+        HookEvent::PostTrash,
+        HookEvent::PreRestore,
+        HookEvent::PostRestore,
+        HookEvent::PrePurge,
+        HookEvent::PostPurge,
+    ];
+}
+
+/// What a hook is told about the item of the operation.
+#[derive(Debug, Clone)]
+pub struct HookContext {
+    /// Id of the item in the trash.
+    pub id: String,
+    /// Where the item is trashed from, restored to or was trashed from when purged.
+    pub path: PathBuf,
+    /// Where the item is stored in the trash.
+    pub trash_path: PathBuf,
+    /// Size of the item in bytes, if known.
+    pub size: Option<u64>,
+    /// When the item is, or was, due for deletion.
+    pub deletion_date: Option<DateTime<Utc>>,
+}
+
+/// Commands run before and after items are trashed, restored and purged.
+///
+/// A hook is either a shell command set in the configuration, or an executable named
+/// after its event (e.g. `pre-purge`) in the hooks directory. When both exist, the command
+/// runs first. Hooks get the item as `RRM_*` environment variables and as a JSON object on
+/// stdin.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    commands: BTreeMap<HookEvent, String>,
+    dir: Option<PathBuf>,
+}
+
+impl Hooks {
+    /// Sets the shell command run for `event`.
+    pub fn with_command(mut self, event: HookEvent, command: impl Into<String>) -> Self {
+        self.commands.insert(event, command.into());
+        self
+    }
+
+    /// Sets the directory where executables named after an event are looked up.
+    pub fn with_dir(self, dir: PathBuf) -> Self {
+        Self {
+            dir: Some(dir),
+            ..self
+        }
+    }
+
+    /// Returns the shell command run for `event`, if one is set.
+    pub fn command(&self, event: HookEvent) -> Option<&str> {
+        self.commands.get(&event).map(String::as_str)
+    }
+
+    /// Returns `$XDG_CONFIG_HOME/rrm/hooks`, or `~/.config/rrm/hooks` if it is not set.
+    pub fn default_dir() -> Option<PathBuf> {
+        let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                PathBuf::from(env::var_os("HOME").filter(|home| !home.is_empty())?).join(".config")
+            }
+        };
+        Some(config_dir.join("rrm").join("hooks"))
+    }
+
+    /// Runs the hooks of `event`, failing with [`Error::HookFailed`] as soon as one exits
+    /// with a non-zero status.
+    pub fn run(&self, event: HookEvent, context: &HookContext) -> Result<()> {
+        let mut hooks = Vec::new();
+        if let Some(command) = self.command(event) {
+            let mut shell = Command::new("sh");
+            shell.arg("-c").arg(command);
+            hooks.push((command.to_string(), shell));
+        }
+        if let Some(executable) = self.executable(event) {
+            hooks.push((
+                escape_os_str(executable.as_os_str()),
+                Command::new(executable),
+            ));
+        }
+
+        for (hook, mut command) in hooks {
+            info!("Running {} hook: {}", event, hook);
+            let mut child = command
+                .envs(env_vars(event, context))
+                .stdin(Stdio::piped())
+                .spawn()?;
+            let input = to_json(event, context).to_string();
+            if let Some(mut stdin) = child.stdin.take() {
+                // Hooks that do not read their input close it early, that is fine
+                match stdin.write_all(input.as_bytes()) {
+                    Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(Error::HookFailed {
+                    event: event.to_string(),
+                    hook,
+                    status: status.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the executable for `event` in the hooks directory, if there is one.
+    fn executable(&self, event: HookEvent) -> Option<PathBuf> {
+        let path = self.dir.as_ref()?.join(event.to_string());
+        let meta = path.metadata().ok()?;
+        if !meta.is_file() {
+            return None;
+        }
+        if meta.permissions().mode() & 0o111 == 0 {
+            warn!(
+                "Skipping hook {}, it is not executable",
+                escape_os_str(path.as_os_str())
+            );
+            return None;
+        }
+        Some(path)
+    }
+}
+
+/// Returns the environment variables describing the operation to a hook. Paths are
+/// passed as is, even if they are not valid UTF-8.
+fn env_vars(event: HookEvent, context: &HookContext) -> Vec<(&'static str, OsString)> {
+    let mut vars = vec![
+        ("RRM_HOOK", event.to_string().into()),
+        ("RRM_ID", context.id.as_str().into()),
+        ("RRM_PATH", context.path.as_os_str().to_owned()),
+        ("RRM_TRASH_PATH", context.trash_path.as_os_str().to_owned()),
+    ];
+    if let Some(size) = context.size {
+        vars.push(("RRM_SIZE", size.to_string().into()));
+    }
+    if let Some(deletion_date) = context.deletion_date {
+        vars.push(("RRM_DELETION_DATE", deletion_date.to_rfc3339().into()));
+    }
+    vars
+}
+
+/// Describes the operation to a hook as JSON, with paths escaped like everywhere else.
+fn to_json(event: HookEvent, context: &HookContext) -> serde_json::Value {
+    json!({
+        "event": event.to_string(),
+        "id": context.id,
+        "path": escape_os_str(context.path.as_os_str()),
+        "trash_path": escape_os_str(context.trash_path.as_os_str()),
+        "size": context.size,
+        "deletion_date": context.deletion_date,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{fs, path::Path};
+    use tempfile::tempdir;
+
+    fn context(path: &Path, trash_path: &Path) -> HookContext {
+        HookContext {
+            id: "1234".to_string(),
+            path: path.to_path_buf(),
+            trash_path: trash_path.to_path_buf(),
+            size: Some(42),
+            deletion_date: None,
+        }
+    }
+
+    #[test]
+    fn hooks_get_the_item_in_env_vars_and_on_stdin() -> Result<()> {
+        let dir = tempdir()?;
+        let output = dir.path().join("output");
+        let executable = dir.path().join("post-purge");
+        fs::write(
+            &executable,
+            "#!/bin/sh\ncat >> \"$(dirname \"$0\")/output\"\n",
+        )?;
+        fs::set_permissions(&executable, fs::Permissions::from_mode(0o755))?;
+        let hooks = Hooks::default()
+            .with_command(
+                HookEvent::PostPurge,
+                format!(
+                    "echo \"$RRM_HOOK $RRM_ID $RRM_SIZE\" > {}",
+                    output.display()
+                ),
+            )
+            .with_dir(dir.path().to_path_buf());
+
+        let path = Path::new("/home/me/notes.md");
+        hooks.run(
+            HookEvent::PostPurge,
+            &context(path, Path::new("/trash/1234")),
+        )?;
+
+        // The command runs first, then the executable appends the JSON it was given
+        let contents = fs::read_to_string(&output)?;
+        let (vars, input) = contents.split_once('\n').unwrap();
+        assert_eq!(vars, "post-purge 1234 42");
+        let input: serde_json::Value = serde_json::from_str(input).unwrap();
+        assert_eq!(input["path"], "/home/me/notes.md");
+        assert_eq!(input["trash_path"], "/trash/1234");
+        assert_eq!(input["deletion_date"], serde_json::Value::Null);
+
+        // Events without hooks do nothing
+        hooks.run(
+            HookEvent::PrePurge,
+            &context(path, Path::new("/trash/1234")),
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn failing_hooks_are_an_error() {
+        let hooks = Hooks::default().with_command(HookEvent::PreRestore, "exit 3");
+        let path = Path::new("/home/me/notes.md");
+        let result = hooks.run(HookEvent::PreRestore, &context(path, path));
+        assert!(matches!(
+            result,
+            Err(Error::HookFailed { event, hook, .. }) if event == "pre-restore" && hook == "exit 3"
+        ));
+    }
+}
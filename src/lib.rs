@@ -0,0 +1,64 @@
+//! Move files and directories to a trash with a grace period instead of deleting them
+//! right away, and restore them until the grace period is over.
+//!
+//! This crate is both the `rrm` command line tool and a library for programs that need to
+//! trash, list, restore or clean items without shelling out. The library shares the trash
+//! and the configuration with the CLI, so items trashed through one are visible to the
+//! other.
+//!
+//! The types re-exported at the root are the stable API: [`TrashManager`] and the
+//! [`TrashBackend`] trait it implements, [`TrashItem`],
+//! [`Config`], [`ExtendedAttributes`] with its default implementation [`XAttrManager`],
+//! [`FileSystem`] and [`Error`], along with the shred, encryption and compression
+//! policies a manager is built with. The modules expose the supporting types of the
+//! other builders, such as the [`audit`] log, the [`hooks`] and the [`dry_run`] plan.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use rrm::{Config, TrashManager, XAttrManager};
+//!
+//! # fn main() -> rrm::Result<()> {
+//! let xattr_manager = XAttrManager::new()?;
+//! let config = Config::load(xattr_manager)?;
+//! let trash = TrashManager::new(config.trash_dir.clone(), xattr_manager)
+//!     .with_shred_policy(config.shred_policy.clone());
+//!
+//! let deletion_date = chrono::Utc::now() + config.grace_period;
+//! trash.trash_items(vec!["build.log".into()], deletion_date)?;
+//!
+//! for item in trash.list_items()? {
+//!     println!("{} {}", item.id(), item.format_original_path());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod audit;
+pub mod clock;
+mod compress;
+pub mod config;
+mod crypto;
+pub mod dry_run;
+// Parsing and formatting shared with the CLI, not part of the API
+#[doc(hidden)]
+pub mod duration;
+mod error;
+pub mod filesystem;
+pub mod hooks;
+pub mod parallel;
+#[doc(hidden)]
+pub mod patterns;
+pub mod progress;
+mod shred;
+pub mod trash;
+pub mod xattr;
+
+pub use compress::{Compression, CompressionPolicy};
+pub use config::Config;
+pub use crypto::{EncryptionPolicy, KeySource};
+pub use error::{Error, ErrorKind, ItemFailure, Result, Warning};
+pub use filesystem::FileSystem;
+pub use shred::{ShredPolicy, ShredWarning};
+pub use trash::{TrashBackend, TrashItem, TrashManager};
+pub use xattr::{ExtendedAttributes, XAttrError, XAttrManager};
@@ -1,19 +1,35 @@
 mod commands;
-mod config;
-mod error;
-mod trash;
-mod xattr;
 
-use clap::{ArgAction, Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use commands::{
+    browse::{handle_browse, BrowseArgs},
+    cat::{handle_cat, CatArgs},
     clean::{handle_clean, CleanArgs},
     config::{handle_config, ConfigArgs},
+    daemon::handle_daemon,
+    diff::{handle_diff, DiffArgs},
+    expire::{handle_expire, ExpireArgs},
+    fsck::{handle_fsck, FsckArgs},
+    grep::{handle_grep, GrepArgs},
+    install_timer::{handle_install_timer, InstallTimerArgs},
+    keep::{handle_keep, KeepArgs},
     list::{handle_list, ListArgs},
+    log::{handle_log, LogArgs},
     restore::{handle_restore, RestoreArgs},
     rm::{handle_rm, RmArgs},
+    stats::{handle_stats, StatsArgs},
+    verify::{handle_verify, VerifyArgs},
 };
-pub use error::{Error, Result};
-use xattr::XAttrManager;
+use rrm::{
+    audit::AuditLog,
+    clock,
+    dry_run::{DryRunAttributes, Plan},
+    parallel::Workers,
+    progress::Progress,
+    trash::format_size,
+    Config, Error, ExtendedAttributes, Result, TrashManager, XAttrManager,
+};
+use serde_json::json;
 
 #[derive(Subcommand)]
 enum Commands {
@@ -26,11 +42,51 @@ enum Commands {
     #[clap(about = "List files and directories in the trash")]
     List(ListArgs),
 
+    #[clap(about = "Keep a trashed item for longer, or pin it so it is never cleaned")]
+    Keep(KeepArgs),
+
+    #[clap(about = "Mark a trashed item for deletion on the next clean")]
+    Expire(ExpireArgs),
+
+    #[clap(about = "Print the contents of a trashed file")]
+    Cat(CatArgs),
+
+    #[clap(
+        about = "Compare a trashed file with its original path, a file or another trashed file"
+    )]
+    Diff(DiffArgs),
+
+    #[clap(about = "Search the contents of trashed files")]
+    Grep(GrepArgs),
+
+    #[clap(about = "Browse the trash interactively")]
+    Browse(BrowseArgs),
+
+    #[clap(about = "Show how much space the trash takes up and what is in it")]
+    Stats(StatsArgs),
+
     #[clap(about = "Clean files and directories that have passed the grace period")]
     Clean(CleanArgs),
 
+    #[clap(about = "Show the audit log of trashed, restored and purged items")]
+    Log(LogArgs),
+
     #[clap(about = "Show or edit the configuration")]
     Config(ConfigArgs),
+
+    #[clap(about = "Check trashed items against the checksums recorded when they were trashed")]
+    Verify(VerifyArgs),
+
+    #[clap(about = "Check the trash for inconsistent entries and optionally repair them")]
+    Fsck(FsckArgs),
+
+    #[clap(about = "Run in the foreground, cleaning items as soon as their grace period is over")]
+    Daemon,
+
+    #[clap(
+        about = "Install a systemd user timer or crontab entry that cleans the trash periodically"
+    )]
+    InstallTimer(InstallTimerArgs),
 }
 
 #[derive(Parser)]
@@ -44,18 +100,97 @@ struct App {
         help = "Increase verbosity level (use multiple times for more verbosity)"
     )]
     verbose: u8,
+    #[clap(
+        long,
+        global = true,
+        help = "Print errors on stderr as a JSON object, for scripts"
+    )]
+    json_errors: bool,
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "human",
+        help = "Show what would change instead of changing it, as JSON with --dry-run=json"
+    )]
+    dry_run: Option<PlanFormat>,
+    #[clap(
+        long,
+        short,
+        global = true,
+        help = "Do not show progress bars, for scripts"
+    )]
+    quiet: bool,
+    #[clap(
+        long,
+        short,
+        global = true,
+        value_name = "N",
+        help = "Number of threads deleting and copying files [default: one per CPU]"
+    )]
+    jobs: Option<usize>,
+}
+
+/// How the plan of a dry run is printed.
+#[derive(Clone, Copy, ValueEnum)]
+enum PlanFormat {
+    Human,
+    Json,
 }
 
 fn main() {
-    run().unwrap_or_else(|e| {
-        eprintln!("{}", e);
-        std::process::exit(1);
+    let app = App::parse();
+    let json_errors = app.json_errors;
+    run(app).unwrap_or_else(|e| {
+        if json_errors {
+            eprintln!("{}", error_json(&e));
+        } else {
+            print_error(&e);
+        }
+        std::process::exit(e.kind().exit_code());
     });
 }
 
-fn run() -> Result<()> {
-    let app = App::parse();
+/// Prints an error on stderr, one line per failed item followed by a summary when
+/// several items were involved.
+fn print_error(error: &Error) {
+    if let Error::ItemsFailed { failures, total } = error {
+        if *total > 1 {
+            for failure in failures {
+                eprintln!("{}", failure);
+            }
+        }
+    }
+    eprintln!("{}", error);
+}
+
+/// Describes an error for `--json-errors`, with the failed items if there are several.
+fn error_json(error: &Error) -> serde_json::Value {
+    let kind = error.kind();
+    let mut json = json!({
+        "error": kind.name(),
+        "code": kind.exit_code(),
+        "message": error.to_string(),
+    });
+    if let Error::ItemsFailed { failures, .. } = error {
+        json["items"] = failures
+            .iter()
+            .map(|failure| {
+                json!({
+                    "item": failure.item,
+                    "error": failure.error.kind().name(),
+                    "message": failure.error.to_string(),
+                })
+            })
+            .collect();
+    }
+    json
+}
 
+fn run(app: App) -> Result<()> {
     // Set the log level based on the verbosity flag
     let log_level = match app.verbose {
         1 => log::LevelFilter::Warn,
@@ -70,17 +205,144 @@ fn run() -> Result<()> {
         .format_timestamp(None)
         .init();
 
+    let mut workers = Workers::default();
+    if let Some(jobs) = app.jobs {
+        workers = workers.with_jobs(jobs);
+    }
+    // A dry run does not delete or copy anything, so there is no progress to show
+    if !app.quiet && app.dry_run.is_none() {
+        workers = workers.with_progress(Progress::on_terminal());
+    }
+
     let xattr_manager = XAttrManager::new()?;
-    let config = config::Config::load(xattr_manager)?;
-    let trash_manager = trash::TrashManager::new(config.trash_dir.clone(), xattr_manager);
+    let Some(format) = app.dry_run else {
+        return run_command(app.cmd, xattr_manager, workers, None);
+    };
+
+    // Errors, like conflicts, are still reported once the plan up to them is printed
+    let plan = Plan::new();
+    let xattr_manager = DryRunAttributes::new(xattr_manager, plan.clone());
+    let result = run_command(app.cmd, xattr_manager, workers, Some(plan.clone()));
+    if result.is_ok() || !plan.actions().is_empty() {
+        print_plan(&plan, format)?;
+    }
+    result
+}
 
-    match app.cmd {
-        Commands::Rm(args) => handle_rm(config, trash_manager, args),
-        Commands::List(args) => handle_list(trash_manager, args),
+/// Prints the changes a dry run would have made and the space they would free.
+fn print_plan(plan: &Plan, format: PlanFormat) -> Result<()> {
+    let actions = plan.actions();
+    match format {
+        PlanFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&json!({
+                "actions": actions,
+                "reclaimed": plan.reclaimed(),
+            }))
+            .map_err(std::io::Error::from)?
+        ),
+        PlanFormat::Human if actions.is_empty() => println!("Dry run: nothing would change."),
+        PlanFormat::Human => {
+            println!("Dry run, nothing was changed. Planned actions:");
+            for action in actions {
+                println!("  {}", action);
+            }
+            println!("Space reclaimed: {}", format_size(plan.reclaimed()));
+        }
+    }
+    Ok(())
+}
+
+/// Runs a command with `xattr_manager` and `workers`, planning the changes instead of
+/// making them if `plan` is given.
+fn run_command<T: ExtendedAttributes + Clone>(
+    cmd: Commands,
+    xattr_manager: T,
+    workers: Workers,
+    plan: Option<Plan>,
+) -> Result<()> {
+    if let Some(name) = match cmd {
+        Commands::Browse(_) => Some("browse"),
+        Commands::Daemon => Some("daemon"),
+        Commands::InstallTimer(_) => Some("install-timer"),
+        _ => None,
+    }
+    .filter(|_| plan.is_some())
+    {
+        return Err(Error::DryRunUnsupported(name.to_string()));
+    }
+
+    let config = match &plan {
+        Some(plan) => Config::load_dry_run(xattr_manager.clone(), plan.clone())?,
+        None => Config::load(xattr_manager.clone())?,
+    };
+    let clock = clock::from_env()?;
+    let audit_log = AuditLog::in_trash(&config.trash_dir, clock.clone());
+    let trash_manager = TrashManager::new(config.trash_dir.clone(), xattr_manager)
+        .with_shred_policy(config.shred_policy.clone())
+        .with_encryption_policy(config.encryption_policy.clone())
+        .with_compression_policy(config.compression_policy.clone())
+        .with_dedup(config.dedup)
+        .with_hooks(config.hooks.clone())
+        .with_clock(clock.clone())
+        .with_audit_log(audit_log.clone())
+        .with_workers(workers)
+        .with_warning_handler(|warning| eprintln!("{}", warning));
+    let trash_manager = match &plan {
+        Some(plan) => trash_manager.with_dry_run(plan.clone()),
+        None => trash_manager,
+    };
+    let trash_manager = configure(trash_manager, &cmd);
+
+    match cmd {
+        Commands::Rm(args) => handle_rm(config, trash_manager, &*clock, args),
+        Commands::List(args) => handle_list(trash_manager, &*clock, args),
         Commands::Restore(args) => handle_restore(trash_manager, args),
+        Commands::Keep(args) => handle_keep(trash_manager, &*clock, args),
+        Commands::Expire(args) => handle_expire(trash_manager, &*clock, args),
+        Commands::Cat(args) => handle_cat(trash_manager, args),
+        Commands::Diff(args) => handle_diff(trash_manager, args),
+        Commands::Grep(args) => handle_grep(trash_manager, args),
+        Commands::Browse(args) => handle_browse(trash_manager, &*clock, args),
+        Commands::Stats(args) => handle_stats(trash_manager, &*clock, args),
         Commands::Clean(args) => handle_clean(trash_manager, args),
-        Commands::Config(args) => handle_config(config, args),
+        Commands::Log(args) => handle_log(&audit_log, &*clock, args),
+        Commands::Config(args) => handle_config(config, plan.is_none().then_some(&audit_log), args),
+        Commands::Verify(args) => handle_verify(trash_manager, args),
+        Commands::Fsck(args) => handle_fsck(config, trash_manager, &*clock, args),
+        Commands::Daemon => handle_daemon(trash_manager, &*clock, &config.trash_dir),
+        Commands::InstallTimer(args) => handle_install_timer(args),
     }?;
 
     Ok(())
 }
+
+/// Applies the flags of `cmd` that change how the manager stores, restores or deletes
+/// items, so the command itself only sees a `TrashBackend`.
+fn configure<T: ExtendedAttributes>(
+    trash_manager: TrashManager<T>,
+    cmd: &Commands,
+) -> TrashManager<T> {
+    match cmd {
+        Commands::Rm(args) => {
+            let trash_manager = if args.shred {
+                let policy = trash_manager.shred_policy().clone().shred_all();
+                trash_manager.with_shred_policy(policy)
+            } else {
+                trash_manager
+            };
+            if args.encrypt {
+                let policy = trash_manager.encryption_policy().clone().encrypt_all();
+                trash_manager.with_encryption_policy(policy)
+            } else {
+                trash_manager
+            }
+        }
+        Commands::Clean(args) if args.shred => {
+            let policy = trash_manager.shred_policy().clone().shred_all();
+            trash_manager.with_shred_policy(policy)
+        }
+        Commands::Restore(args) => trash_manager.with_verification(!args.no_verify),
+        _ => trash_manager,
+    }
+}
@@ -0,0 +1,69 @@
+use crate::Result;
+use glob::Pattern;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Parses a comma or newline separated list of globs, e.g. `*.pem,.env`.
+pub fn parse_patterns(value: &str) -> std::result::Result<Vec<Pattern>, String> {
+    value
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(|pattern| Pattern::new(pattern).map_err(|e| format!("'{}': {}", pattern, e)))
+        .collect()
+}
+
+/// Returns whether any of `patterns` matches `path`. Patterns containing a `/` are matched
+/// against the whole path, the others against its file name only.
+pub fn matches_any(patterns: &[Pattern], path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    patterns.iter().any(|pattern| {
+        if pattern.as_str().contains('/') {
+            pattern.matches_path(path)
+        } else {
+            pattern.matches(&name)
+        }
+    })
+}
+
+/// Formats patterns back into the comma separated form they are configured with.
+pub fn format_patterns(patterns: &[Pattern]) -> String {
+    patterns
+        .iter()
+        .map(|pattern| pattern.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Returns the regular files of an item matching `filter`, along with the path they had
+/// before being trashed. Symlinks are not followed.
+pub fn matching_files(
+    path: &Path,
+    original_path: &Path,
+    filter: impl Fn(&Path) -> bool,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(path).sort_by_file_name() {
+        let entry = entry.map_err(io::Error::from)?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(path).unwrap_or(Path::new(""));
+        let original = if relative.as_os_str().is_empty() {
+            original_path.to_path_buf()
+        } else {
+            original_path.join(relative)
+        };
+        if filter(&original) {
+            files.push((entry.into_path(), original));
+        }
+    }
+    Ok(files)
+}
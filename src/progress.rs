@@ -0,0 +1,213 @@
+use crate::trash::format_size;
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// How often the progress bar is redrawn at most.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Width of the bar itself, in characters.
+const BAR_WIDTH: usize = 24;
+
+/// Reports how many items and bytes an operation went through, as a progress bar on
+/// stderr with the time left.
+///
+/// A hidden progress, the default, reports nothing. Clones share the same bar, so the
+/// workers deleting or copying the files of an item can report the bytes they are done
+/// with while the manager reports the items.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    state: Option<Arc<Mutex<State>>>,
+}
+
+#[derive(Debug)]
+struct State {
+    label: &'static str,
+    items_total: u64,
+    items_done: u64,
+    bytes_total: u64,
+    /// Bytes of the items that are done.
+    bytes_done: u64,
+    /// Size of the current item, and how many of its bytes were reported so far.
+    item_size: u64,
+    item_done: u64,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+/// Clears the progress bar of an operation when the operation is over, whether it
+/// succeeded or not.
+#[must_use]
+pub struct ProgressGuard<'a> {
+    progress: &'a Progress,
+}
+
+impl Drop for ProgressGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(mut state) = self.progress.state() {
+            if state.drawn.take().is_some() {
+                eprint!("\r\x1b[2K");
+                let _ = io::stderr().flush();
+            }
+        }
+    }
+}
+
+impl Progress {
+    /// Creates a progress that reports nothing.
+    pub fn hidden() -> Self {
+        Self::default()
+    }
+
+    /// Creates a progress drawn on stderr if it is a terminal, or else hidden so scripts
+    /// and logs are not cluttered.
+    pub fn on_terminal() -> Self {
+        if !io::stderr().is_terminal() {
+            return Self::hidden();
+        }
+        Self {
+            state: Some(Arc::new(Mutex::new(State {
+                label: "",
+                items_total: 0,
+                items_done: 0,
+                bytes_total: 0,
+                bytes_done: 0,
+                item_size: 0,
+                item_done: 0,
+                started: Instant::now(),
+                drawn: None,
+            }))),
+        }
+    }
+
+    /// Starts reporting an operation on `items` items, e.g. `"Purging"`. The bar is
+    /// cleared when the returned guard is dropped.
+    pub fn start(&self, label: &'static str, items: u64) -> ProgressGuard<'_> {
+        if let Some(mut state) = self.state() {
+            state.label = label;
+            state.items_total = items;
+            state.items_done = 0;
+            state.bytes_total = 0;
+            state.bytes_done = 0;
+            state.item_size = 0;
+            state.item_done = 0;
+            state.started = Instant::now();
+        }
+        ProgressGuard { progress: self }
+    }
+
+    /// Starts the next item, which takes up `size` bytes.
+    pub fn begin_item(&self, size: u64) {
+        if let Some(mut state) = self.state() {
+            state.bytes_total += size;
+            state.item_size = size;
+            state.item_done = 0;
+            state.draw(false);
+        }
+    }
+
+    /// Reports that `bytes` more bytes of the current item were deleted or copied.
+    pub fn add_bytes(&self, bytes: u64) {
+        if let Some(mut state) = self.state() {
+            state.item_done += bytes;
+            state.draw(false);
+        }
+    }
+
+    /// Reports that the current item is done, whether it succeeded or not.
+    pub fn end_item(&self) {
+        if let Some(mut state) = self.state() {
+            state.items_done += 1;
+            state.bytes_done += state.item_size;
+            state.item_size = 0;
+            state.item_done = 0;
+            state.draw(true);
+        }
+    }
+
+    fn state(&self) -> Option<MutexGuard<'_, State>> {
+        let state = self.state.as_ref()?;
+        Some(state.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl State {
+    fn draw(&mut self, force: bool) {
+        let now = Instant::now();
+        if !force
+            && self
+                .drawn
+                .is_some_and(|drawn| now - drawn < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.drawn = Some(now);
+        // An item moved and then removed from where it was reports its bytes twice
+        let bytes_done = self.bytes_done + self.item_done.min(self.item_size);
+        let line = render(
+            self.label,
+            (self.items_done, self.items_total),
+            (bytes_done, self.bytes_total),
+            now - self.started,
+        );
+        eprint!("\r\x1b[2K{}", line);
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Formats a progress line like `Purging [#####-----] 2/4 items, 1.0 KiB / 2.0 KiB, ETA 0:05`.
+/// The time left is extrapolated from the bytes done so far, or from the items for
+/// operations that do not move any bytes.
+fn render(
+    label: &str,
+    (items_done, items_total): (u64, u64),
+    (bytes_done, bytes_total): (u64, u64),
+    elapsed: Duration,
+) -> String {
+    let (done, total) = match bytes_total {
+        0 => (items_done, items_total),
+        _ => (bytes_done, bytes_total),
+    };
+    let ratio = match total {
+        0 => 0.0,
+        total => done.min(total) as f64 / total as f64,
+    };
+    let filled = (ratio * BAR_WIDTH as f64) as usize;
+    let eta = match done {
+        0 => "--:--".to_string(),
+        done => {
+            let left = elapsed.as_secs_f64() * total.saturating_sub(done) as f64 / done as f64;
+            let left = left.round() as u64;
+            format!("{}:{:02}", left / 60, left % 60)
+        }
+    };
+    format!(
+        "{} [{}{}] {}/{} items, {} / {}, ETA {}",
+        label,
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        items_done,
+        items_total,
+        format_size(bytes_done),
+        format_size(bytes_total),
+        eta
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lines_show_the_items_bytes_and_time_left() {
+        let line = render("Purging", (1, 4), (1024, 4096), Duration::from_secs(10));
+        assert_eq!(
+            line,
+            "Purging [######------------------] 1/4 items, 1.0 KiB / 4.0 KiB, ETA 0:30"
+        );
+        let line = render("Trashing", (0, 2), (0, 0), Duration::from_secs(1));
+        assert!(line.ends_with("0/2 items, 0 B / 0 B, ETA --:--"));
+    }
+}
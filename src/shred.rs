@@ -0,0 +1,230 @@
+use crate::{
+    parallel::Workers,
+    patterns::{matches_any, matching_files},
+    trash::escape_os_str,
+    Result,
+};
+use derive_more::Display;
+use glob::Pattern;
+use std::{
+    ffi::CString,
+    fs,
+    io::{Read, Seek, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+/// Default number of times file contents are overwritten before unlinking.
+pub const DEFAULT_SHRED_PASSES: u32 = 3;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Filesystems that write modified blocks to a new location, so overwriting a file in place
+/// leaves its old contents on disk. Values are the `statfs` magic numbers.
+const COPY_ON_WRITE_FILESYSTEMS: [(i64, &str); 3] = [
+    (0x9123_683e, "btrfs"),
+    (0x2fc1_2fc1, "zfs"),
+    (0xca45_1a4e, "bcachefs"),
+];
+
+/// Decides which files are shredded when they are permanently deleted.
+#[derive(Debug, Clone)]
+pub struct ShredPolicy {
+    /// Number of overwrite passes.
+    pub passes: u32,
+    /// Shred every file, not only those matching `patterns`.
+    pub always: bool,
+    /// Globs like `*.pem` or `.env`, matched against file names, or against the whole
+    /// original path when they contain a `/`.
+    pub patterns: Vec<Pattern>,
+}
+
+impl Default for ShredPolicy {
+    fn default() -> Self {
+        Self {
+            passes: DEFAULT_SHRED_PASSES,
+            always: false,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl ShredPolicy {
+    /// Returns the same policy, but shredding every file.
+    pub fn shred_all(self) -> Self {
+        Self {
+            always: true,
+            ..self
+        }
+    }
+
+    /// Returns whether any file can be shredded at all.
+    pub fn is_enabled(&self) -> bool {
+        self.always || !self.patterns.is_empty()
+    }
+
+    /// Returns whether a file that was originally at `original_path` must be shredded.
+    pub fn matches(&self, original_path: &Path) -> bool {
+        self.always || matches_any(&self.patterns, original_path)
+    }
+}
+
+/// A file that could not be shredded effectively. Its contents may still be recoverable.
+#[derive(Debug, Display)]
+#[non_exhaustive]
+pub enum ShredWarning {
+    #[display(
+        "{}: not shredded, the file has {} other hard link(s) sharing its contents",
+        escape_os_str(path.as_os_str()),
+        links
+    )]
+    HardLinked { path: PathBuf, links: u64 },
+
+    #[display(
+        "{}: shredding is not effective on {} (copy-on-write), old contents may remain on disk",
+        escape_os_str(path.as_os_str()),
+        filesystem
+    )]
+    CopyOnWrite {
+        path: PathBuf,
+        filesystem: &'static str,
+    },
+}
+
+/// Removes a file, symlink or directory tree with `workers`, first overwriting and
+/// truncating the regular files selected by `policy`.
+///
+/// `original_path` is where `path` lived before being trashed and is what the policy is
+/// matched against. Symlinks are removed, never followed. Files that cannot be shredded
+/// effectively are still removed and reported in the returned warnings.
+pub fn shred_path(
+    path: &Path,
+    original_path: &Path,
+    policy: &ShredPolicy,
+    workers: &Workers,
+) -> Result<Vec<ShredWarning>> {
+    let mut warnings = Vec::new();
+    for (file, _) in matching_files(path, original_path, |original| policy.matches(original))? {
+        warnings.extend(shred_file(&file, policy.passes)?);
+    }
+
+    workers.remove(path)?;
+    Ok(warnings)
+}
+
+/// Overwrites a regular file `passes` times with random data and truncates it, leaving
+/// the unlinking to the caller.
+fn shred_file(path: &Path, passes: u32) -> Result<Option<ShredWarning>> {
+    let meta = path.symlink_metadata()?;
+    if meta.nlink() > 1 {
+        // Overwriting would destroy the contents seen through the other links
+        return Ok(Some(ShredWarning::HardLinked {
+            path: path.to_path_buf(),
+            links: meta.nlink() - 1,
+        }));
+    }
+
+    let mut permissions = meta.permissions();
+    if permissions.mode() & 0o200 == 0 {
+        permissions.set_mode(permissions.mode() | 0o200);
+        fs::set_permissions(path, permissions)?;
+    }
+
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    let mut random = fs::File::open("/dev/urandom")?;
+    let mut buffer = vec![0; BUFFER_SIZE];
+    for _ in 0..passes {
+        file.rewind()?;
+        let mut remaining = meta.len();
+        while remaining > 0 {
+            let chunk = remaining.min(BUFFER_SIZE as u64) as usize;
+            random.read_exact(&mut buffer[..chunk])?;
+            file.write_all(&buffer[..chunk])?;
+            remaining -= chunk as u64;
+        }
+        file.sync_data()?;
+    }
+    file.set_len(0)?;
+    file.sync_all()?;
+
+    Ok(
+        copy_on_write_filesystem(path).map(|filesystem| ShredWarning::CopyOnWrite {
+            path: path.to_path_buf(),
+            filesystem,
+        }),
+    )
+}
+
+/// Returns the name of the filesystem holding `path` if it is copy-on-write.
+fn copy_on_write_filesystem(path: &Path) -> Option<&'static str> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a valid NUL-terminated string and `stat` is a properly sized buffer
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    let magic = stat.f_type as i64;
+    COPY_ON_WRITE_FILESYSTEMS
+        .iter()
+        .find(|(fs_magic, _)| *fs_magic == magic)
+        .map(|(_, name)| *name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::patterns::parse_patterns;
+    use tempfile::tempdir;
+
+    #[test]
+    fn policy_matches_names_and_paths() {
+        let policy = ShredPolicy {
+            patterns: parse_patterns("*.pem, .env,/home/*/secrets/*").unwrap(),
+            ..ShredPolicy::default()
+        };
+        assert!(policy.matches(Path::new("/srv/tls/server.pem")));
+        assert!(policy.matches(Path::new("/srv/app/.env")));
+        assert!(policy.matches(Path::new("/home/me/secrets/token")));
+        assert!(!policy.matches(Path::new("/srv/app/.env.example")));
+        assert!(policy
+            .clone()
+            .shred_all()
+            .matches(Path::new("/srv/app/main.rs")));
+    }
+
+    #[test]
+    fn shred_path_skips_hard_linked_files() -> Result<()> {
+        let dir = tempdir()?;
+        let trashed = dir.path().join("item");
+        fs::create_dir(&trashed)?;
+        fs::write(trashed.join("key.pem"), "secret")?;
+        fs::write(trashed.join("notes.txt"), "notes")?;
+        let link = dir.path().join("link.pem");
+        fs::hard_link(trashed.join("key.pem"), &link)?;
+
+        let policy = ShredPolicy {
+            patterns: parse_patterns("*.pem").unwrap(),
+            ..ShredPolicy::default()
+        };
+        let warnings = shred_path(
+            &trashed,
+            Path::new("/work/item"),
+            &policy,
+            &Workers::default(),
+        )?;
+
+        assert!(!trashed.exists());
+        assert!(matches!(
+            warnings.as_slice(),
+            [ShredWarning::HardLinked { links: 1, .. }]
+        ));
+        // The other link keeps its contents
+        assert_eq!(fs::read_to_string(link)?, "secret");
+        Ok(())
+    }
+}
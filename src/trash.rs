@@ -1,101 +1,779 @@
-use crate::{xattr::ExtendedAttributes, Error, Result};
-use chrono::{DateTime, Utc};
+use crate::{
+    audit::{Action, AuditLog, Event},
+    clock::{Clock, SystemClock},
+    compress::{self, Compression, CompressionPolicy},
+    crypto::{self, EncryptionPolicy},
+    dry_run::{absolute, DryRunFileSystem, Plan, PlannedAction},
+    filesystem::{FileKind, FileSystem, Lock, Metadata, StdFileSystem},
+    hooks::{HookContext, HookEvent, Hooks},
+    parallel::Workers,
+    patterns::matching_files,
+    shred::{shred_path, ShredPolicy},
+    xattr::ExtendedAttributes,
+    Error, ItemFailure, Result, Warning,
+};
+use chrono::{DateTime, Local, TimeZone, Utc};
 use log::{error, info, warn};
-use std::{fs, path::PathBuf};
+use std::{
+    collections::HashSet,
+    env,
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    fmt::Write,
+    fs,
+    io::{self, Read},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 use uuid::Uuid;
+use walkdir::WalkDir;
+
+mod backend;
+mod checksum;
+mod dedup;
+mod fsck;
+pub use backend::{MetadataUpdate, TrashBackend};
+pub use checksum::Mismatch;
+pub use dedup::DedupStats;
+pub use fsck::{Issue, IssueKind, Repair};
 
 const ORIGINAL_PATH_ATTR: &str = "original_path";
 const DELETION_DATE_ATTR: &str = "deletion_date";
+const PINNED_ATTR: &str = "pinned";
+const SIZE_ATTR: &str = "size";
+const SHRED_ATTR: &str = "shred";
+const ENCRYPTED_ATTR: &str = "encrypted";
+const TRASH_DATE_ATTR: &str = "trash_date";
+const COMPRESSED_ATTR: &str = "compressed";
+const DEDUP_ATTR: &str = "deduplicated";
+const CHECKSUM_ATTR: &str = "checksum";
+
+/// Value of the compressed attribute for items that did not get smaller when compressed,
+/// so they are not compressed again on every clean.
+const INCOMPRESSIBLE: &[u8] = b"none";
+
+/// Every attribute rrm may set on a trashed item, carried over when an item is replaced by
+/// its compressed version.
+const ITEM_ATTRS: [&str; 10] = [
+    ORIGINAL_PATH_ATTR,
+    DELETION_DATE_ATTR,
+    PINNED_ATTR,
+    SIZE_ATTR,
+    SHRED_ATTR,
+    ENCRYPTED_ATTR,
+    TRASH_DATE_ATTR,
+    COMPRESSED_ATTR,
+    DEDUP_ATTR,
+    CHECKSUM_ATTR,
+];
+
+/// Shown instead of the original path of an encrypted item when no key is available.
+const ENCRYPTED_PATH: &str = "<encrypted>";
 
+/// Hidden directory inside the trash holding the metadata of items that cannot carry
+/// extended attributes themselves (user xattrs cannot be placed on symlinks on Linux).
+const METADATA_DIR: &str = ".meta";
+
+/// An item in the trash, as returned by [`TrashManager::list_items`] and
+/// [`TrashManager::get_item`].
+#[derive(Debug, Clone)]
 pub struct TrashItem {
-    pub id: String,
-    pub path: PathBuf,
-    pub original_path: String,
-    pub deletion_date: DateTime<Utc>,
+    id: String,
+    path: PathBuf,
+    original_path: PathBuf,
+    deletion_date: DateTime<Utc>,
+    pinned: bool,
+    size: Option<u64>,
+    shred: bool,
+    encrypted: bool,
+    trash_date: Option<DateTime<Utc>>,
+    compression: Option<Compression>,
+    deduplicated: bool,
+    file_kind: FileKind,
 }
 
 impl TrashItem {
+    /// Creates an item as stored by a [`TrashBackend`]: a plain, unpinned file with no
+    /// recorded size or trash date. The `with_*` methods set the rest of its metadata.
+    pub fn new(
+        id: impl Into<String>,
+        path: PathBuf,
+        original_path: PathBuf,
+        deletion_date: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            path,
+            original_path,
+            deletion_date,
+            pinned: false,
+            size: None,
+            shred: false,
+            encrypted: false,
+            trash_date: None,
+            compression: None,
+            deduplicated: false,
+            file_kind: FileKind::File,
+        }
+    }
+
+    /// Sets when the item is permanently deleted.
+    pub fn with_deletion_date(self, deletion_date: DateTime<Utc>) -> Self {
+        Self {
+            deletion_date,
+            ..self
+        }
+    }
+
+    /// Sets whether the item is kept past its deletion date.
+    pub fn with_pinned(self, pinned: bool) -> Self {
+        Self { pinned, ..self }
+    }
+
+    /// Sets the size in bytes of the item when it was trashed.
+    pub fn with_size(self, size: Option<u64>) -> Self {
+        Self { size, ..self }
+    }
+
+    /// Sets whether the item's files are overwritten before being permanently deleted.
+    pub fn with_shred(self, shred: bool) -> Self {
+        Self { shred, ..self }
+    }
+
+    /// Sets whether the item's files and original path are encrypted in the trash.
+    pub fn with_encrypted(self, encrypted: bool) -> Self {
+        Self { encrypted, ..self }
+    }
+
+    /// Sets when the item was trashed.
+    pub fn with_trash_date(self, trash_date: Option<DateTime<Utc>>) -> Self {
+        Self { trash_date, ..self }
+    }
+
+    /// Sets how the item is compressed in the trash.
+    pub fn with_compression(self, compression: Option<Compression>) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    /// Sets whether the contents of the item's files are kept in the blob store.
+    pub fn with_deduplicated(self, deduplicated: bool) -> Self {
+        Self {
+            deduplicated,
+            ..self
+        }
+    }
+
+    /// Sets what the item is stored as in the trash.
+    pub fn with_file_kind(self, file_kind: FileKind) -> Self {
+        Self { file_kind, ..self }
+    }
+
+    /// Unique id of the item, also its file name in the trash directory.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Where the item is stored in the trash.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where the item was before being trashed, and where it is restored to.
+    pub fn original_path(&self) -> &Path {
+        &self.original_path
+    }
+
+    /// When the item is permanently deleted by [`TrashManager::clean_trash`].
+    pub fn deletion_date(&self) -> DateTime<Utc> {
+        self.deletion_date
+    }
+
+    /// Pinned items are kept past their deletion date until they are unpinned.
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Size in bytes recorded when the item was trashed, unknown for items trashed by
+    /// older versions.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// The item's files are overwritten before being permanently deleted, regardless of
+    /// the shred patterns.
+    pub fn shred(&self) -> bool {
+        self.shred
+    }
+
+    /// Files of the item were encrypted when it was trashed, along with its original path.
+    pub fn encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// When the item was trashed, unknown for items trashed by older versions.
+    pub fn trash_date(&self) -> Option<DateTime<Utc>> {
+        self.trash_date
+    }
+
+    /// How the item is compressed in the trash, if it is.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// The contents of the item's files are kept in the trash's blob store, shared with
+    /// identical files of other items.
+    pub fn deduplicated(&self) -> bool {
+        self.deduplicated
+    }
+
+    /// What the item is stored as in the trash, read when the item was listed.
+    pub fn file_kind(&self) -> FileKind {
+        self.file_kind
+    }
+
+    /// Returns what the purge hooks are told about the item.
+    fn hook_context(&self) -> HookContext {
+        HookContext {
+            id: self.id.clone(),
+            path: self.original_path.clone(),
+            trash_path: self.path.clone(),
+            size: self.size,
+            deletion_date: Some(self.deletion_date),
+        }
+    }
+
+    /// Returns `"File"`, `"Directory"` or `"Symlink"`, depending on what was trashed.
     pub fn kind(&self) -> &str {
-        if self.path.is_dir() {
-            "Directory"
-        } else {
-            "File"
+        if self.compression == Some(Compression::TarZstd) {
+            return "Directory";
+        }
+        match self.file_kind {
+            FileKind::Symlink => "Symlink",
+            FileKind::Directory => "Directory",
+            FileKind::File => "File",
+        }
+    }
+
+    /// Formats the original path for display purposes, escaping bytes that are not valid UTF-8.
+    pub fn format_original_path(&self) -> String {
+        escape_os_str(self.original_path.as_os_str())
+    }
+
+    /// Formats the recorded size for display purposes, e.g. `1.5 MiB`.
+    pub fn format_size(&self) -> String {
+        match self.size {
+            Some(size) => format_size(size),
+            None => "-".to_string(),
         }
     }
 
-    /// Formats the deletion date for display purposes.
-    pub fn format_deletion_date(&self) -> String {
-        let now = Utc::now().date_naive();
-        let deletion_date = self.deletion_date.date_naive();
+    /// Returns the number of bytes the item takes up in the trash, which is less than its
+    /// size once it is compressed. The shared contents of deduplicated items are not
+    /// included.
+    pub fn stored_size(&self) -> u64 {
+        item_size(&self.path)
+    }
+
+    /// Formats the size the item takes up in the trash for display purposes.
+    pub fn format_stored_size(&self) -> String {
+        format_size(self.stored_size())
+    }
+
+    /// Formats the deletion date for display purposes, in local time. Dates on the same
+    /// day as `now` or the next one are shown as today or tomorrow.
+    pub fn format_deletion_date(&self, now: DateTime<Utc>) -> String {
+        self.format_deletion_date_in(now, &Local)
+    }
+
+    fn format_deletion_date_in<Tz: TimeZone>(&self, now: DateTime<Utc>, tz: &Tz) -> String
+    where
+        Tz::Offset: Display,
+    {
+        if self.pinned {
+            return "Never (pinned)".to_string();
+        }
+
+        // Days start at local midnight, which a DST change does not move
+        let today = now.with_timezone(tz).date_naive();
+        let deletion_date = self.deletion_date.with_timezone(tz);
 
-        match deletion_date {
-            d if d == now => "Today".to_string(),
-            d if d == now.succ_opt().unwrap_or(now) => "Tomorrow".to_string(),
-            _ => self.deletion_date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        match deletion_date.date_naive() {
+            // Grace periods can be shorter than a day, so the time matters here too
+            d if d == today => format!("Today {}", deletion_date.format("%H:%M")),
+            d if d == today.succ_opt().unwrap_or(today) => {
+                format!("Tomorrow {}", deletion_date.format("%H:%M"))
+            }
+            _ => deletion_date.format("%Y-%m-%d %H:%M:%S").to_string(),
         }
     }
 }
 
+/// A lock on the trash. The post-hooks of the operations made under it run once it is
+/// released, so they may run rrm on the same trash.
+struct TrashLock<'a, T: ExtendedAttributes> {
+    lock: Option<Lock>,
+    trash_manager: &'a TrashManager<T>,
+}
+
+impl<T: ExtendedAttributes> Drop for TrashLock<'_, T> {
+    fn drop(&mut self) {
+        self.lock.take();
+        self.trash_manager.run_post_hooks();
+    }
+}
+
+/// Moves items to the trash directory and back, and permanently deletes them once their
+/// grace period is over.
+///
+/// Every operation locks the trash directory, so several processes (e.g. the CLI and a
+/// daemon) can share a trash safely. Items keep their metadata in extended attributes,
+/// read and written through `T`.
 pub struct TrashManager<T: ExtendedAttributes> {
     trash_dir: PathBuf,
     xattr_manager: T,
+    file_system: Box<dyn FileSystem>,
+    /// Whether `file_system` was set with [`Self::with_file_system`] instead of being the
+    /// real one, which is the only one [`Self::with_workers`] applies to.
+    custom_file_system: bool,
+    clock: Arc<dyn Clock>,
+    audit_log: Option<AuditLog>,
+    hooks: Hooks,
+    plan: Option<Plan>,
+    workers: Workers,
+    shred_policy: ShredPolicy,
+    encryption_policy: EncryptionPolicy,
+    compression_policy: CompressionPolicy,
+    dedup: bool,
+    verify: bool,
+    /// Post-hooks of the operations made while the trash is locked, run once it is not.
+    post_hooks: Mutex<Vec<(HookEvent, HookContext)>>,
+    on_warning: Arc<dyn Fn(&Warning) + Send + Sync>,
 }
 
 impl<T: ExtendedAttributes> TrashManager<T> {
+    /// Creates a manager for the trash at `trash_dir`, which must exist. Nothing is
+    /// shredded, encrypted, compressed or deduplicated until the matching `with_*`
+    /// builder is used, e.g. with the policies of a [`crate::Config`].
     pub fn new(trash_dir: PathBuf, xattr_manager: T) -> Self {
         Self {
             trash_dir,
             xattr_manager,
+            file_system: Box::new(StdFileSystem::default()),
+            custom_file_system: false,
+            clock: Arc::new(SystemClock),
+            audit_log: None,
+            hooks: Hooks::default(),
+            plan: None,
+            workers: Workers::default(),
+            shred_policy: ShredPolicy::default(),
+            encryption_policy: EncryptionPolicy::default(),
+            compression_policy: CompressionPolicy::default(),
+            dedup: false,
+            verify: true,
+            post_hooks: Mutex::default(),
+            on_warning: Arc::new(|warning| warn!("{}", warning)),
+        }
+    }
+
+    /// Returns the policy deciding which files are shredded on permanent deletion.
+    pub fn shred_policy(&self) -> &ShredPolicy {
+        &self.shred_policy
+    }
+
+    /// Sets which files are shredded when items are permanently deleted. Items trashed
+    /// while the policy shreds everything are marked so they are shredded later too.
+    pub fn with_shred_policy(self, shred_policy: ShredPolicy) -> Self {
+        Self {
+            shred_policy,
+            ..self
+        }
+    }
+
+    /// Returns the policy deciding which items are encrypted in the trash.
+    pub fn encryption_policy(&self) -> &EncryptionPolicy {
+        &self.encryption_policy
+    }
+
+    /// Sets which items are encrypted when they are trashed, and the key used to encrypt
+    /// and decrypt them.
+    pub fn with_encryption_policy(self, encryption_policy: EncryptionPolicy) -> Self {
+        Self {
+            encryption_policy,
+            ..self
+        }
+    }
+
+    /// Sets which items are compressed, when they are trashed or later on when the trash
+    /// is cleaned.
+    pub fn with_compression_policy(self, compression_policy: CompressionPolicy) -> Self {
+        Self {
+            compression_policy,
+            ..self
+        }
+    }
+
+    /// Sets whether the contents of trashed files are stored only once across the trash.
+    /// Deduplicated items are never compressed.
+    pub fn with_dedup(self, dedup: bool) -> Self {
+        Self { dedup, ..self }
+    }
+
+    /// Sets whether items are checked against the checksums recorded when they were
+    /// trashed before they are restored. On by default.
+    pub fn with_verification(self, verify: bool) -> Self {
+        Self { verify, ..self }
+    }
+
+    /// Sets the filesystem items are moved, listed and deleted through, instead of the
+    /// real one, e.g. an in-memory one in tests. The trash cannot be changed through it
+    /// while compression, deduplication, encryption or shredding is enabled.
+    pub fn with_file_system(self, file_system: impl FileSystem + 'static) -> Self {
+        Self {
+            file_system: Box::new(file_system),
+            custom_file_system: true,
+            ..self
+        }
+    }
+
+    /// Sets the threads deleting and copying the files of items, and where the progress
+    /// of operations on several items or large trees is reported. A filesystem set with
+    /// [`Self::with_file_system`] is kept as is, the workers only drive the real one.
+    pub fn with_workers(self, workers: Workers) -> Self {
+        let file_system = match self.custom_file_system {
+            true => self.file_system,
+            false => Box::new(StdFileSystem::new(workers.clone())),
+        };
+        Self {
+            file_system,
+            workers,
+            ..self
+        }
+    }
+
+    /// Returns the threads deleting and copying the files of items.
+    pub fn workers(&self) -> &Workers {
+        &self.workers
+    }
+
+    /// Sets where the current time comes from, which decides when items expire.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Sets the log every trash, restore, purge and metadata change is recorded in. Nothing
+    /// is recorded by default.
+    pub fn with_audit_log(self, audit_log: AuditLog) -> Self {
+        Self {
+            audit_log: Some(audit_log),
+            ..self
+        }
+    }
+
+    /// Returns the log operations are recorded in, if any.
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit_log.as_ref()
+    }
+
+    /// Records an operation in the audit log, if there is one, including operations done
+    /// outside the manager like immediate deletions. The operation already happened, so
+    /// failing to record it is only reported. Nothing is recorded by a dry run.
+    pub fn record(&self, event: Event) {
+        if self.plan.is_some() {
+            return;
+        }
+        if let Some(audit_log) = &self.audit_log {
+            if let Err(e) = audit_log.record(event) {
+                self.report(Warning::AuditLog(e));
+            }
+        }
+    }
+
+    /// Sets what is done with the [`Warning`]s of the operations, which are only logged
+    /// by default.
+    pub fn with_warning_handler(self, handler: impl Fn(&Warning) + Send + Sync + 'static) -> Self {
+        Self {
+            on_warning: Arc::new(handler),
+            ..self
+        }
+    }
+
+    fn report(&self, warning: Warning) {
+        (self.on_warning)(&warning);
+    }
+
+    /// Sets the commands run before and after items are trashed, restored and purged.
+    pub fn with_hooks(self, hooks: Hooks) -> Self {
+        Self { hooks, ..self }
+    }
+
+    /// Makes every change go into `plan` instead of being made: moves, deletions and
+    /// writes through the manager's filesystem, and compression, encryption,
+    /// deduplication and shredding. Hooks are not run and nothing is audited.
+    ///
+    /// Attribute changes are only planned if `T` is a
+    /// [`crate::dry_run::DryRunAttributes`] sharing the same plan.
+    pub fn with_dry_run(self, plan: Plan) -> Self {
+        Self {
+            file_system: Box::new(DryRunFileSystem::new(self.file_system, plan.clone())),
+            plan: Some(plan),
+            ..self
+        }
+    }
+
+    /// Returns the plan changes go into, if this is a dry run.
+    pub fn dry_run(&self) -> Option<&Plan> {
+        self.plan.as_ref()
+    }
+
+    /// Runs the hooks of `event`, unless this is a dry run.
+    fn run_hooks(&self, event: HookEvent, context: &HookContext) -> Result<()> {
+        match self.plan {
+            Some(_) => Ok(()),
+            None => self.hooks.run(event, context),
+        }
+    }
+
+    /// Queues the post-hooks of `event`, to run once the trash is unlocked.
+    fn queue_post_hooks(&self, event: HookEvent, context: HookContext) {
+        if self.plan.is_none() {
+            self.post_hooks().push((event, context));
+        }
+    }
+
+    /// Runs the queued post-hooks. The operations already happened, so a failing hook is
+    /// only reported.
+    fn run_post_hooks(&self) {
+        let post_hooks = std::mem::take(&mut *self.post_hooks());
+        for (event, context) in post_hooks {
+            if let Err(e) = self.hooks.run(event, &context) {
+                self.report(Warning::PostHook(e));
+            }
         }
     }
 
+    fn post_hooks(&self) -> std::sync::MutexGuard<'_, Vec<(HookEvent, HookContext)>> {
+        // Queuing cannot panic halfway, the queue is fine even if the lock is poisoned
+        self.post_hooks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the current time, according to the manager's clock.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Returns the directory where trashed items are stored.
+    pub fn trash_dir(&self) -> &Path {
+        &self.trash_dir
+    }
+
     /// Moves the specified items to the trash.
+    ///
+    /// Symlinks are trashed as links: the link itself is moved, never its target, and its
+    /// metadata is stored on a sidecar file in the trash metadata directory.
+    ///
+    /// An item that cannot be trashed does not stop the others, the failures are returned
+    /// together as [`Error::ItemsFailed`].
     pub fn trash_items(&self, paths: Vec<PathBuf>, deletion_date: DateTime<Utc>) -> Result<()> {
+        let total = paths.len();
+        let mut failures = Vec::new();
+        let mut approved = Vec::new();
         for path in paths {
-            // Gets the original path"
-            let original_path = path.canonicalize()?;
-            let original_path_str = match original_path.to_str() {
-                Some(p) => p,
-                None => {
-                    error!(
-                        "Failed to convert original path to string: {} cannot be represented as UTF-8",
-                        original_path.display()
-                    );
-                    continue;
-                }
-            };
+            match self.prepare_trash(&path, deletion_date) {
+                Ok(context) => approved.push((path, context)),
+                Err(error) => failures.push(ItemFailure {
+                    item: escape_os_str(path.as_os_str()),
+                    error,
+                }),
+            }
+        }
+
+        let _lock = self.lock_exclusive()?;
+        let now = self.now();
+        let progress = self.workers.progress();
+        let _progress = progress.start("Trashing", approved.len() as u64);
+        for (path, context) in approved {
+            let result = self.trash_item(&path, context, now);
+            progress.end_item();
+            if let Err(error) = result {
+                failures.push(ItemFailure {
+                    item: escape_os_str(path.as_os_str()),
+                    error,
+                });
+            }
+        }
+        Error::from_failures(failures, total)
+    }
+
+    /// Picks an id for the item at `path` and runs its pre-trash hooks, before the trash
+    /// is locked so they may run rrm themselves. Returns what the hooks were told.
+    fn prepare_trash(&self, path: &Path, deletion_date: DateTime<Utc>) -> Result<HookContext> {
+        // Gets the original path, kept as raw bytes so any file name can be stored
+        let original_path = self.absolute_path(path)?;
+
+        // Generate a unique id to prevent collisions
+        let id = Uuid::new_v4().to_string();
+        let context = HookContext {
+            trash_path: self.trash_dir.join(&id),
+            id,
+            path: original_path,
+            size: Some(self.item_size(path)?),
+            deletion_date: Some(deletion_date),
+        };
+        self.run_hooks(HookEvent::PreTrash, &context)?;
+        Ok(context)
+    }
+
+    /// Moves a single item, whose pre-hooks agreed, to the trash and returns its id.
+    fn trash_item(&self, path: &Path, context: HookContext, now: DateTime<Utc>) -> Result<String> {
+        let unique_id = context.id.clone();
+        let original_path = context.path.clone();
+        let trashed_item_path = context.trash_path.clone();
+        let size = context.size.unwrap_or_default();
+        let deletion_date = context.deletion_date.unwrap_or(now);
+        self.workers.progress().begin_item(size);
+
+        // Symlinks keep their metadata on a sidecar file, everything else carries it along
+        let symlink = self.is_symlink(path);
+        let attrs_path = if symlink {
+            let sidecar = self.sidecar_path(OsStr::new(&unique_id));
+            self.file_system
+                .create_dir_all(&self.trash_dir.join(METADATA_DIR))?;
+            self.file_system.write(&sidecar, b"")?;
+            sidecar
+        } else {
+            path.to_path_buf()
+        };
+
+        // Files matching the encryption policy are encrypted once they are in the trash,
+        // and so is the original path. Symlinks have no contents to encrypt.
+        let encrypt = self.encryption_policy.is_enabled()
+            && !symlink
+            && !matching_files(path, &original_path, |original| {
+                self.encryption_policy.matches(original)
+            })?
+            .is_empty();
+        let original_path_value = if encrypt {
+            self.encryption_policy
+                .encrypt_bytes(original_path.as_os_str().as_bytes())?
+        } else {
+            original_path.as_os_str().as_bytes().to_vec()
+        };
 
-            // Sets extended attributes on the trashed item
+        // Sets extended attributes on the trashed item
+        self.xattr_manager
+            .set_attr(&attrs_path, ORIGINAL_PATH_ATTR, &original_path_value)?;
+        self.xattr_manager.set_attr(
+            &attrs_path,
+            DELETION_DATE_ATTR,
+            deletion_date.to_rfc3339().as_bytes(),
+        )?;
+        self.xattr_manager
+            .set_attr(&attrs_path, SIZE_ATTR, size.to_string().as_bytes())?;
+        self.xattr_manager
+            .set_attr(&attrs_path, TRASH_DATE_ATTR, now.to_rfc3339().as_bytes())?;
+        if self.shred_policy.always {
             self.xattr_manager
-                .set_attr(&path, ORIGINAL_PATH_ATTR, original_path_str)?;
+                .set_attr(&attrs_path, SHRED_ATTR, b"true")?;
+        }
+        self.record_checksum(path, &unique_id, encrypt)?;
+        if encrypt {
             self.xattr_manager
-                .set_attr(&path, DELETION_DATE_ATTR, &deletion_date.to_rfc3339())?;
+                .set_attr(&attrs_path, ENCRYPTED_ATTR, b"true")?;
+        }
 
-            // Generate a unique id to prevent collisions
-            let unique_id = Uuid::new_v4().to_string();
-            let trashed_item_path = self.trash_dir.join(&unique_id);
+        // Move the item to the trash directory, leaving it as it was if that fails
+        if let Err(e) = self.move_path(path, &trashed_item_path) {
+            // Unless the item is complete in the trash and only leftovers remain
+            if matches!(e, Error::IncompleteMove { .. }) {
+                return Err(e);
+            }
+            if attrs_path != path {
+                let _ = self.file_system.remove(&attrs_path);
+            } else {
+                let _ = self.remove_item_attrs(path);
+            }
+            let _ = self.remove_checksums(OsStr::new(&unique_id));
+            return Err(e);
+        }
+        let event = Event {
+            id: Some(unique_id.clone()),
+            size: Some(size),
+            deletion_date: Some(deletion_date),
+            ..Event::new(Action::Trash)
+        };
+        let event = if encrypt {
+            event
+        } else {
+            event.with_path(&original_path)
+        };
 
-            // Move the item to the trash directory
-            fs::rename(path, &trashed_item_path)?;
+        // The item is in the trash from here on. Deduplication and compression only save
+        // space, an item they fail on is kept as it is, but an item that cannot be
+        // encrypted does not stay in the trash in the clear.
+        if let Some(plan) = self.plan.as_ref().filter(|_| encrypt) {
+            plan.record(PlannedAction::Encrypt {
+                path: trashed_item_path.clone(),
+            });
+        } else if encrypt {
+            match self.encryption_policy.encrypt_tree(
+                &trashed_item_path,
+                &original_path,
+                &self.trash_dir,
+            ) {
+                Ok(skipped) => {
+                    for path in skipped {
+                        self.report(Warning::NotEncrypted(path));
+                    }
+                }
+                Err(e) => {
+                    if let Err(put_back) = self.put_back(path, &trashed_item_path, &unique_id) {
+                        error!(
+                            "Failed to move {} back out of the trash: {}",
+                            escape_os_str(path.as_os_str()),
+                            put_back
+                        );
+                        self.record(event);
+                    }
+                    return Err(e);
+                }
+            }
+        } else if attrs_path != path {
+            // Symlinks, whose metadata lives on a sidecar, have no contents to store
+        } else if self.dedup {
+            if let Err(e) = self.dedup_item(&trashed_item_path) {
+                self.report(Warning::StoredAsIs(original_path.clone(), e));
+            }
+        } else if self.compression_policy.on_trash(size) {
+            if let Err(e) = self.compress_item(&trashed_item_path, &original_path) {
+                self.report(Warning::StoredAsIs(original_path.clone(), e));
+            }
         }
-        Ok(())
+        self.record(event);
+        self.queue_post_hooks(HookEvent::PostTrash, context);
+        Ok(unique_id)
     }
 
     /// Retrieves a list of items currently in the trash.
     pub fn list_items(&self) -> Result<Vec<TrashItem>> {
-        let mut items: Vec<TrashItem> = Vec::new();
-        for entry_result in self.trash_dir.read_dir()? {
-            let entry = match entry_result {
-                Ok(e) => e,
-                Err(e) => {
-                    println!("Failed to read entry in trash directory: {}", e);
+        let _lock = self.lock_shared()?;
+        self.scan_items()
+    }
 
-                    error!("Failed to read entry in trash directory: {}", e);
-                    continue;
-                }
+    fn scan_items(&self) -> Result<Vec<TrashItem>> {
+        let mut items: Vec<TrashItem> = Vec::new();
+        for path in self.file_system.read_dir(&self.trash_dir)? {
+            let Some(name) = path.file_name() else {
+                continue;
             };
+            if name.as_bytes().starts_with(b".") {
+                // Hidden entries are rrm's own bookkeeping, e.g. the metadata directory
+                continue;
+            }
 
-            let path = entry.path();
             let id = path
                 .file_name()
                 .and_then(|name| name.to_str())
@@ -103,8 +781,12 @@ impl<T: ExtendedAttributes> TrashManager<T> {
                 .to_string();
 
             // Get the extended attributes
-            let original_path = match self.xattr_manager.get_attr(&path, ORIGINAL_PATH_ATTR) {
-                Ok(Some(val)) => val,
+            let attrs_path = self.attrs_path(&path);
+            let original_path = match self.xattr_manager.get_attr(&attrs_path, ORIGINAL_PATH_ATTR) {
+                Ok(Some(val)) => self.decode_original_path(val).unwrap_or_else(|e| {
+                    warn!("Cannot decrypt the original path of item '{}': {}", id, e);
+                    PathBuf::from(ENCRYPTED_PATH)
+                }),
                 _ => {
                     warn!(
                         "Missing '{ORIGINAL_PATH_ATTR}' for item with id: '{}' - maybe it was not deleted by rrm?",
@@ -114,7 +796,10 @@ impl<T: ExtendedAttributes> TrashManager<T> {
                 }
             };
 
-            let deletion_date_str = match self.xattr_manager.get_attr(&path, DELETION_DATE_ATTR) {
+            let deletion_date_raw = match self
+                .xattr_manager
+                .get_attr(&attrs_path, DELETION_DATE_ATTR)
+            {
                 Ok(Some(val)) => val,
                 _ => {
                     warn!("Missing '{DELETION_DATE_ATTR}' for item with id: '{}' - maybe it was not deleted by rrm?", id);
@@ -122,273 +807,1957 @@ impl<T: ExtendedAttributes> TrashManager<T> {
                 }
             };
 
-            let deletion_date = match DateTime::parse_from_rfc3339(&deletion_date_str) {
-                Ok(dt) => dt.with_timezone(&Utc),
-                Err(_) => {
+            let deletion_date = match std::str::from_utf8(&deletion_date_raw)
+                .ok()
+                .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            {
+                Some(dt) => dt.with_timezone(&Utc),
+                None => {
                     error!("Failed to parse deletion date for item with id: {}", id);
                     continue;
                 }
             };
 
+            let pinned = self.read_flag(&attrs_path, PINNED_ATTR);
+            let size = self.read_size(&attrs_path);
+            let shred = self.read_flag(&attrs_path, SHRED_ATTR);
+            let encrypted = self.read_flag(&attrs_path, ENCRYPTED_ATTR);
+            let trash_date = self.read_trash_date(&attrs_path);
+            let compression = self.read_compression(&attrs_path);
+            let deduplicated = self.read_flag(&attrs_path, DEDUP_ATTR);
+            let file_kind = match self.file_system.metadata(&path) {
+                Ok(meta) => meta.kind,
+                Err(e) => {
+                    error!("Failed to read the metadata of item with id: {}: {}", id, e);
+                    continue;
+                }
+            };
             items.push(TrashItem {
                 id,
                 path,
                 original_path,
                 deletion_date,
+                pinned,
+                size,
+                shred,
+                encrypted,
+                trash_date,
+                compression,
+                deduplicated,
+                file_kind,
             });
         }
 
         Ok(items)
     }
 
-    /// Restores an item from the trash by its ID.
-    pub fn restore_item_by_id(&self, id: &str, rename: Option<String>) -> Result<()> {
-        let item_path = self.trash_dir.join(id);
-        if !item_path.exists() {
-            return Err(Error::ItemNotFound(id.to_string()));
-        }
-
-        let original_path = self
-            .xattr_manager
-            .get_attr(&item_path, ORIGINAL_PATH_ATTR)?
-            .ok_or_else(|| Error::MissingAttribute {
-                attr: ORIGINAL_PATH_ATTR.to_string(),
-                id: id.to_string(),
-            })?;
-
-        // Get the original path
-        let mut original_path = PathBuf::from(original_path);
+    /// Retrieves a single item from the trash by its ID.
+    pub fn get_item(&self, id: &str) -> Result<TrashItem> {
+        let _lock = self.lock_shared()?;
+        self.load_item(id)
+    }
 
-        // Rename the item if a new name is provided
-        let original_path = if let Some(new_name) = rename {
-            original_path.set_file_name(new_name);
-            original_path
-        } else {
-            original_path
-        };
+    fn load_item(&self, id: &str) -> Result<TrashItem> {
+        let (path, attrs_path) = self.locate_item(id)?;
+        let original_path = self.read_original_path(id, &attrs_path)?;
+        let deletion_date = self.read_deletion_date(id, &attrs_path)?;
+        let pinned = self.read_flag(&attrs_path, PINNED_ATTR);
+        let size = self.read_size(&attrs_path);
+        let shred = self.read_flag(&attrs_path, SHRED_ATTR);
+        let encrypted = self.read_flag(&attrs_path, ENCRYPTED_ATTR);
+        let trash_date = self.read_trash_date(&attrs_path);
+        let compression = self.read_compression(&attrs_path);
+        let deduplicated = self.read_flag(&attrs_path, DEDUP_ATTR);
+        let file_kind = self.file_system.metadata(&path)?.kind;
 
-        if original_path.exists() {
-            return Err(Error::PathAlreadyExists(
-                original_path.to_string_lossy().to_string(),
-            ));
-        }
+        Ok(TrashItem {
+            id: id.to_string(),
+            path,
+            original_path,
+            deletion_date,
+            pinned,
+            size,
+            shred,
+            encrypted,
+            trash_date,
+            compression,
+            deduplicated,
+            file_kind,
+        })
+    }
 
-        if let Some(parent) = original_path.parent() {
-            if !parent.exists() {
-                warn!(
-                    "Parent directory of the original path does not exist: {}",
-                    parent.display()
-                );
-                return Err(Error::InvalidOriginalPath(
-                    original_path.to_string_lossy().to_string(),
-                ));
-            }
+    /// Reads the contents of a trashed file without restoring it.
+    pub fn read_item(&self, item: &TrashItem) -> Result<Vec<u8>> {
+        let _lock = self.lock_shared()?;
+        if item.kind() != "File" {
+            return Err(Error::NotAFile(item.format_original_path()));
         }
+        let mut content = Vec::new();
+        self.open_file(item, &item.path)?
+            .read_to_end(&mut content)?;
+        Ok(content)
+    }
 
-        // Remove the xattr attributes
-        self.xattr_manager
-            .remove_attr(&item_path, ORIGINAL_PATH_ATTR)?;
-        self.xattr_manager
-            .remove_attr(&item_path, DELETION_DATE_ATTR)?;
+    /// Calls `f` with the path each regular file of a trashed item had before being
+    /// trashed, and its contents. Directories are walked recursively, symlinks are not
+    /// followed. Files that cannot be read are passed on with their error.
+    pub fn for_each_file(
+        &self,
+        item: &TrashItem,
+        f: impl FnMut(PathBuf, Result<Vec<u8>>),
+    ) -> Result<()> {
+        let _lock = self.lock_shared()?;
+        self.visit_files(item, f)
+    }
 
-        fs::rename(&item_path, &original_path)?;
-        Ok(())
+    fn visit_files(
+        &self,
+        item: &TrashItem,
+        mut f: impl FnMut(PathBuf, Result<Vec<u8>>),
+    ) -> Result<()> {
+        self.read_files(item, |original_path, reader| {
+            let content = reader.and_then(|reader| {
+                let mut content = Vec::new();
+                reader.read_to_end(&mut content)?;
+                Ok(content)
+            });
+            f(original_path, content)
+        })
     }
 
-    pub fn clean_trash(&self, immediate: bool) -> Result<()> {
-        let items = self.list_items()?;
-        info!("Trash items found: {}", items.len());
-        let now = Utc::now();
-        let mut items_deleted = 0;
-        for item in items {
-            if (immediate || item.deletion_date < now) && item.path.exists() {
-                info!(
-                    "Deleting item with id: {} and original path: {}",
-                    item.id, item.original_path
-                );
-                self.delete_item_permanently(item)?;
-                items_deleted += 1;
+    /// Like [`Self::visit_files`], but passes a reader of the contents of each file, which
+    /// are decompressed and decrypted as they are read rather than loaded in memory.
+    fn read_files(
+        &self,
+        item: &TrashItem,
+        mut f: impl FnMut(PathBuf, Result<&mut dyn Read>),
+    ) -> Result<()> {
+        if item.compression == Some(Compression::TarZstd) {
+            return compress::for_each_file(&item.path, &item.original_path, |path, reader| {
+                f(path, Ok(reader))
+            });
+        }
+        if item.deduplicated {
+            for (original_path, file) in self.deduplicated_files(&item.path, &item.original_path)? {
+                match fs::File::open(file) {
+                    Ok(mut file) => f(original_path, Ok(&mut file)),
+                    Err(e) => f(original_path, Err(e.into())),
+                }
             }
+            return Ok(());
         }
 
-        info!("Items deleted from trash: {}", items_deleted);
+        for (file, original_path) in self.walk_files(&item.path, &item.original_path)? {
+            match self.open_file(item, &file) {
+                Ok(mut reader) => f(original_path, Ok(&mut *reader)),
+                Err(e) => f(original_path, Err(e)),
+            }
+        }
         Ok(())
     }
 
-    fn delete_item_permanently(&self, item: TrashItem) -> Result<()> {
-        assert!(item.path.exists());
-        if item.path.is_dir() {
-            fs::remove_dir_all(&item.path)?;
+    /// Opens a file of a trashed item, which is the item itself or a file inside a trashed
+    /// directory, decompressing and decrypting it as it is read if needed.
+    fn open_file(&self, item: &TrashItem, file: &Path) -> Result<Box<dyn Read>> {
+        if item.compression == Some(Compression::Zstd) {
+            compress::open_file(file)
+        } else if item.deduplicated {
+            self.open_deduplicated(&item.path, file)
+        } else if item.encrypted {
+            self.encryption_policy.open_file(file)
         } else {
-            fs::remove_file(&item.path)?;
+            Ok(self.file_system.open(file)?)
         }
-        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
+    /// Restores an item from the trash by its ID.
+    pub fn restore_item_by_id(&self, id: &str, rename: Option<OsString>) -> Result<()> {
+        self.restore_to(id, |attrs_path| {
+            let mut original_path = self.read_original_path(id, attrs_path)?;
+
+            // Rename the item if a new name is provided
+            if let Some(new_name) = &rename {
+                original_path.set_file_name(new_name);
+            }
+            Ok(original_path)
+        })
+    }
+
+    /// Restores an item from the trash into `dir`, keeping its original file name.
+    pub fn restore_item_to(&self, id: &str, dir: &Path) -> Result<()> {
+        self.restore_to(id, |attrs_path| {
+            let original_path = self.read_original_path(id, attrs_path)?;
+            let file_name = original_path.file_name().ok_or_else(|| {
+                Error::InvalidOriginalPath(escape_os_str(original_path.as_os_str()))
+            })?;
+            Ok(dir.join(file_name))
+        })
+    }
+
+    /// Moves the item `id` to the destination `destination` picks from the path carrying
+    /// its metadata. The pre-hooks run before the trash is locked for writing, so they may
+    /// run rrm themselves, then the item is verified if verification is on.
+    fn restore_to(&self, id: &str, destination: impl Fn(&Path) -> Result<PathBuf>) -> Result<()> {
+        let context = {
+            let _lock = self.lock_shared()?;
+            let (item_path, attrs_path) = self.locate_item(id)?;
+            HookContext {
+                id: id.to_string(),
+                path: destination(&attrs_path)?,
+                trash_path: item_path,
+                size: self.read_size(&attrs_path),
+                deletion_date: self.read_deletion_date(id, &attrs_path).ok(),
+            }
+        };
+        self.run_hooks(HookEvent::PreRestore, &context)?;
+
+        // Another process may have restored or purged the item in the meantime
+        let _lock = self.lock_exclusive()?;
+        let (item_path, attrs_path) = self.locate_item(id)?;
+        if self.verify {
+            self.ensure_integrity(id, &attrs_path)?;
+        }
+        let progress = self.workers.progress();
+        let _progress = progress.start("Restoring", 1);
+        progress.begin_item(context.size.unwrap_or_default());
+        self.move_out_of_trash(&item_path, &attrs_path, &context.path)?;
+        self.record(
+            Event {
+                id: Some(id.to_string()),
+                ..Event::new(Action::Restore)
+            }
+            .with_path(&context.path),
+        );
+        self.remove_checksums(OsStr::new(id))?;
+        self.queue_post_hooks(HookEvent::PostRestore, context);
+        Ok(())
+    }
+
+    /// Updates the date after which a trashed item is permanently deleted.
+    pub fn set_deletion_date(&self, id: &str, deletion_date: DateTime<Utc>) -> Result<()> {
+        self.update_schedule(id, Some(deletion_date), None)
+    }
+
+    /// Pins or unpins a trashed item. Pinned items are skipped by [`Self::clean_trash`]
+    /// unless it is forced.
+    pub fn set_pinned(&self, id: &str, pinned: bool) -> Result<()> {
+        self.update_schedule(id, None, Some(pinned))
+    }
+
+    /// Unpins a trashed item and sets its deletion date under a single lock, so a clean
+    /// running alongside never sees one change without the other.
+    pub fn reschedule(&self, id: &str, deletion_date: DateTime<Utc>) -> Result<()> {
+        self.update_schedule(id, Some(deletion_date), Some(false))
+    }
+
+    fn update_schedule(
+        &self,
+        id: &str,
+        deletion_date: Option<DateTime<Utc>>,
+        pinned: Option<bool>,
+    ) -> Result<()> {
+        let _lock = self.lock_exclusive()?;
+        let (_, attrs_path) = self.locate_item(id)?;
+        match pinned {
+            Some(true) => self
+                .xattr_manager
+                .set_attr(&attrs_path, PINNED_ATTR, b"true")?,
+            Some(false) => self.remove_attr_if_present(&attrs_path, PINNED_ATTR)?,
+            None => {}
+        }
+        if let Some(deletion_date) = deletion_date {
+            self.xattr_manager.set_attr(
+                &attrs_path,
+                DELETION_DATE_ATTR,
+                deletion_date.to_rfc3339().as_bytes(),
+            )?;
+        }
+        self.record(Event {
+            id: Some(id.to_string()),
+            deletion_date,
+            pinned,
+            ..Event::new(Action::Update)
+        });
+        Ok(())
+    }
+
+    /// Permanently deletes paths right away instead of trashing them, shredding the files
+    /// selected by the shred policy, and records the deletions in the audit log. Every
+    /// path is tried, those that are missing or fail are reported together at the end.
+    pub fn delete_paths(&self, paths: Vec<PathBuf>) -> Result<()> {
+        let progress = self.workers.progress();
+        let total = paths.len();
+        let _progress = progress.start("Deleting", total as u64);
+        let mut failures = Vec::new();
+        for path in paths {
+            if !self.file_system.exists(&path) {
+                // Still counted, so the progress reaches the number of paths given
+                progress.end_item();
+                failures.push(ItemFailure {
+                    item: escape_os_str(path.as_os_str()),
+                    error: io::Error::from_raw_os_error(libc::ENOENT).into(),
+                });
+                continue;
+            }
+
+            let result = self.delete_path(&path);
+            progress.end_item();
+            match result {
+                Ok(size) => self.record(
+                    Event {
+                        size: Some(size),
+                        ..Event::new(Action::Delete)
+                    }
+                    .with_path(&absolute(&path)),
+                ),
+                Err(error) => failures.push(ItemFailure {
+                    item: escape_os_str(path.as_os_str()),
+                    error,
+                }),
+            }
+        }
+        Error::from_failures(failures, total)
+    }
+
+    /// Permanently deletes a path outside the trash, returning its size.
+    fn delete_path(&self, path: &Path) -> Result<u64> {
+        let size = self.item_size(path)?;
+        self.workers.progress().begin_item(size);
+        let policy = &self.shred_policy;
+        if let Some(plan) = self.plan.as_ref().filter(|_| policy.is_enabled()) {
+            plan.record(PlannedAction::Shred {
+                path: absolute(path),
+                size,
+            });
+        } else if policy.is_enabled() {
+            let original_path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            for warning in shred_path(path, &original_path, policy, &self.workers)? {
+                self.report(Warning::Shred(warning));
+            }
+        } else {
+            self.file_system.remove(path)?;
+        }
+        Ok(size)
+    }
+
+    /// Permanently deletes an item from the trash by its ID, regardless of its grace period.
+    pub fn delete_item_by_id(&self, id: &str) -> Result<()> {
+        // The pre-hooks run before the trash is locked for writing, so they may run rrm
+        let context = {
+            let _lock = self.lock_shared()?;
+            self.load_item(id)?.hook_context()
+        };
+        self.run_hooks(HookEvent::PrePurge, &context)?;
+
+        let _lock = self.lock_exclusive()?;
+        let item = self.load_item(id)?;
+        let progress = self.workers.progress();
+        let _progress = progress.start("Purging", 1);
+        progress.begin_item(item.size.unwrap_or_default());
+        self.delete_item_permanently(item)
+    }
+
+    /// Permanently deletes the items that reached their deletion date, or every item if
+    /// `immediate` is set. Pinned items are kept unless `force` is set. Items that cannot
+    /// be deleted are skipped and returned together as [`Error::ItemsFailed`].
+    pub fn clean_trash(&self, immediate: bool, force: bool) -> Result<()> {
+        let now = self.now();
+        // An item is due from its deletion date on, not only once it has passed
+        let is_due = |item: &TrashItem| immediate || item.deletion_date <= now;
+
+        // The pre-hooks run before the trash is locked for writing, so they may run rrm
+        let items = {
+            let _lock = self.lock_shared()?;
+            self.scan_items()?
+        };
+        let mut approved = HashSet::new();
+        let mut failures = Vec::new();
+        for item in items
+            .iter()
+            .filter(|item| (force || !item.pinned) && is_due(item))
+        {
+            match self.run_hooks(HookEvent::PrePurge, &item.hook_context()) {
+                Ok(()) => {
+                    approved.insert(item.id.clone());
+                }
+                Err(error) => failures.push(ItemFailure {
+                    item: item.id.clone(),
+                    error,
+                }),
+            }
+        }
+
+        let _lock = self.lock_exclusive()?;
+        let items = self.scan_items()?;
+        info!("Trash items found: {}", items.len());
+        let progress = self.workers.progress();
+        let _progress = progress.start("Purging", approved.len() as u64);
+        let mut items_deleted = 0;
+        let mut items_kept = Vec::new();
+        let mut items_due = failures.len();
+        for item in items {
+            if item.pinned && !force {
+                info!("Keeping pinned item with id: {}", item.id);
+                items_kept.push(item);
+                continue;
+            }
+
+            // Items refused by their pre-hooks, or due since they ran, wait for next time
+            if is_due(&item) && !approved.contains(&item.id) {
+                continue;
+            }
+            if is_due(&item) && self.file_system.exists(&item.path) {
+                info!(
+                    "Deleting item with id: {} and original path: {}",
+                    item.id,
+                    item.format_original_path()
+                );
+                items_due += 1;
+                let id = item.id.clone();
+                progress.begin_item(item.size.unwrap_or_default());
+                let result = self.delete_item_permanently(item);
+                progress.end_item();
+                match result {
+                    Ok(()) => items_deleted += 1,
+                    Err(error) => failures.push(ItemFailure { item: id, error }),
+                }
+            } else {
+                items_kept.push(item);
+            }
+        }
+        info!("Items deleted from trash: {}", items_deleted);
+
+        for item in items_kept {
+            let due = item
+                .trash_date
+                .is_some_and(|trash_date| self.compression_policy.is_due(trash_date, now));
+            let attempted = matches!(
+                self.xattr_manager.get_attr(&item.path, COMPRESSED_ATTR),
+                Ok(Some(_))
+            );
+            if !due || attempted || item.encrypted || item.deduplicated || item.kind() == "Symlink"
+            {
+                continue;
+            }
+
+            // Compression only saves space, failing to do it must not stop the cleaning
+            info!("Compressing item with id: {}", item.id);
+            if let Err(e) = self.compress_item(&item.path, &item.original_path) {
+                warn!("Failed to compress item with id: {}: {}", item.id, e);
+            }
+        }
+        Error::from_failures(failures, items_due)
+    }
+
+    /// Replaces a trashed file or directory with a compressed copy carrying the same
+    /// attributes. The uncompressed copy is shredded if the shred policy selects it.
+    fn compress_item(&self, item_path: &Path, original_path: &Path) -> Result<()> {
+        if let Some(plan) = &self.plan {
+            plan.record(PlannedAction::Compress {
+                path: item_path.to_path_buf(),
+            });
+            return Ok(());
+        }
+        let id = item_path.file_name().unwrap_or_default().to_string_lossy();
+        let compressed_path = self.trash_dir.join(format!(".compressing-{}", id));
+        let uncompressed_path = self.trash_dir.join(format!(".uncompressed-{}", id));
+
+        let compressed = compress::compress(item_path, &compressed_path).and_then(|compression| {
+            if item_size(&compressed_path) >= item_size(item_path) {
+                return Ok(None);
+            }
+            for key in ITEM_ATTRS {
+                if let Some(value) = self.xattr_manager.get_attr(item_path, key)? {
+                    self.xattr_manager.set_attr(&compressed_path, key, &value)?;
+                }
+            }
+            self.xattr_manager.set_attr(
+                &compressed_path,
+                COMPRESSED_ATTR,
+                compression.as_str().as_bytes(),
+            )?;
+            Ok(Some(compression))
+        });
+        match compressed {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                fs::remove_file(&compressed_path)?;
+                return self
+                    .xattr_manager
+                    .set_attr(item_path, COMPRESSED_ATTR, INCOMPRESSIBLE);
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&compressed_path);
+                return Err(e);
+            }
+        }
+
+        // Both copies are hidden while they are swapped, so a crash leaves a leftover for
+        // fsck rather than a broken item
+        fs::rename(item_path, &uncompressed_path)?;
+        if let Err(e) = fs::rename(&compressed_path, item_path) {
+            let _ = fs::rename(&uncompressed_path, item_path);
+            let _ = fs::remove_file(&compressed_path);
+            return Err(e.into());
+        }
+        // The item is compressed, an uncompressed copy that cannot be removed is only a
+        // leftover for fsck
+        match shred_path(
+            &uncompressed_path,
+            original_path,
+            &self.shred_policy,
+            &self.workers,
+        ) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    self.report(Warning::Shred(warning));
+                }
+            }
+            Err(e) => error!(
+                "Failed to remove the uncompressed copy of {}: {}",
+                escape_os_str(original_path.as_os_str()),
+                e
+            ),
+        }
+        Ok(())
+    }
+
+    /// Deletes a trashed item and its metadata, shredding the files selected by the shred
+    /// policy. Items that are already gone, e.g. removed by hand or by an older rrm that
+    /// did not lock the trash, are not an error. The pre-hooks must have agreed already.
+    fn delete_item_permanently(&self, item: TrashItem) -> Result<()> {
+        let attrs_path = self.attrs_path(&item.path);
+        let policy = if item.shred {
+            self.shred_policy.clone().shred_all()
+        } else {
+            self.shred_policy.clone()
+        };
+        if let Some(plan) = self.plan.as_ref().filter(|_| policy.is_enabled()) {
+            plan.record(PlannedAction::Shred {
+                path: item.path.clone(),
+                size: self.item_size(&item.path)?,
+            });
+        } else if policy.is_enabled() {
+            match shred_path(&item.path, &item.original_path, &policy, &self.workers) {
+                Ok(warnings) => {
+                    for warning in warnings {
+                        self.report(Warning::Shred(warning));
+                    }
+                }
+                result => ignore_not_found(result.map(|_| ()))?,
+            }
+        } else {
+            ignore_not_found(self.file_system.remove(&item.path).map_err(Error::from))?;
+        }
+        if attrs_path != item.path {
+            ignore_not_found(self.file_system.remove(&attrs_path).map_err(Error::from))?;
+        }
+        let id = item.path.file_name().unwrap_or_default();
+        let event = Event {
+            id: Some(id.to_string_lossy().into_owned()),
+            size: item.size,
+            ..Event::new(Action::Purge)
+        };
+        self.record(if item.encrypted {
+            event
+        } else {
+            event.with_path(&item.original_path)
+        });
+        self.remove_checksums(id)?;
+        if item.deduplicated {
+            for warning in self.delete_manifest(id, &item.original_path, &policy)? {
+                self.report(Warning::Shred(warning));
+            }
+        }
+        self.queue_post_hooks(HookEvent::PostPurge, item.hook_context());
+        Ok(())
+    }
+
+    /// Takes a shared lock on the trash, for operations that only read it.
+    /// The lock is released when the returned guard is dropped.
+    fn lock_shared(&self) -> Result<TrashLock<'_, T>> {
+        self.lock(false)
+    }
+
+    /// Takes an exclusive lock on the trash, for operations that add, move or delete items.
+    /// The lock is released when the returned guard is dropped.
+    fn lock_exclusive(&self) -> Result<TrashLock<'_, T>> {
+        self.check_file_system()?;
+        self.lock(true)
+    }
+
+    /// Fails if a policy that only works on the real filesystem is enabled while another
+    /// one is set, see [`FileSystem`]. A dry run only plans those steps.
+    fn check_file_system(&self) -> Result<()> {
+        if !self.custom_file_system || self.plan.is_some() {
+            return Ok(());
+        }
+        if self.dedup
+            || self.compression_policy.is_enabled()
+            || self.encryption_policy.is_enabled()
+            || self.shred_policy.is_enabled()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compression, deduplication, encryption and shredding need the real filesystem",
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn lock(&self, exclusive: bool) -> Result<TrashLock<'_, T>> {
+        Ok(TrashLock {
+            lock: Some(self.file_system.lock(&self.trash_dir, exclusive)?),
+            trash_manager: self,
+        })
+    }
+
+    /// Returns the path of the trashed item `id` and the path carrying its metadata.
+    fn locate_item(&self, id: &str) -> Result<(PathBuf, PathBuf)> {
+        // Only the entries of the trash directory itself are items, and not its hidden
+        // stores, so an id like `../file` cannot reach outside of the trash
+        let mut components = Path::new(id).components();
+        let is_entry = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        );
+        if !is_entry || id.starts_with('.') {
+            return Err(Error::ItemNotFound(id.to_string()));
+        }
+
+        let item_path = self.trash_dir.join(id);
+        if !self.file_system.exists(&item_path) {
+            return Err(Error::ItemNotFound(id.to_string()));
+        }
+
+        let attrs_path = self.attrs_path(&item_path);
+        Ok((item_path, attrs_path))
+    }
+
+    fn read_original_path(&self, id: &str, attrs_path: &Path) -> Result<PathBuf> {
+        let original_path = self
+            .xattr_manager
+            .get_attr(attrs_path, ORIGINAL_PATH_ATTR)?
+            .ok_or_else(|| Error::MissingAttribute {
+                attr: ORIGINAL_PATH_ATTR.to_string(),
+                id: id.to_string(),
+            })?;
+        self.decode_original_path(original_path)
+    }
+
+    /// Converts the raw value of the original path attribute, decrypting it if needed.
+    fn decode_original_path(&self, value: Vec<u8>) -> Result<PathBuf> {
+        let value = if crypto::is_encrypted(&value) {
+            self.encryption_policy.decrypt_bytes(&value)?
+        } else {
+            value
+        };
+        Ok(PathBuf::from(OsString::from_vec(value)))
+    }
+
+    fn read_deletion_date(&self, id: &str, attrs_path: &Path) -> Result<DateTime<Utc>> {
+        let deletion_date = self
+            .xattr_manager
+            .get_attr(attrs_path, DELETION_DATE_ATTR)?
+            .ok_or_else(|| Error::MissingAttribute {
+                attr: DELETION_DATE_ATTR.to_string(),
+                id: id.to_string(),
+            })?;
+
+        std::str::from_utf8(&deletion_date)
+            .ok()
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| date.with_timezone(&Utc))
+            .ok_or_else(|| Error::InvalidAttribute {
+                attr: DELETION_DATE_ATTR.to_string(),
+                id: id.to_string(),
+            })
+    }
+
+    /// Reads a boolean attribute, which is only set when true.
+    fn read_flag(&self, attrs_path: &Path, key: &str) -> bool {
+        matches!(
+            self.xattr_manager.get_attr(attrs_path, key),
+            Ok(Some(val)) if val == b"true"
+        )
+    }
+
+    fn read_trash_date(&self, attrs_path: &Path) -> Option<DateTime<Utc>> {
+        match self.xattr_manager.get_attr(attrs_path, TRASH_DATE_ATTR) {
+            Ok(Some(val)) => DateTime::parse_from_rfc3339(std::str::from_utf8(&val).ok()?)
+                .ok()
+                .map(|date| date.with_timezone(&Utc)),
+            _ => None,
+        }
+    }
+
+    fn read_compression(&self, attrs_path: &Path) -> Option<Compression> {
+        match self.xattr_manager.get_attr(attrs_path, COMPRESSED_ATTR) {
+            Ok(Some(val)) => Compression::parse(&val),
+            _ => None,
+        }
+    }
+
+    fn read_size(&self, attrs_path: &Path) -> Option<u64> {
+        match self.xattr_manager.get_attr(attrs_path, SIZE_ATTR) {
+            Ok(Some(val)) => std::str::from_utf8(&val).ok()?.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Removes an optional attribute, which fails if the attribute is not set.
+    fn remove_attr_if_present(&self, path: &Path, key: &str) -> Result<()> {
+        if self.xattr_manager.get_attr(path, key)?.is_some() {
+            self.xattr_manager.remove_attr(path, key)?;
+        }
+        Ok(())
+    }
+
+    /// Moves a trashed item to `destination`, dropping its rrm metadata and decrypting it.
+    fn move_out_of_trash(
+        &self,
+        item_path: &Path,
+        attrs_path: &Path,
+        destination: &Path,
+    ) -> Result<()> {
+        if self.file_system.exists(destination) {
+            return Err(Error::PathAlreadyExists(escape_os_str(
+                destination.as_os_str(),
+            )));
+        }
+
+        if let Some(parent) = destination.parent() {
+            if !self.file_system.exists(parent) {
+                warn!(
+                    "Parent directory of the original path does not exist: {}",
+                    parent.display()
+                );
+                return Err(Error::InvalidOriginalPath(escape_os_str(
+                    destination.as_os_str(),
+                )));
+            }
+        }
+
+        // Fail before moving anything if the item cannot be decrypted
+        let encrypted = self.read_flag(attrs_path, ENCRYPTED_ATTR);
+        if encrypted {
+            self.encryption_policy.key()?;
+        }
+
+        if let Some(compression) = self.read_compression(attrs_path) {
+            if let Some(plan) = &self.plan {
+                plan.record(PlannedAction::Decompress {
+                    from: item_path.to_path_buf(),
+                    to: destination.to_path_buf(),
+                });
+                return Ok(());
+            }
+            // The decompressed copy is written to the destination, its metadata goes away
+            // with the compressed copy
+            compress::decompress(item_path, compression, destination)?;
+            self.file_system.remove(item_path)?;
+            return Ok(());
+        }
+        if self.read_flag(attrs_path, DEDUP_ATTR) {
+            return self.restore_deduplicated(item_path, destination);
+        }
+
+        if attrs_path != item_path {
+            // The metadata lives on a sidecar, so the item itself has nothing to clean up
+            self.move_path(item_path, destination)?;
+            self.file_system.remove(attrs_path)?;
+            return Ok(());
+        }
+
+        // The attributes go once the item is out, so a failed move leaves it intact
+        self.move_path(item_path, destination)?;
+        self.remove_item_attrs(destination)?;
+        if let Some(plan) = self.plan.as_ref().filter(|_| encrypted) {
+            plan.record(PlannedAction::Decrypt {
+                path: destination.to_path_buf(),
+            });
+        } else if encrypted {
+            self.encryption_policy.decrypt_tree(destination)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the rrm attributes of an item that left the trash, or failed to enter it.
+    fn remove_item_attrs(&self, item_path: &Path) -> Result<()> {
+        self.xattr_manager
+            .remove_attr(item_path, ORIGINAL_PATH_ATTR)?;
+        self.xattr_manager
+            .remove_attr(item_path, DELETION_DATE_ATTR)?;
+        self.remove_attr_if_present(item_path, PINNED_ATTR)?;
+        self.remove_attr_if_present(item_path, SIZE_ATTR)?;
+        self.remove_attr_if_present(item_path, SHRED_ATTR)?;
+        self.remove_attr_if_present(item_path, ENCRYPTED_ATTR)?;
+        self.remove_attr_if_present(item_path, TRASH_DATE_ATTR)?;
+        self.remove_attr_if_present(item_path, COMPRESSED_ATTR)?;
+        self.remove_attr_if_present(item_path, DEDUP_ATTR)?;
+        self.remove_attr_if_present(item_path, CHECKSUM_ATTR)
+    }
+
+    /// Moves an item whose encryption failed in the trash back to `path`, decrypting the
+    /// files that were encrypted already. Fails without moving the item if they cannot be
+    /// decrypted, it then stays in the trash, where it is restored like any encrypted item.
+    fn put_back(&self, path: &Path, item_path: &Path, id: &str) -> Result<()> {
+        self.encryption_policy.decrypt_tree(item_path)?;
+        self.move_path(item_path, path)?;
+        self.remove_item_attrs(path)?;
+        self.remove_checksums(OsStr::new(id))
+    }
+
+    /// Returns the path of the sidecar file holding the metadata of the item `id`.
+    fn sidecar_path(&self, id: &OsStr) -> PathBuf {
+        self.trash_dir.join(METADATA_DIR).join(id)
+    }
+
+    /// Returns the path carrying the extended attributes of a trashed item: the item
+    /// itself, or its sidecar file when the item is a symlink.
+    fn attrs_path(&self, item_path: &Path) -> PathBuf {
+        match item_path.file_name() {
+            Some(id) if self.is_symlink(item_path) => self.sidecar_path(id),
+            _ => item_path.to_path_buf(),
+        }
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.file_system
+            .metadata(path)
+            .is_ok_and(|meta| meta.kind == FileKind::Symlink)
+    }
+
+    /// Moves `from` to `to`. Across filesystems, the item is copied under a hidden name
+    /// next to `to` first, so a copy that fails halfway (e.g. for lack of space) is
+    /// removed without ever showing up at `to`, and the original is only removed once the
+    /// copy is complete. If the original then cannot be removed, the copy is removed
+    /// instead, unless the original is a directory that may be partly gone: that fails with
+    /// [`Error::IncompleteMove`] and leaves the copy.
+    fn move_path(&self, from: &Path, to: &Path) -> Result<()> {
+        match self.file_system.rename(from, to) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+            result => return Ok(result?),
+        }
+
+        let partial = hidden_sibling(to, "partial");
+        if let Err(e) = self
+            .file_system
+            .copy(from, &partial)
+            .and_then(|_| self.file_system.rename(&partial, to))
+        {
+            let _ = self.file_system.remove(&partial);
+            return Err(e.into());
+        }
+        if let Err(e) = self.file_system.remove(from) {
+            // A file or symlink is left whole and the copy can go, but part of a directory
+            // may already be gone
+            match self.file_system.metadata(from) {
+                Ok(meta) if meta.kind == FileKind::Directory => {
+                    return Err(Error::IncompleteMove {
+                        from: escape_os_str(from.as_os_str()),
+                        to: escape_os_str(to.as_os_str()),
+                        source: e,
+                    });
+                }
+                _ => {
+                    let _ = self.file_system.remove(to);
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns `path` and every path below it without following symlinks, parents first.
+    fn walk(&self, path: &Path) -> Result<Vec<(PathBuf, Metadata)>> {
+        let mut entries = vec![(path.to_path_buf(), self.file_system.metadata(path)?)];
+        let mut next = 0;
+        while next < entries.len() {
+            let (dir, meta) = &entries[next];
+            next += 1;
+            if meta.kind == FileKind::Directory {
+                let children = self.file_system.read_dir(dir)?;
+                for child in children {
+                    let meta = self.file_system.metadata(&child)?;
+                    entries.push((child, meta));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the regular files of an item along with the path they had before being
+    /// trashed, like [`matching_files`] but through the manager's filesystem.
+    fn walk_files(&self, path: &Path, original_path: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut files: Vec<_> = self
+            .walk(path)?
+            .into_iter()
+            .filter(|(_, meta)| meta.kind == FileKind::File)
+            .map(|(file, _)| {
+                let relative = file.strip_prefix(path).unwrap_or(Path::new(""));
+                let original = join_relative(original_path, relative);
+                (file, original)
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    /// Returns the number of bytes used by the files of an item, like [`item_size`].
+    fn item_size(&self, path: &Path) -> Result<u64> {
+        Ok(self
+            .walk(path)?
+            .iter()
+            .filter(|(_, meta)| meta.kind != FileKind::Directory)
+            .map(|(_, meta)| meta.len)
+            .sum())
+    }
+
+    /// Makes a path absolute by resolving its parent directory only. The final component
+    /// is kept as is, so a symlink is recorded by its own location rather than by its
+    /// target.
+    fn absolute_path(&self, path: &Path) -> Result<PathBuf> {
+        let Some(file_name) = path.file_name() else {
+            // Paths like `.` or `foo/..` have no final component to preserve
+            return Ok(self.file_system.canonicalize(path)?);
+        };
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                self.file_system.canonicalize(parent)?
+            }
+            _ => env::current_dir()?,
+        };
+        Ok(parent.join(file_name))
+    }
+}
+
+/// Checks whether a path exists without following symlinks, so dangling links count too.
+pub fn path_exists(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
+
+/// Joins a path relative to an item, where an empty path is the item itself.
+fn join_relative(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relative)
+    }
+}
+
+fn ignore_not_found(result: Result<()>) -> Result<()> {
+    match result {
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Formats a number of bytes with binary units, e.g. `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Parses a number of bytes with an optional unit, e.g. `512`, `10K`, `1.5MiB` or `2GB`.
+/// Units are binary multiples, so `1K` and `1KiB` are both 1024 bytes.
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "'{}' is not a valid size (e.g. 512K, 10M or 1G)",
+                value
+            ))
+        }
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok((number * multiplier as f64) as u64),
+        _ => Err(format!(
+            "'{}' is not a valid size (e.g. 512K, 10M or 1G)",
+            value
+        )),
+    }
+}
+
+/// Returns the number of bytes used by the files of an item, walking directories without
+/// following symlinks.
+pub fn item_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| !entry.file_type().is_dir())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// Returns a hidden path next to `path` for work in progress on it, e.g. `.name.partial`
+/// for `name`, so it is never mistaken for the finished path.
+pub(crate) fn hidden_sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Removes a file, symlink or directory tree without following symlinks, with one thread
+/// per CPU.
+pub fn remove_path(path: &Path) -> Result<()> {
+    Ok(Workers::default().remove(path)?)
+}
+
+/// Returns the name of the user with id `uid`, or `None` if it has no name.
+pub fn user_name(uid: u32) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0; 4096];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: `passwd` and `buffer` outlive the call, which only writes within them
+    let found = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    } == 0
+        && !result.is_null();
+    if !found {
+        return None;
+    }
+    // SAFETY: on success, `pw_name` points to a NUL-terminated string inside `buffer`
+    let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Escapes a string for display without losing information: bytes that are not valid UTF-8
+/// are written as `\xNN` and backslashes are doubled, so the output maps back to the
+/// original bytes unambiguously.
+pub fn escape_os_str(s: &OsStr) -> String {
+    let mut escaped = String::new();
+    for chunk in s.as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '\\' {
+                escaped.push_str("\\\\");
+            } else {
+                escaped.push(c);
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(escaped, "\\x{:02x}", byte);
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
     use super::*;
-    use mockall::{mock, predicate::in_iter};
+    use crate::{
+        clock::FakeClock,
+        error::ErrorKind,
+        filesystem::{MemoryFileSystem, Operation},
+    };
+    use checksum::CHECKSUMS_DIR;
+    use mockall::mock;
     use tempfile::{tempdir, NamedTempFile};
 
+    /// Checksum recorded for empty files.
+    const EMPTY_SHA256: &[u8] = b"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
     mock! {
         pub XattrManager {}
         impl ExtendedAttributes for XattrManager {
-            fn set_attr(&self, path: &std::path::Path, key: &str, value: &str) -> crate::Result<()>;
-            fn get_attr(&self, path: &std::path::Path, key: &str) -> crate::Result<Option<String>>;
+            fn set_attr(&self, path: &std::path::Path, key: &str, value: &[u8]) -> crate::Result<()>;
+            fn get_attr(&self, path: &std::path::Path, key: &str) -> crate::Result<Option<Vec<u8>>>;
             fn remove_attr(&self, path: &std::path::Path, key: &str) -> crate::Result<()>;
         }
     }
 
+    /// Returns the items in a memory trash, without its hidden directories.
+    fn trashed_paths(file_system: &MemoryFileSystem) -> Result<Vec<PathBuf>> {
+        Ok(file_system
+            .read_dir(Path::new("/trash"))?
+            .into_iter()
+            .filter(|path| !path.file_name().unwrap().as_bytes().starts_with(b"."))
+            .collect())
+    }
+
     #[test]
     fn test_trash_items() -> Result<()> {
         let deletion_date = Utc::now();
-        let temp_dir = tempdir()?;
-        let trash_dir = temp_dir.path().to_path_buf();
-        assert!(trash_dir.exists());
-
-        // Create two temporary files
-        // Create a temporary file
-        let temp_file = NamedTempFile::new()?;
-        let original_path = temp_file.path().to_path_buf();
-        assert!(original_path.exists());
-        let original_path_canonicalized = original_path.canonicalize()?;
-        let original_path_str = original_path_canonicalized.to_str().unwrap().to_string();
-
-        // Create a second temporary file
-        let temp_file2 = NamedTempFile::new()?;
-        let original_path2 = temp_file2.path().to_path_buf();
-        assert!(original_path2.exists());
-        let original_path2_canonicalized = original_path2.canonicalize()?;
-        let original_path2_str = original_path2_canonicalized.to_str().unwrap().to_string();
-
-        let mut xattr_manager = MockXattrManager::new();
-        xattr_manager
-            .expect_set_attr()
-            .with(
-                in_iter(vec![original_path.clone(), original_path2.clone()]),
-                in_iter(vec![ORIGINAL_PATH_ATTR, DELETION_DATE_ATTR]),
-                in_iter(vec![
-                    original_path_str,
-                    original_path2_str,
-                    deletion_date.to_rfc3339().to_string(),
-                ]),
-            )
-            .times(4)
-            .returning(|_, _, _| Ok(()));
+        let (file_system, trash_manager) = memory_trash(None);
+        let paths = vec![
+            PathBuf::from("/home/me/a.txt"),
+            PathBuf::from("/home/me/b.txt"),
+        ];
+        for path in &paths {
+            file_system.add_file(path, "");
+        }
 
-        let trash_manager = TrashManager::new(trash_dir.clone(), xattr_manager);
-        trash_manager.trash_items(vec![original_path, original_path2], deletion_date)?;
+        trash_manager.trash_items(paths.clone(), deletion_date)?;
 
-        // Check if the files were moved to the trash
-        assert_eq!(trash_dir.read_dir()?.count(), 2);
+        // Both files were moved to the trash along with their attributes
+        let mut original_paths = Vec::new();
+        for path in trashed_paths(&file_system)? {
+            let attr = |key: &str| file_system.get_attr(&path, key);
+            assert_eq!(
+                attr(DELETION_DATE_ATTR)?,
+                Some(deletion_date.to_rfc3339().into_bytes())
+            );
+            assert_eq!(attr(SIZE_ATTR)?, Some(b"0".to_vec()));
+            assert_eq!(attr(CHECKSUM_ATTR)?, Some(EMPTY_SHA256.to_vec()));
+            assert!(attr(TRASH_DATE_ATTR)?.is_some());
+            original_paths.push(attr(ORIGINAL_PATH_ATTR)?.unwrap());
+        }
+        original_paths.sort();
+        let expected: Vec<_> = paths.iter().map(|p| p.as_os_str().as_bytes()).collect();
+        assert_eq!(original_paths, expected);
+        assert!(paths.iter().all(|path| !file_system.exists(path)));
         Ok(())
     }
 
     #[test]
     fn test_list_items() -> Result<()> {
         let deletion_date = Utc::now();
-        let temp_dir = tempdir()?;
-        let trash_dir = temp_dir.path().to_path_buf();
-        assert!(trash_dir.exists());
+        let (file_system, trash_manager) = memory_trash(None);
+        file_system.add_file("/home/me/a.txt", "a");
+        file_system.add_file("/home/me/b.txt", "b");
 
-        // Create two temporary files
-        // Create a temporary file
-        let temp_file = NamedTempFile::new()?;
-        let original_path = temp_file.path().to_path_buf();
-        assert!(original_path.exists());
-        let original_path_canonicalized = original_path.canonicalize()?;
-        let original_path_str = original_path_canonicalized.to_str().unwrap().to_string();
-
-        // Create a second temporary file
-        let temp_file2 = NamedTempFile::new()?;
-        let original_path2 = temp_file2.path().to_path_buf();
-        assert!(original_path2.exists());
-        let original_path2_canonicalized = original_path2.canonicalize()?;
-        let original_path2_str = original_path2_canonicalized.to_str().unwrap().to_string();
+        trash_manager.trash_items(
+            vec![
+                PathBuf::from("/home/me/a.txt"),
+                PathBuf::from("/home/me/b.txt"),
+            ],
+            deletion_date,
+        )?;
 
-        let mut xattr_manager = MockXattrManager::new();
-        xattr_manager
-            .expect_set_attr()
-            .with(
-                in_iter(vec![original_path.clone(), original_path2.clone()]),
-                in_iter(vec![ORIGINAL_PATH_ATTR, DELETION_DATE_ATTR]),
-                in_iter(vec![
-                    original_path_str,
-                    original_path2_str,
-                    deletion_date.to_rfc3339().to_string(),
-                ]),
-            )
-            .times(4)
-            .returning(|_, _, _| Ok(()));
+        let mut items = trash_manager.list_items()?;
+        items.sort_by(|a, b| a.original_path.cmp(&b.original_path));
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].original_path, Path::new("/home/me/a.txt"));
+        assert_eq!(items[1].original_path, Path::new("/home/me/b.txt"));
+        for item in &items {
+            assert_eq!(item.deletion_date, deletion_date);
+            assert_eq!(item.size, Some(1));
+            assert_eq!(item.file_kind, FileKind::File);
+        }
+        Ok(())
+    }
 
-        xattr_manager
-            .expect_get_attr()
-            .times(4)
-            .returning(move |_, key| match key {
-                DELETION_DATE_ATTR => Ok(Some(deletion_date.to_rfc3339())),
-                _ => Ok(Some("some_path".to_string())),
-            });
+    #[test]
+    fn clean_trash_delete_old_file() -> Result<()> {
+        let deletion_date_past = Utc::now() - chrono::Duration::days(1);
+        let (file_system, trash_manager) = memory_trash(None);
+        file_system.add_file("/home/me/notes.md", "content");
+        trash_manager.trash_items(vec![PathBuf::from("/home/me/notes.md")], deletion_date_past)?;
+        let items = trash_manager.list_items()?;
+        assert_eq!(items.len(), 1);
+
+        trash_manager.clean_trash(false, false)?;
+
+        assert!(trash_manager.list_items()?.is_empty());
+        assert!(!file_system.exists(&items[0].path));
+        assert!(trashed_paths(&file_system)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn trash_symlink_records_link_path() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let target = Path::new("/home/me/target.txt");
+        let link = Path::new("/home/me/link.txt");
+        file_system.add_file(target, "content");
+        file_system.add_symlink(link, target);
 
-        let trash_manager = TrashManager::new(trash_dir.clone(), xattr_manager);
-        trash_manager.trash_items(vec![original_path, original_path2], deletion_date)?;
+        trash_manager.trash_items(vec![link.to_path_buf()], Utc::now())?;
 
+        // The link was moved, the target was left untouched
+        assert!(!file_system.exists(link));
+        assert_eq!(file_system.read(target)?, b"content");
         let items = trash_manager.list_items()?;
-        assert_eq!(trash_dir.read_dir()?.count(), 2);
-        assert_eq!(items.len(), 2);
-        assert_eq!(items[0].deletion_date, deletion_date);
-        assert_eq!(items[1].deletion_date, deletion_date);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].original_path, link);
+        assert_eq!(items[0].file_kind, FileKind::Symlink);
+        assert_eq!(
+            file_system.metadata(&items[0].path)?.kind,
+            FileKind::Symlink
+        );
+        // Its attributes are kept on a sidecar file, not on the link's target
+        let sidecar = Path::new("/trash").join(METADATA_DIR).join(&items[0].id);
+        assert_eq!(
+            file_system.get_attr(&sidecar, ORIGINAL_PATH_ATTR)?,
+            Some(link.as_os_str().as_bytes().to_vec())
+        );
+        assert_eq!(file_system.get_attr(target, ORIGINAL_PATH_ATTR)?, None);
         Ok(())
     }
 
     #[test]
-    fn clean_trash_delete_old_file() -> Result<()> {
+    fn trash_items_non_utf8_name() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+
+        // A file name that is not valid UTF-8
+        let name = OsStr::from_bytes(b"notes-\xff.md");
+        let original_path = Path::new("/home/me").join(name);
+        file_system.add_file(&original_path, "content");
+
+        trash_manager.trash_items(vec![original_path.clone()], Utc::now())?;
+
+        assert!(!file_system.exists(&original_path));
+        let items = trash_manager.list_items()?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].original_path, original_path);
+        Ok(())
+    }
+
+    #[test]
+    fn escape_os_str_is_lossless() {
+        let name = OsStr::from_bytes(b"a\\b-\xff\xfe-\xc3\xa9");
+        assert_eq!(escape_os_str(name), "a\\\\b-\\xff\\xfe-\u{e9}");
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("1.5MiB"), Ok(3 * 512 * 1024));
+        assert_eq!(parse_size("2 GB"), Ok(2 << 30));
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn restore_item_to_directory() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let original_path = Path::new("/home/me/notes.md");
+        file_system.add_file(original_path, "content");
+        file_system.add_dir("/home/me/restored");
+
+        trash_manager.trash_items(vec![original_path.to_path_buf()], Utc::now())?;
+        let items = trash_manager.list_items()?;
+        trash_manager.restore_item_to(&items[0].id, Path::new("/home/me/restored"))?;
+
+        let restored = Path::new("/home/me/restored/notes.md");
+        assert_eq!(file_system.read(restored)?, b"content");
+        for key in ITEM_ATTRS {
+            assert_eq!(file_system.get_attr(restored, key)?, None);
+        }
+        assert!(!file_system.exists(original_path));
+        assert!(trash_manager.list_items()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn clean_trash_keeps_pinned_items_unless_forced() -> Result<()> {
         let deletion_date_past = Utc::now() - chrono::Duration::days(1);
+        let (file_system, trash_manager) = memory_trash(None);
+        file_system.add_file("/home/me/notes.md", "content");
+        trash_manager.trash_items(vec![PathBuf::from("/home/me/notes.md")], deletion_date_past)?;
+        let id = trash_manager.list_items()?[0].id.clone();
+        trash_manager.set_pinned(&id, true)?;
+
+        // Neither the expired date nor an immediate clean remove a pinned item
+        trash_manager.clean_trash(false, false)?;
+        trash_manager.clean_trash(true, false)?;
+        assert_eq!(trash_manager.list_items()?.len(), 1);
+
+        trash_manager.clean_trash(false, true)?;
+        assert!(trash_manager.list_items()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn reschedule_unpins_and_sets_the_deletion_date() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        file_system.add_file("/home/me/notes.md", "content");
+        trash_manager.trash_items(vec![PathBuf::from("/home/me/notes.md")], Utc::now())?;
+        let id = trash_manager.list_items()?[0].id.clone();
+        trash_manager.set_pinned(&id, true)?;
 
-        let temp_dir = tempdir()?;
-        let trash_dir = temp_dir.path().to_path_buf();
-        assert!(trash_dir.exists());
+        let deletion_date = Utc::now() - chrono::Duration::days(1);
+        trash_manager.reschedule(&id, deletion_date)?;
+        let item = trash_manager.get_item(&id)?;
+        assert!(!item.pinned);
+        assert_eq!(item.deletion_date.timestamp(), deletion_date.timestamp());
+
+        trash_manager.clean_trash(false, false)?;
+        assert!(trash_manager.list_items()?.is_empty());
+        Ok(())
+    }
+
+    /// Each manager opens its own handle on the trash directory, so threads contend for the
+    /// lock exactly like separate `rrm` processes do.
+    #[test]
+    fn clean_waits_for_exclusive_lock() -> Result<()> {
+        let deletion_date_past = Utc::now() - chrono::Duration::days(1);
+        let trash = tempdir()?;
+        let trash_dir = trash.path().to_path_buf();
+
+        let past_date_manager = || {
+            let mut xattr_manager = MockXattrManager::new();
+            xattr_manager.expect_set_attr().returning(|_, _, _| Ok(()));
+            xattr_manager
+                .expect_get_attr()
+                .returning(move |_, key| match key {
+                    DELETION_DATE_ATTR => Ok(Some(deletion_date_past.to_rfc3339().into_bytes())),
+                    PINNED_ATTR => Ok(None),
+                    _ => Ok(Some(b"some_path".to_vec())),
+                });
+            TrashManager::new(trash_dir.clone(), xattr_manager)
+        };
 
-        // Create two temporary files
-        // Create a temporary file
         let temp_file = NamedTempFile::new()?;
-        let original_path = temp_file.path().to_path_buf();
-        assert!(original_path.exists());
-        let original_path_canonicalized = original_path.canonicalize()?;
-        let original_path_str = original_path_canonicalized.to_str().unwrap().to_string();
+        let holder = past_date_manager();
+        holder.trash_items(vec![temp_file.path().to_path_buf()], deletion_date_past)?;
 
-        let mut xattr_manager = MockXattrManager::new();
-        xattr_manager
-            .expect_set_attr()
-            .with(
-                in_iter(vec![original_path.clone()]),
-                in_iter(vec![ORIGINAL_PATH_ATTR, DELETION_DATE_ATTR]),
-                in_iter(vec![
-                    original_path_str,
-                    deletion_date_past.to_rfc3339().to_string(),
-                ]),
-            )
-            .times(2)
-            .returning(|_, _, _| Ok(()));
+        // Another process is in the middle of e.g. a restore
+        let lock = holder.lock_exclusive()?;
+        let (tx, rx) = std::sync::mpsc::channel();
+        let cleaner = past_date_manager();
+        let handle = std::thread::spawn(move || tx.send(cleaner.clean_trash(false, false)));
 
+        let timeout = std::time::Duration::from_millis(200);
+        assert!(rx.recv_timeout(timeout).is_err());
+        assert_eq!(trash_dir.read_dir()?.count(), 1);
+
+        drop(lock);
+        rx.recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap()?;
+        handle.join().unwrap().unwrap();
+        assert_eq!(trash_dir.read_dir()?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn delete_vanished_item_is_not_an_error() -> Result<()> {
+        let trash = tempdir()?;
+        let trash_manager = TrashManager::new(trash.path().to_path_buf(), MockXattrManager::new());
+
+        // The item was listed, but another process removed it before it could be deleted
+        let item = TrashItem {
+            id: "gone".to_string(),
+            path: trash.path().join("gone"),
+            original_path: PathBuf::from("/tmp/gone"),
+            deletion_date: Utc::now(),
+            pinned: false,
+            shred: false,
+            encrypted: false,
+            size: None,
+            trash_date: None,
+            compression: None,
+            deduplicated: false,
+            file_kind: FileKind::File,
+        };
+        trash_manager.delete_item_permanently(item)
+    }
+
+    #[test]
+    fn verify_item_reports_modified_files() -> Result<()> {
+        let trash = tempdir()?;
+        let mut xattr_manager = MockXattrManager::new();
         xattr_manager
             .expect_get_attr()
-            .times(2)
-            .returning(move |_, key| match key {
-                DELETION_DATE_ATTR => Ok(Some(deletion_date_past.to_rfc3339())),
-                _ => Ok(Some("some_path".to_string())),
+            .withf(|_, key| key == CHECKSUM_ATTR)
+            .returning(|_, _| Ok(Some(EMPTY_SHA256.to_vec())));
+        let trash_manager = TrashManager::new(trash.path().to_path_buf(), xattr_manager);
+
+        let item = TrashItem {
+            id: "item".to_string(),
+            path: trash.path().join("item"),
+            original_path: PathBuf::from("/work/notes.md"),
+            deletion_date: Utc::now(),
+            pinned: false,
+            shred: false,
+            encrypted: false,
+            size: Some(0),
+            trash_date: None,
+            compression: None,
+            deduplicated: false,
+            file_kind: FileKind::File,
+        };
+        fs::write(&item.path, "tampered")?;
+        assert_eq!(
+            trash_manager.verify_item(&item)?,
+            Some(vec![checksum::Mismatch::Modified(
+                item.original_path.clone()
+            )])
+        );
+
+        fs::write(&item.path, "")?;
+        assert_eq!(trash_manager.verify_item(&item)?, Some(vec![]));
+        Ok(())
+    }
+
+    #[test]
+    fn check_trash_reports_orphans_and_leftovers() -> Result<()> {
+        let trash = tempdir()?;
+        let trash_dir = trash.path().to_path_buf();
+        fs::write(trash_dir.join("orphan"), "content")?;
+        fs::write(trash_dir.join(".journal.tmp"), "")?;
+
+        let mut xattr_manager = MockXattrManager::new();
+        xattr_manager.expect_get_attr().returning(|_, _| Ok(None));
+
+        let trash_manager = TrashManager::new(trash_dir, xattr_manager);
+        let issues = trash_manager.check_trash()?;
+        let kinds: Vec<(&str, &fsck::IssueKind)> = issues
+            .iter()
+            .map(|issue| (issue.id.as_str(), &issue.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (".journal.tmp", &fsck::IssueKind::Leftover),
+                ("orphan", &fsck::IssueKind::Orphan),
+            ]
+        );
+        Ok(())
+    }
+
+    /// Returns a filesystem with a home directory, and a manager for a trash on its own
+    /// device holding at most `capacity` bytes.
+    fn memory_trash(capacity: Option<u64>) -> (MemoryFileSystem, TrashManager<MemoryFileSystem>) {
+        let file_system = MemoryFileSystem::new();
+        file_system.add_dir("/home/me");
+        file_system.add_dir("/trash");
+        file_system.mount("/trash", capacity);
+        let trash_manager = TrashManager::new(PathBuf::from("/trash"), file_system.clone())
+            .with_file_system(file_system.clone());
+        (file_system, trash_manager)
+    }
+
+    #[test]
+    fn trash_and_restore_across_devices() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let original_path = Path::new("/home/me/notes.md");
+        file_system.add_file(original_path, "content");
+
+        trash_manager.trash_items(vec![original_path.to_path_buf()], Utc::now())?;
+        assert!(!file_system.exists(original_path));
+        let items = trash_manager.list_items()?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].original_path, original_path);
+        assert_eq!(items[0].kind(), "File");
+        assert_eq!(items[0].size, Some(7));
+
+        trash_manager.restore_item_by_id(&items[0].id, None)?;
+        assert_eq!(file_system.read(original_path)?, b"content");
+        assert_eq!(
+            file_system.get_attr(original_path, ORIGINAL_PATH_ATTR)?,
+            None
+        );
+        assert!(trash_manager.list_items()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn workers_keep_the_file_system_set_before() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let trash_manager = trash_manager.with_workers(Workers::default().with_jobs(2));
+        let original_path = Path::new("/home/me/notes.md");
+        file_system.add_file(original_path, "content");
+
+        trash_manager.trash_items(vec![original_path.to_path_buf()], Utc::now())?;
+        assert!(!file_system.exists(original_path));
+        assert_eq!(trash_manager.list_items()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn ids_outside_of_the_trash_are_not_found() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        file_system.add_file("/victim", "content");
+        file_system.add_dir("/trash/.meta");
+
+        for id in ["../victim", "/victim", ".meta", ".", "", "a/../../victim"] {
+            assert!(
+                matches!(
+                    trash_manager.set_pinned(id, true),
+                    Err(Error::ItemNotFound(_))
+                ),
+                "{:?} was found",
+                id
+            );
+            assert!(matches!(
+                trash_manager.set_deletion_date(id, Utc::now()),
+                Err(Error::ItemNotFound(_))
+            ));
+        }
+        assert_eq!(
+            file_system.get_attr(Path::new("/victim"), PINNED_ATTR)?,
+            None
+        );
+        assert_eq!(
+            file_system.get_attr(Path::new("/victim"), DELETION_DATE_ATTR)?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn trash_without_permission_leaves_the_item_untouched() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let original_path = Path::new("/home/me/notes.md");
+        file_system.add_file(original_path, "content");
+        file_system.fail(
+            Operation::Rename,
+            "/home/me",
+            io::ErrorKind::PermissionDenied,
+        );
+
+        let result = trash_manager.trash_items(vec![original_path.to_path_buf()], Utc::now());
+        assert_eq!(
+            io_error_kinds(result),
+            vec![io::ErrorKind::PermissionDenied]
+        );
+        assert_eq!(file_system.read(original_path)?, b"content");
+        for key in ITEM_ATTRS {
+            assert_eq!(file_system.get_attr(original_path, key)?, None);
+        }
+        assert!(trash_manager.list_items()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn moves_across_devices_are_undone_if_the_original_stays() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let original_path = Path::new("/home/me/notes.md");
+        file_system.add_file(original_path, "content");
+        file_system.fail(
+            Operation::Remove,
+            original_path,
+            io::ErrorKind::PermissionDenied,
+        );
+
+        let result = trash_manager.trash_items(vec![original_path.to_path_buf()], Utc::now());
+        assert_eq!(
+            io_error_kinds(result),
+            vec![io::ErrorKind::PermissionDenied]
+        );
+        assert_eq!(file_system.read(original_path)?, b"content");
+        assert!(trashed_paths(&file_system)?.is_empty());
+
+        // Part of a directory may be gone, so its copy is kept as a complete item
+        let project = Path::new("/home/me/project");
+        file_system.add_file(project.join("a.txt"), "a");
+        file_system.fail(Operation::Remove, project, io::ErrorKind::PermissionDenied);
+        let result = trash_manager.trash_items(vec![project.to_path_buf()], Utc::now());
+        let Err(Error::ItemsFailed { failures, .. }) = result else {
+            panic!("unexpected result: {:?}", result);
+        };
+        assert!(matches!(failures[0].error, Error::IncompleteMove { .. }));
+        assert_eq!(failures[0].error.kind(), ErrorKind::Permission);
+        let items = trash_manager.list_items()?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].original_path, project);
+        assert_eq!(file_system.read(&items[0].path.join("a.txt"))?, b"a");
+        Ok(())
+    }
+
+    /// Returns the kinds of the I/O errors an operation on several items failed with.
+    fn io_error_kinds(result: Result<()>) -> Vec<io::ErrorKind> {
+        match result {
+            Err(Error::ItemsFailed { failures, .. }) => failures
+                .iter()
+                .map(|failure| match &failure.error {
+                    Error::Io(e) => e.kind(),
+                    e => panic!("unexpected error: {}", e),
+                })
+                .collect(),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn failing_pre_hooks_veto_the_operation() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let trash_manager = trash_manager.with_hooks(
+            Hooks::default()
+                .with_command(
+                    HookEvent::PreTrash,
+                    "test \"$RRM_PATH\" != /home/me/keep.md",
+                )
+                .with_command(HookEvent::PrePurge, "exit 1"),
+        );
+        file_system.add_file("/home/me/keep.md", "keep");
+        file_system.add_file("/home/me/notes.md", "notes");
+
+        let result = trash_manager.trash_items(
+            vec![
+                PathBuf::from("/home/me/keep.md"),
+                PathBuf::from("/home/me/notes.md"),
+            ],
+            Utc::now(),
+        );
+        let Err(Error::ItemsFailed { failures, .. }) = result else {
+            panic!("unexpected result: {:?}", result);
+        };
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].item, "/home/me/keep.md");
+        assert_eq!(failures[0].error.kind(), ErrorKind::HookFailed);
+        assert_eq!(file_system.read(Path::new("/home/me/keep.md"))?, b"keep");
+        for key in ITEM_ATTRS {
+            assert_eq!(
+                file_system.get_attr(Path::new("/home/me/keep.md"), key)?,
+                None
+            );
+        }
+
+        let items = trash_manager.list_items()?;
+        assert_eq!(items.len(), 1);
+        let result = trash_manager.delete_item_by_id(&items[0].id);
+        assert!(matches!(result, Err(Error::HookFailed { .. })));
+        assert!(file_system.exists(&items[0].path));
+        Ok(())
+    }
+
+    #[test]
+    fn failing_post_hooks_are_reported_as_warnings() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let trash_manager = trash_manager
+            .with_hooks(Hooks::default().with_command(HookEvent::PostTrash, "exit 1"))
+            .with_warning_handler({
+                let warnings = warnings.clone();
+                move |warning| warnings.lock().unwrap().push(warning.to_string())
             });
+        file_system.add_file("/home/me/notes.md", "notes");
+
+        trash_manager.trash_items(vec![PathBuf::from("/home/me/notes.md")], Utc::now())?;
+        assert_eq!(trash_manager.list_items()?.len(), 1);
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec!["The post-trash hook 'exit 1' failed with exit status: 1".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_trash_goes_through_the_file_system() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        file_system.add_file("/trash/orphan", "content");
+        file_system.add_file("/trash/.journal.tmp", "");
+        file_system.add_file("/trash/.manifests/gone", "");
+        file_system.add_file("/trash/.blobs/ab/abcd", "stored");
 
-        let trash_manager = TrashManager::new(trash_dir.clone(), xattr_manager);
-        trash_manager.trash_items(vec![original_path], deletion_date_past)?;
+        let issues = trash_manager.check_trash()?;
+        let kinds: Vec<_> = issues
+            .iter()
+            .map(|issue| (issue.id.as_str(), &issue.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (".journal.tmp", &fsck::IssueKind::Leftover),
+                ("orphan", &fsck::IssueKind::Orphan),
+                ("gone", &fsck::IssueKind::OrphanManifest),
+                ("abcd", &fsck::IssueKind::OrphanBlob),
+            ]
+        );
 
-        trash_manager.clean_trash(false)?;
+        for issue in &issues {
+            assert!(trash_manager.repair_issue(issue, &fsck::Repair::Delete)?);
+        }
+        assert!(trash_manager.check_trash()?.is_empty());
+        assert!(!file_system.exists(Path::new("/trash/.blobs/ab")));
+        Ok(())
+    }
+
+    #[test]
+    fn policies_needing_the_real_file_system_are_refused() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        file_system.add_file("/home/me/notes.md", "notes");
+        let trash_manager = trash_manager.with_dedup(true);
+
+        let result =
+            trash_manager.trash_items(vec![PathBuf::from("/home/me/notes.md")], Utc::now());
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::UnsupportedFileSystem);
+        assert!(file_system.exists(Path::new("/home/me/notes.md")));
+        // Reading the trash needs none of them
+        assert!(trash_manager.list_items()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn trash_items_goes_on_after_a_failure() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        file_system.add_file("/home/me/locked/a.txt", "a");
+        file_system.add_file("/home/me/b.txt", "b");
+        file_system.fail(
+            Operation::Rename,
+            "/home/me/locked",
+            io::ErrorKind::PermissionDenied,
+        );
+
+        let result = trash_manager.trash_items(
+            vec![
+                PathBuf::from("/home/me/locked/a.txt"),
+                PathBuf::from("/home/me/b.txt"),
+            ],
+            Utc::now(),
+        );
+        let error = result.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::PartialFailure);
+        assert_eq!(error.to_string(), "1 of 2 items failed");
+        let Error::ItemsFailed { failures, .. } = error else {
+            unreachable!()
+        };
+        assert_eq!(failures[0].item, "/home/me/locked/a.txt");
+        assert_eq!(failures[0].error.kind(), ErrorKind::Permission);
 
-        // Check if the files were moved to the trash
         let items = trash_manager.list_items()?;
-        assert_eq!(trash_dir.read_dir()?.count(), 0);
-        assert_eq!(items.len(), 0);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].original_path, Path::new("/home/me/b.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn trash_on_a_full_device_removes_the_partial_copy() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(Some(300));
+        let original_path = Path::new("/home/me/project");
+        file_system.add_file(original_path.join("a.txt"), vec![b'a'; 100]);
+        file_system.add_file(original_path.join("b.txt"), vec![b'b'; 100]);
+
+        // The checksum manifest and the first file fit, the second file does not
+        let result = trash_manager.trash_items(vec![original_path.to_path_buf()], Utc::now());
+        assert_eq!(io_error_kinds(result), vec![io::ErrorKind::StorageFull]);
+        assert_eq!(
+            file_system.read(&original_path.join("b.txt"))?,
+            vec![b'b'; 100]
+        );
+        assert_eq!(
+            file_system.read_dir(Path::new("/trash"))?,
+            vec![PathBuf::from("/trash").join(CHECKSUMS_DIR)]
+        );
+        assert!(file_system
+            .read_dir(&Path::new("/trash").join(CHECKSUMS_DIR))?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn clean_trash_deletes_items_expiring_exactly_now() -> Result<()> {
+        let (file_system, trash_manager) = memory_trash(None);
+        let clock = FakeClock::new(Utc::now());
+        let trash_manager = trash_manager.with_clock(Arc::new(clock.clone()));
+        file_system.add_file("/home/me/notes.md", "content");
+        let deletion_date = clock.now() + chrono::Duration::hours(1);
+        trash_manager.trash_items(vec![PathBuf::from("/home/me/notes.md")], deletion_date)?;
+
+        clock.set(deletion_date - chrono::Duration::seconds(1));
+        trash_manager.clean_trash(false, false)?;
+        assert_eq!(trash_manager.list_items()?.len(), 1);
+
+        clock.set(deletion_date);
+        trash_manager.clean_trash(false, false)?;
+        assert!(trash_manager.list_items()?.is_empty());
+        Ok(())
+    }
+
+    /// Extended attributes on the real filesystem, except that setting `failing` fails.
+    struct FailingXattrs {
+        inner: crate::XAttrManager,
+        failing: &'static str,
+    }
+
+    impl ExtendedAttributes for FailingXattrs {
+        fn set_attr(&self, path: &Path, key: &str, value: &[u8]) -> Result<()> {
+            if key == self.failing {
+                return Err(io::Error::from(io::ErrorKind::PermissionDenied).into());
+            }
+            self.inner.set_attr(path, key, value)
+        }
+
+        fn get_attr(&self, path: &Path, key: &str) -> Result<Option<Vec<u8>>> {
+            self.inner.get_attr(path, key)
+        }
+
+        fn remove_attr(&self, path: &Path, key: &str) -> Result<()> {
+            self.inner.remove_attr(path, key)
+        }
+    }
+
+    /// Trashes a project whose storage fails on setting `failing`, returning the item and
+    /// the warnings reported.
+    fn trash_failing_project(
+        dir: &Path,
+        failing: &'static str,
+        configure: impl FnOnce(TrashManager<FailingXattrs>) -> TrashManager<FailingXattrs>,
+    ) -> Result<(TrashItem, Vec<String>)> {
+        let project = dir.join("project");
+        fs::create_dir_all(project.join("src"))?;
+        fs::write(project.join("README.md"), "shared ".repeat(100))?;
+        fs::write(project.join("src/copy.md"), "shared ".repeat(100))?;
+        fs::create_dir(dir.join("trash"))?;
+        let xattrs = FailingXattrs {
+            inner: crate::XAttrManager::new()?,
+            failing,
+        };
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let trash_manager = configure(TrashManager::new(dir.join("trash"), xattrs))
+            .with_warning_handler({
+                let warnings = warnings.clone();
+                move |warning| warnings.lock().unwrap().push(warning.to_string())
+            });
+
+        trash_manager.trash_items(vec![project.clone()], Utc::now())?;
+        assert!(!project.exists());
+        let items = trash_manager.list_items()?;
+        assert_eq!(items.len(), 1);
+        let item = items.into_iter().next().unwrap();
+        assert_eq!(item.compression, None);
+        assert!(!item.deduplicated);
+        assert_eq!(
+            fs::read_to_string(item.path.join("src/copy.md"))?,
+            "shared ".repeat(100)
+        );
+        // Nothing is left behind but the item and its checksums, at most empty stores
+        for entry in fs::read_dir(dir.join("trash"))? {
+            let path = entry?.path();
+            if path != item.path && !path.ends_with(CHECKSUMS_DIR) {
+                assert_eq!(fs::read_dir(&path)?.count(), 0, "{}", path.display());
+            }
+        }
+        trash_manager.restore_item_by_id(&item.id, None)?;
+        assert_eq!(
+            fs::read_to_string(project.join("README.md"))?,
+            "shared ".repeat(100)
+        );
+        let warnings = warnings.lock().unwrap().clone();
+        Ok((item, warnings))
+    }
+
+    #[test]
+    fn items_that_fail_to_compress_are_stored_as_they_were() -> Result<()> {
+        let dir = tempdir()?;
+        let (_, warnings) = trash_failing_project(dir.path(), COMPRESSED_ATTR, |trash_manager| {
+            trash_manager.with_compression_policy(CompressionPolicy {
+                threshold: Some(0),
+                after: None,
+            })
+        })?;
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("project: stored as it was: "));
+        Ok(())
+    }
+
+    #[test]
+    fn items_that_fail_to_deduplicate_are_stored_as_they_were() -> Result<()> {
+        let dir = tempdir()?;
+        let (_, warnings) = trash_failing_project(dir.path(), DEDUP_ATTR, |trash_manager| {
+            trash_manager.with_dedup(true)
+        })?;
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("project: stored as it was: "));
         Ok(())
     }
+
+    #[test]
+    fn items_that_fail_to_encrypt_are_put_back() -> Result<()> {
+        let dir = tempdir()?;
+        let project = dir.path().join("project");
+        fs::create_dir(&project)?;
+        fs::write(project.join("a.env"), "first")?;
+        fs::write(project.join("b.env"), "second")?;
+        fs::create_dir(dir.path().join("trash"))?;
+        // Even root cannot overwrite an append-only file, which needs the privilege to set
+        let append_only = |flag| {
+            std::process::Command::new("chattr")
+                .arg(flag)
+                .arg(project.join("b.env"))
+                .stderr(std::process::Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        };
+        if !append_only("+a") {
+            return Ok(());
+        }
+        let key_file = dir.path().join("key");
+        crypto::generate_key_file(&key_file)?;
+        let trash_manager =
+            TrashManager::new(dir.path().join("trash"), crate::XAttrManager::new()?)
+                .with_encryption_policy(EncryptionPolicy::new(
+                    true,
+                    Vec::new(),
+                    crypto::KeySource::File(key_file),
+                ));
+
+        let result = trash_manager.trash_items(vec![project.clone()], Utc::now());
+        append_only("-a");
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(project.join("a.env"))?, "first");
+        assert_eq!(fs::read_to_string(project.join("b.env"))?, "second");
+        assert_eq!(
+            crate::XAttrManager::new()?.get_attr(&project, ORIGINAL_PATH_ATTR)?,
+            None
+        );
+        assert!(trash_manager.list_items()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn deletion_dates_are_relative_to_the_local_day() {
+        let utc = |date: &str| DateTime::parse_from_rfc3339(date).unwrap().to_utc();
+        let item = |deletion_date| TrashItem {
+            id: "item".to_string(),
+            path: PathBuf::from("/trash/item"),
+            original_path: PathBuf::from("/work/notes.md"),
+            deletion_date,
+            pinned: false,
+            shred: false,
+            encrypted: false,
+            size: None,
+            trash_date: None,
+            compression: None,
+            deduplicated: false,
+            file_kind: FileKind::File,
+        };
+        let now = utc("2024-03-30T21:00:00Z");
+        let utc_plus_2 = chrono::FixedOffset::east_opt(2 * 3600).unwrap();
+        let utc_minus_4 = chrono::FixedOffset::west_opt(4 * 3600).unwrap();
+
+        // Still the same day in UTC, but already the next one two hours east
+        let soon = item(utc("2024-03-30T22:30:00Z"));
+        assert_eq!(soon.format_deletion_date_in(now, &Utc), "Today 22:30");
+        assert_eq!(
+            soon.format_deletion_date_in(now, &utc_plus_2),
+            "Tomorrow 00:30"
+        );
+        assert_eq!(
+            soon.format_deletion_date_in(now, &utc_minus_4),
+            "Today 18:30"
+        );
+
+        let later = item(utc("2024-03-31T23:00:00Z"));
+        assert_eq!(later.format_deletion_date_in(now, &Utc), "Tomorrow 23:00");
+        assert_eq!(
+            later.format_deletion_date_in(now, &utc_plus_2),
+            "2024-04-01 01:00:00"
+        );
+    }
 }
@@ -0,0 +1,304 @@
+use super::{escape_os_str, DedupStats, Issue, Mismatch, Repair, TrashItem, TrashManager};
+use crate::{xattr::ExtendedAttributes, Error, ItemFailure, Result};
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+
+/// A change to the metadata of a trashed item.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum MetadataUpdate {
+    /// Sets the date after which the item is permanently deleted.
+    DeletionDate(DateTime<Utc>),
+    /// Pins or unpins the item. Pinned items are kept past their deletion date.
+    Pinned(bool),
+    /// Unpins the item and sets its deletion date, as a single change.
+    Reschedule(DateTime<Utc>),
+}
+
+/// Where and how trashed items are stored.
+///
+/// [`TrashManager`] is the default implementation: UUID-named entries in a flat trash
+/// directory, with their metadata in extended attributes. Other layouts implement the same
+/// operations, so code written against this trait (like the commands of the CLI) works
+/// with any of them.
+pub trait TrashBackend {
+    /// Moves the item at `path` into the trash until `deletion_date`, returning its id.
+    fn put(&self, path: &Path, deletion_date: DateTime<Utc>) -> Result<String>;
+
+    /// Moves the items at `paths` into the trash until `deletion_date`. An item that
+    /// cannot be trashed does not stop the others, the failures are returned together as
+    /// [`Error::ItemsFailed`].
+    fn put_all(&self, paths: Vec<PathBuf>, deletion_date: DateTime<Utc>) -> Result<()> {
+        let total = paths.len();
+        let failures = paths
+            .into_iter()
+            .filter_map(|path| {
+                let error = self.put(&path, deletion_date).err()?;
+                Some(ItemFailure {
+                    item: escape_os_str(path.as_os_str()),
+                    error,
+                })
+            })
+            .collect();
+        Error::from_failures(failures, total)
+    }
+
+    /// Permanently deletes `paths` right away instead of trashing them. Every path is
+    /// tried, the failures are returned together as [`Error::ItemsFailed`].
+    fn delete_paths(&self, paths: Vec<PathBuf>) -> Result<()>;
+
+    /// Returns every item in the trash, in no particular order.
+    fn list(&self) -> Result<Vec<TrashItem>>;
+
+    /// Returns the item `id`, or [`crate::Error::ItemNotFound`].
+    fn get(&self, id: &str) -> Result<TrashItem>;
+
+    /// Moves the item `id` out of the trash, to `destination` or else to its original
+    /// path. Fails if something already exists there.
+    fn restore(&self, id: &str, destination: Option<&Path>) -> Result<()>;
+
+    /// Permanently deletes the item `id`, regardless of its deletion date.
+    fn purge(&self, id: &str) -> Result<()>;
+
+    /// Changes the metadata of the item `id`.
+    fn update(&self, id: &str, update: MetadataUpdate) -> Result<()>;
+
+    /// Permanently deletes the items that reached their deletion date, or every item if
+    /// `immediate` is set. Pinned items are kept unless `force` is set.
+    fn clean(&self, immediate: bool, force: bool) -> Result<()>;
+
+    /// Returns the contents of a trashed file, failing with [`Error::NotAFile`] for
+    /// other items.
+    fn read(&self, item: &TrashItem) -> Result<Vec<u8>>;
+
+    /// Calls `f` with the path each regular file of a trashed item had before being
+    /// trashed, and its contents or the error reading them.
+    fn for_each_file(
+        &self,
+        item: &TrashItem,
+        f: &mut dyn FnMut(PathBuf, Result<Vec<u8>>),
+    ) -> Result<()>;
+
+    /// Checks the files of an item against the checksums recorded when it was trashed,
+    /// returning those that do not match, or `None` if nothing was recorded.
+    fn verify(&self, _item: &TrashItem) -> Result<Option<Vec<Mismatch>>> {
+        Ok(None)
+    }
+
+    /// Returns the problems that make entries of the trash invisible to [`Self::list`].
+    fn check(&self) -> Result<Vec<Issue>> {
+        Ok(Vec::new())
+    }
+
+    /// Repairs a problem found by [`Self::check`], returning `false` if `repair` does not
+    /// apply to it.
+    fn repair(&self, _issue: &Issue, _repair: &Repair) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Returns the space saved by storing identical contents only once, if the trash
+    /// does.
+    fn dedup_stats(&self) -> Result<DedupStats> {
+        Ok(DedupStats::default())
+    }
+
+    /// Returns the earliest deletion date among the items that are not pinned, i.e. the
+    /// next time cleaning the trash has something to delete.
+    fn next_deletion_date(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .list()?
+            .iter()
+            .filter(|item| !item.pinned)
+            .map(|item| item.deletion_date)
+            .min())
+    }
+}
+
+impl<T: ExtendedAttributes> TrashBackend for TrashManager<T> {
+    fn put(&self, path: &Path, deletion_date: DateTime<Utc>) -> Result<String> {
+        let context = self.prepare_trash(path, deletion_date)?;
+        let _lock = self.lock_exclusive()?;
+        self.trash_item(path, context, self.now())
+    }
+
+    fn put_all(&self, paths: Vec<PathBuf>, deletion_date: DateTime<Utc>) -> Result<()> {
+        self.trash_items(paths, deletion_date)
+    }
+
+    fn delete_paths(&self, paths: Vec<PathBuf>) -> Result<()> {
+        TrashManager::delete_paths(self, paths)
+    }
+
+    fn list(&self) -> Result<Vec<TrashItem>> {
+        self.list_items()
+    }
+
+    fn get(&self, id: &str) -> Result<TrashItem> {
+        self.get_item(id)
+    }
+
+    fn restore(&self, id: &str, destination: Option<&Path>) -> Result<()> {
+        self.restore_to(id, |attrs_path| match destination {
+            Some(destination) => Ok(destination.to_path_buf()),
+            None => self.read_original_path(id, attrs_path),
+        })
+    }
+
+    fn purge(&self, id: &str) -> Result<()> {
+        self.delete_item_by_id(id)
+    }
+
+    fn update(&self, id: &str, update: MetadataUpdate) -> Result<()> {
+        match update {
+            MetadataUpdate::DeletionDate(deletion_date) => {
+                self.set_deletion_date(id, deletion_date)
+            }
+            MetadataUpdate::Pinned(pinned) => self.set_pinned(id, pinned),
+            MetadataUpdate::Reschedule(deletion_date) => self.reschedule(id, deletion_date),
+        }
+    }
+
+    fn clean(&self, immediate: bool, force: bool) -> Result<()> {
+        self.clean_trash(immediate, force)
+    }
+
+    fn read(&self, item: &TrashItem) -> Result<Vec<u8>> {
+        self.read_item(item)
+    }
+
+    fn for_each_file(
+        &self,
+        item: &TrashItem,
+        f: &mut dyn FnMut(PathBuf, Result<Vec<u8>>),
+    ) -> Result<()> {
+        TrashManager::for_each_file(self, item, f)
+    }
+
+    fn verify(&self, item: &TrashItem) -> Result<Option<Vec<Mismatch>>> {
+        self.verify_item(item)
+    }
+
+    fn check(&self) -> Result<Vec<Issue>> {
+        self.check_trash()
+    }
+
+    fn repair(&self, issue: &Issue, repair: &Repair) -> Result<bool> {
+        self.repair_issue(issue, repair)
+    }
+
+    fn dedup_stats(&self) -> Result<DedupStats> {
+        TrashManager::dedup_stats(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    /// Keeps items in memory, enough to exercise the provided methods of the trait.
+    #[derive(Default)]
+    struct MemoryBackend {
+        items: RefCell<BTreeMap<String, TrashItem>>,
+    }
+
+    impl TrashBackend for MemoryBackend {
+        fn put(&self, path: &Path, deletion_date: DateTime<Utc>) -> Result<String> {
+            let mut items = self.items.borrow_mut();
+            let id = items.len().to_string();
+            let item = TrashItem::new(
+                id.clone(),
+                PathBuf::from("/memory").join(&id),
+                path.to_path_buf(),
+                deletion_date,
+            )
+            .with_trash_date(Some(Utc::now()));
+            items.insert(id.clone(), item);
+            Ok(id)
+        }
+
+        fn list(&self) -> Result<Vec<TrashItem>> {
+            Ok(self.items.borrow().values().cloned().collect())
+        }
+
+        fn get(&self, id: &str) -> Result<TrashItem> {
+            self.items
+                .borrow()
+                .get(id)
+                .cloned()
+                .ok_or_else(|| Error::ItemNotFound(id.to_string()))
+        }
+
+        fn restore(&self, id: &str, _destination: Option<&Path>) -> Result<()> {
+            self.purge(id)
+        }
+
+        fn purge(&self, id: &str) -> Result<()> {
+            self.items
+                .borrow_mut()
+                .remove(id)
+                .map(|_| ())
+                .ok_or_else(|| Error::ItemNotFound(id.to_string()))
+        }
+
+        fn update(&self, id: &str, update: MetadataUpdate) -> Result<()> {
+            let mut items = self.items.borrow_mut();
+            let item = items
+                .remove(id)
+                .ok_or_else(|| Error::ItemNotFound(id.to_string()))?;
+            let item = match update {
+                MetadataUpdate::DeletionDate(deletion_date) => {
+                    item.with_deletion_date(deletion_date)
+                }
+                MetadataUpdate::Pinned(pinned) => item.with_pinned(pinned),
+                MetadataUpdate::Reschedule(deletion_date) => {
+                    item.with_deletion_date(deletion_date).with_pinned(false)
+                }
+            };
+            items.insert(id.to_string(), item);
+            Ok(())
+        }
+
+        fn delete_paths(&self, _paths: Vec<PathBuf>) -> Result<()> {
+            Ok(())
+        }
+
+        fn clean(&self, _immediate: bool, force: bool) -> Result<()> {
+            self.items
+                .borrow_mut()
+                .retain(|_, item| item.pinned() && !force);
+            Ok(())
+        }
+
+        fn read(&self, item: &TrashItem) -> Result<Vec<u8>> {
+            Err(Error::NotAFile(item.format_original_path()))
+        }
+
+        fn for_each_file(
+            &self,
+            _item: &TrashItem,
+            _f: &mut dyn FnMut(PathBuf, Result<Vec<u8>>),
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn next_deletion_date_skips_pinned_items() -> Result<()> {
+        let backend = MemoryBackend::default();
+        assert_eq!(backend.next_deletion_date()?, None);
+
+        let now = Utc::now();
+        let soon = backend.put(Path::new("/work/soon"), now + Duration::hours(1))?;
+        backend.put(Path::new("/work/later"), now + Duration::days(1))?;
+        assert_eq!(
+            backend.next_deletion_date()?,
+            Some(now + Duration::hours(1))
+        );
+
+        backend.update(&soon, MetadataUpdate::Pinned(true))?;
+        assert_eq!(backend.next_deletion_date()?, Some(now + Duration::days(1)));
+        Ok(())
+    }
+}
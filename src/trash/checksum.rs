@@ -0,0 +1,251 @@
+use super::{
+    escape_os_str, ignore_not_found, join_relative, TrashItem, TrashManager, CHECKSUM_ATTR,
+};
+use crate::{crypto, filesystem::FileKind, xattr::ExtendedAttributes, Error, Result};
+use derive_more::Display;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fmt::Write,
+    fs, io,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+/// Hidden directory inside the trash holding, for each trashed directory, the hash of
+/// every regular file it contained when it was trashed.
+pub(super) const CHECKSUMS_DIR: &str = ".checksums";
+
+/// A difference between a trashed item and the checksums recorded when it was trashed.
+#[derive(Debug, Display, PartialEq)]
+#[non_exhaustive]
+pub enum Mismatch {
+    #[display("{}: contents changed", escape_os_str(_0.as_os_str()))]
+    Modified(PathBuf),
+
+    #[display("{}: missing", escape_os_str(_0.as_os_str()))]
+    Missing(PathBuf),
+
+    #[display("{}: not part of the item when it was trashed", escape_os_str(_0.as_os_str()))]
+    Unexpected(PathBuf),
+
+    #[display("{}: cannot be read: {}", escape_os_str(path.as_os_str()), error)]
+    Unreadable { path: PathBuf, error: String },
+
+    #[display("the checksum manifest is missing or was modified")]
+    Manifest,
+}
+
+impl<T: ExtendedAttributes> TrashManager<T> {
+    /// Records the checksum of the item at `path`, before it is moved to the trash as `id`.
+    /// Files get the hash of their contents; directories get a manifest with the hash of
+    /// each regular file, and the hash of that manifest. Symlinks have no contents to hash.
+    ///
+    /// The hashes of an item that is going to be `encrypt`ed are those of its plaintext,
+    /// and are encrypted too, so they do not tell which contents the item holds.
+    pub(super) fn record_checksum(&self, path: &Path, id: &str, encrypt: bool) -> Result<()> {
+        let seal = |value: &[u8]| match encrypt {
+            true => self.encryption_policy.encrypt_bytes(value),
+            false => Ok(value.to_vec()),
+        };
+        match self.file_system.metadata(path)?.kind {
+            FileKind::Symlink => return Ok(()),
+            FileKind::File => {
+                let hash = hash_reader(self.file_system.open(path)?)?;
+                return self
+                    .xattr_manager
+                    .set_attr(path, CHECKSUM_ATTR, &seal(hash.as_bytes())?);
+            }
+            FileKind::Directory => {}
+        }
+
+        let mut manifest = Vec::new();
+        for (file, _) in self.walk_files(path, path)? {
+            let relative = file.strip_prefix(path).unwrap_or(&file);
+            let hash = hash_reader(self.file_system.open(&file)?)?;
+            manifest.extend_from_slice(format!("{}\t", hash).as_bytes());
+            manifest.extend_from_slice(relative.as_os_str().as_bytes());
+            manifest.push(0);
+        }
+        self.file_system
+            .create_dir_all(&self.trash_dir.join(CHECKSUMS_DIR))?;
+        self.file_system
+            .write(&self.checksums_path(OsStr::new(id)), &seal(&manifest)?)?;
+        self.xattr_manager.set_attr(
+            path,
+            CHECKSUM_ATTR,
+            &seal(hash_bytes(&manifest).as_bytes())?,
+        )
+    }
+
+    /// Recomputes the checksums of a trashed item and compares them with those recorded
+    /// when it was trashed, decrypting the files of encrypted items. Encrypted items
+    /// trashed by older versions have no checksums, and are only checked to decrypt.
+    /// Returns `None` for other items trashed without checksums.
+    ///
+    /// Files are streamed through the hash, so they never have to fit in memory.
+    pub fn verify_item(&self, item: &TrashItem) -> Result<Option<Vec<Mismatch>>> {
+        let _lock = self.lock_shared()?;
+        self.check_integrity(item)
+    }
+
+    pub(super) fn check_integrity(&self, item: &TrashItem) -> Result<Option<Vec<Mismatch>>> {
+        let attrs_path = self.attrs_path(&item.path);
+        let Some(checksum) = self.xattr_manager.get_attr(&attrs_path, CHECKSUM_ATTR)? else {
+            if !item.encrypted {
+                return Ok(None);
+            }
+            // Decrypting the files of older encrypted items authenticates their contents
+            let mut mismatches = Vec::new();
+            self.read_files(item, |path, reader| {
+                let read = reader.and_then(|reader| Ok(io::copy(reader, &mut io::sink())?));
+                if let Err(e) = read {
+                    mismatches.push(Mismatch::Unreadable {
+                        path,
+                        error: e.to_string(),
+                    });
+                }
+            })?;
+            return Ok(Some(mismatches));
+        };
+        let checksum = self.unseal(checksum)?;
+        let checksum = String::from_utf8_lossy(&checksum).into_owned();
+
+        let mut actual = BTreeMap::new();
+        self.read_files(item, |original_path, reader| {
+            let relative = original_path
+                .strip_prefix(&item.original_path)
+                .unwrap_or(&original_path)
+                .to_path_buf();
+            actual.insert(relative, reader.and_then(hash_reader));
+        })?;
+
+        let expected = if item.kind() == "Directory" {
+            let manifest = match self
+                .file_system
+                .read(&self.checksums_path(OsStr::new(&item.id)))
+                .map_err(Error::from)
+                .and_then(|manifest| self.unseal(manifest))
+            {
+                Ok(manifest) if hash_bytes(&manifest) == checksum => manifest,
+                // Encrypted manifests fail to decrypt once modified
+                Ok(_) | Err(Error::Encryption(_)) => return Ok(Some(vec![Mismatch::Manifest])),
+                Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(Some(vec![Mismatch::Manifest]))
+                }
+                Err(e) => return Err(e),
+            };
+            match parse_manifest(&manifest) {
+                Some(expected) => expected,
+                None => return Ok(Some(vec![Mismatch::Manifest])),
+            }
+        } else {
+            BTreeMap::from([(PathBuf::new(), checksum)])
+        };
+
+        let mut mismatches = Vec::new();
+        for (relative, hash) in &expected {
+            let path = join_relative(&item.original_path, relative);
+            match actual.remove(relative) {
+                Some(Ok(actual)) if actual == *hash => {}
+                Some(Ok(_)) => mismatches.push(Mismatch::Modified(path)),
+                Some(Err(e)) => mismatches.push(Mismatch::Unreadable {
+                    path,
+                    error: e.to_string(),
+                }),
+                None => mismatches.push(Mismatch::Missing(path)),
+            }
+        }
+        for relative in actual.into_keys() {
+            mismatches.push(Mismatch::Unexpected(join_relative(
+                &item.original_path,
+                &relative,
+            )));
+        }
+        Ok(Some(mismatches))
+    }
+
+    /// Fails with the mismatches found if a trashed item does not match its checksums.
+    pub(super) fn ensure_integrity(&self, id: &str, attrs_path: &Path) -> Result<()> {
+        if self
+            .xattr_manager
+            .get_attr(attrs_path, CHECKSUM_ATTR)?
+            .is_none()
+        {
+            return Ok(());
+        }
+        let item = self.load_item(id)?;
+        match self.check_integrity(&item)? {
+            Some(mismatches) if !mismatches.is_empty() => Err(Error::Corrupted {
+                id: id.to_string(),
+                problems: mismatches
+                    .iter()
+                    .map(|mismatch| mismatch.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes the checksum manifest of an item that left the trash, if it has one.
+    pub(super) fn remove_checksums(&self, id: &OsStr) -> Result<()> {
+        ignore_not_found(
+            self.file_system
+                .remove(&self.checksums_path(id))
+                .map_err(Error::from),
+        )
+    }
+
+    /// Decrypts a checksum or manifest recorded for an encrypted item.
+    fn unseal(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        if crypto::is_encrypted(&value) {
+            self.encryption_policy.decrypt_bytes(&value)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn checksums_path(&self, id: &OsStr) -> PathBuf {
+        self.trash_dir.join(CHECKSUMS_DIR).join(id)
+    }
+}
+
+/// Parses a checksum manifest made of `<hash>\t<path>` entries terminated by NUL.
+fn parse_manifest(manifest: &[u8]) -> Option<BTreeMap<PathBuf, String>> {
+    manifest
+        .split(|&b| b == 0)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let tab = entry.iter().position(|&b| b == b'\t')?;
+            let hash = std::str::from_utf8(&entry[..tab]).ok()?.to_string();
+            let path = PathBuf::from(OsString::from_vec(entry[tab + 1..].to_vec()));
+            Some((path, hash))
+        })
+        .collect()
+}
+
+/// Returns the hex-encoded SHA-256 hash of a file's contents.
+pub(super) fn hash_file(path: &Path) -> Result<String> {
+    hash_reader(fs::File::open(path)?)
+}
+
+/// Returns the hex-encoded SHA-256 hash of everything `reader` reads.
+fn hash_reader(mut reader: impl io::Read) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Returns the hex-encoded SHA-256 hash of `bytes`.
+fn hash_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
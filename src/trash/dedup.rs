@@ -0,0 +1,764 @@
+use super::{
+    checksum::hash_file, escape_os_str, hidden_sibling, join_relative, TrashManager, DEDUP_ATTR,
+    ITEM_ATTRS,
+};
+use crate::{
+    dry_run::PlannedAction,
+    filesystem::FileKind,
+    patterns::matching_files,
+    shred::{shred_path, ShredPolicy, ShredWarning},
+    xattr::ExtendedAttributes,
+    Error, Result,
+};
+use log::error;
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs, io,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Hidden directory inside the trash holding the contents of deduplicated files, each
+/// stored once under its SHA-256 hash.
+pub(super) const BLOBS_DIR: &str = ".blobs";
+
+/// Hidden directory inside the trash holding, for each deduplicated item, the list of its
+/// files and the blobs holding their contents.
+pub(super) const MANIFESTS_DIR: &str = ".manifests";
+
+/// A file of a deduplicated item, whose contents were moved to the blob store.
+#[derive(Debug, PartialEq)]
+struct ManifestEntry {
+    /// Path of the file relative to the item, empty when the item itself is the file.
+    path: PathBuf,
+    hash: String,
+    mode: u32,
+    modified: SystemTime,
+    size: u64,
+}
+
+impl ManifestEntry {
+    /// Encodes the entry as `<hash> <mode> <secs> <nanos> <size>\t<path>`. Paths can hold
+    /// any byte but NUL, which terminates the entry.
+    fn encode(&self, out: &mut Vec<u8>) {
+        let modified = self.modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+        out.extend_from_slice(
+            format!(
+                "{} {:o} {} {} {}\t",
+                self.hash,
+                self.mode,
+                modified.as_secs(),
+                modified.subsec_nanos(),
+                self.size
+            )
+            .as_bytes(),
+        );
+        out.extend_from_slice(self.path.as_os_str().as_bytes());
+        out.push(0);
+    }
+
+    fn decode(record: &[u8]) -> Option<Self> {
+        let tab = record.iter().position(|&b| b == b'\t')?;
+        let header = std::str::from_utf8(&record[..tab]).ok()?;
+        let mut fields = header.split(' ');
+        let hash = fields.next()?.to_string();
+        let mode = u32::from_str_radix(fields.next()?, 8).ok()?;
+        let secs = fields.next()?.parse().ok()?;
+        let nanos = fields.next()?.parse().ok()?;
+        let size = fields.next()?.parse().ok()?;
+        Some(Self {
+            path: PathBuf::from(std::ffi::OsString::from_vec(record[tab + 1..].to_vec())),
+            hash,
+            mode,
+            modified: UNIX_EPOCH + Duration::new(secs, nanos),
+            size,
+        })
+    }
+}
+
+/// Space saved by storing the contents of deduplicated items only once.
+#[derive(Debug, Default)]
+pub struct DedupStats {
+    /// Number of deduplicated items.
+    pub items: usize,
+    /// Total size of the files of the deduplicated items.
+    pub logical_size: u64,
+    /// Number of distinct contents in the blob store.
+    pub blobs: usize,
+    /// Bytes actually used by the blob store.
+    pub stored_size: u64,
+}
+
+impl DedupStats {
+    /// Returns the number of bytes not stored thanks to deduplication.
+    pub fn savings(&self) -> u64 {
+        self.logical_size.saturating_sub(self.stored_size)
+    }
+}
+
+impl<T: ExtendedAttributes> TrashManager<T> {
+    /// Moves the contents of the regular files of a trashed item to the blob store, where
+    /// identical contents are kept only once, and records them in the item's manifest.
+    ///
+    /// A trashed directory keeps its tree of directories and symlinks, a trashed file is
+    /// replaced by an empty file carrying its attributes. Files with other hard links are
+    /// left in place, as removing them would not free any space.
+    pub(super) fn dedup_item(&self, item_path: &Path) -> Result<()> {
+        if let Some(plan) = &self.plan {
+            plan.record(PlannedAction::Deduplicate {
+                path: item_path.to_path_buf(),
+            });
+            return Ok(());
+        }
+        let id = item_path.file_name().unwrap_or_default();
+        let mut entries = Vec::new();
+        for (file, _) in matching_files(item_path, item_path, |_| true)? {
+            let meta = file.symlink_metadata()?;
+            if meta.nlink() > 1 {
+                continue;
+            }
+            entries.push(ManifestEntry {
+                path: file.strip_prefix(item_path).unwrap_or(&file).to_path_buf(),
+                hash: hash_file(&file)?,
+                mode: meta.permissions().mode(),
+                modified: meta.modified()?,
+                size: meta.len(),
+            });
+        }
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        // The contents are linked into the blob store first, then recorded in the
+        // manifest, and only then removed from the item, so a crash halfway never loses
+        // them: at worst it leaves blobs no manifest refers to, which fsck deletes
+        let mut linked = Vec::new();
+        if let Err(e) = self.link_blobs(item_path, &entries, &mut linked) {
+            self.unlink_blobs(&linked);
+            return Err(e);
+        }
+        let mut manifest = Vec::new();
+        entries.iter().for_each(|entry| entry.encode(&mut manifest));
+        let recorded = fs::create_dir_all(self.trash_dir.join(MANIFESTS_DIR))
+            .and_then(|_| fs::write(self.manifest_path(id), manifest))
+            .map_err(Error::from)
+            .and_then(|_| self.xattr_manager.set_attr(item_path, DEDUP_ATTR, b"true"));
+        if let Err(e) = recorded {
+            let _ = fs::remove_file(self.manifest_path(id));
+            self.unlink_blobs(&linked);
+            return Err(e);
+        }
+
+        // The item is deduplicated from here on, a file that cannot be removed stays in
+        // place next to its blob and takes its place again when the item is restored
+        for entry in &entries {
+            if let Err(e) = self.release_file(item_path, entry, &linked) {
+                error!(
+                    "Failed to move {} to the blob store: {}",
+                    escape_os_str(join_relative(item_path, &entry.path).as_os_str()),
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a file of an item from the item once its contents are in the blob store.
+    fn release_file(
+        &self,
+        item_path: &Path,
+        entry: &ManifestEntry,
+        linked: &[PathBuf],
+    ) -> Result<()> {
+        if !entry.path.as_os_str().is_empty() {
+            return Ok(fs::remove_file(join_relative(item_path, &entry.path))?);
+        }
+
+        // The item itself is the file: it is swapped for an empty file with the same
+        // attributes, which are then dropped from the shared blob
+        let id = item_path.file_name().unwrap_or_default();
+        let placeholder = self
+            .trash_dir
+            .join(format!(".dedup-{}", id.to_string_lossy()));
+        fs::File::create_new(&placeholder)?;
+        let swapped = ITEM_ATTRS
+            .iter()
+            .try_for_each(|key| match self.xattr_manager.get_attr(item_path, key)? {
+                Some(value) => self.xattr_manager.set_attr(&placeholder, key, &value),
+                None => Ok(()),
+            })
+            .and_then(|_| Ok(fs::rename(&placeholder, item_path)?));
+        if let Err(e) = swapped {
+            let _ = fs::remove_file(&placeholder);
+            return Err(e);
+        }
+        let blob = self.blob_path(&entry.hash);
+        if linked.contains(&blob) {
+            for key in ITEM_ATTRS {
+                self.remove_attr_if_present(&blob, key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Hard-links the files of `entries` into the blob store, unless identical contents
+    /// are already stored, pushing the blobs it creates to `linked`.
+    fn link_blobs(
+        &self,
+        item_path: &Path,
+        entries: &[ManifestEntry],
+        linked: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        for entry in entries {
+            let blob = self.blob_path(&entry.hash);
+            if blob.exists() {
+                continue;
+            }
+            fs::create_dir_all(blob.parent().unwrap_or(&self.trash_dir))?;
+            fs::hard_link(join_relative(item_path, &entry.path), &blob)?;
+            linked.push(blob);
+        }
+        Ok(())
+    }
+
+    /// Removes blobs created for an item whose deduplication failed, which still holds
+    /// their contents.
+    fn unlink_blobs(&self, linked: &[PathBuf]) {
+        for blob in linked {
+            let _ = fs::remove_file(blob);
+            // Fails as long as other blobs share the directory
+            let _ = fs::remove_dir(blob.parent().unwrap_or(&self.trash_dir));
+        }
+    }
+
+    /// Rebuilds a deduplicated item at `destination`, copying each file back from the
+    /// blob store with its permissions and modification time.
+    ///
+    /// The item is rebuilt under a hidden name next to `destination` and only takes its
+    /// place, losing its metadata and manifest, once every file is back. If anything
+    /// fails, it is put back in the trash as it was.
+    pub(super) fn restore_deduplicated(&self, item_path: &Path, destination: &Path) -> Result<()> {
+        if let Some(plan) = &self.plan {
+            plan.record(PlannedAction::Rebuild {
+                from: item_path.to_path_buf(),
+                to: destination.to_path_buf(),
+            });
+            return Ok(());
+        }
+        let id = item_path.file_name().unwrap_or_default();
+        let entries = self.read_manifest(id)?;
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| !self.blob_path(&entry.hash).exists())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "the contents of {} are missing from the trash",
+                    escape_os_str(destination.join(&entry.path).as_os_str())
+                ),
+            )
+            .into());
+        }
+
+        let staging = hidden_sibling(destination, "restoring");
+        if item_path.symlink_metadata()?.is_dir() {
+            // The directories, symlinks and hard-linked files move as they are, possibly
+            // to another filesystem, and the other files are copied back into them
+            self.move_path(item_path, &staging)?;
+            let mut restored = Vec::new();
+            let filled = entries.iter().try_for_each(|entry| {
+                let file = join_relative(&staging, &entry.path);
+                if file.symlink_metadata().is_ok() {
+                    // Left in place when the item was deduplicated
+                    return Ok(());
+                }
+                self.copy_blob(entry, &file)?;
+                restored.push(file);
+                Ok(())
+            });
+            if let Err(e) = filled {
+                for file in restored {
+                    let _ = fs::remove_file(file);
+                }
+                let _ = self.move_path(&staging, item_path);
+                return Err(e);
+            }
+            self.remove_item_attrs(&staging)?;
+            fs::rename(&staging, destination)?;
+        } else {
+            // The item is an empty placeholder, the file is copied back in its place
+            for entry in &entries {
+                self.copy_blob(entry, &staging)?;
+            }
+            fs::rename(&staging, destination)?;
+            fs::remove_file(item_path)?;
+        }
+
+        fs::remove_file(self.manifest_path(id))?;
+        // The contents live on in the restored files, there is nothing to shred
+        self.release_blobs(&entries, None)?;
+        Ok(())
+    }
+
+    /// Deletes the manifest of a deduplicated item that was originally at `original_path`
+    /// and the blobs no other item refers to, shredding those selected by `policy`.
+    /// Returns the warnings of the shredding, for blobs that could not be shredded
+    /// effectively.
+    pub(super) fn delete_manifest(
+        &self,
+        id: &OsStr,
+        original_path: &Path,
+        policy: &ShredPolicy,
+    ) -> Result<Vec<ShredWarning>> {
+        if self.plan.is_some() {
+            // The blob store is only changed on the real filesystem, planning the removal
+            // of the item itself is enough
+            return Ok(Vec::new());
+        }
+        let entries = match self.read_manifest(id) {
+            Ok(entries) => entries,
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        fs::remove_file(self.manifest_path(id))?;
+        self.release_blobs(&entries, Some((original_path, policy)))
+    }
+
+    /// Reports how much space deduplication saves across the trash.
+    pub fn dedup_stats(&self) -> Result<DedupStats> {
+        let _lock = self.lock_shared()?;
+        let mut stats = DedupStats {
+            items: 0,
+            logical_size: 0,
+            blobs: 0,
+            stored_size: 0,
+        };
+        for id in self.manifest_ids()? {
+            stats.items += 1;
+            stats.logical_size += self
+                .read_manifest(&id)?
+                .iter()
+                .map(|entry| entry.size)
+                .sum::<u64>();
+        }
+
+        for blob in self.blob_paths()? {
+            stats.blobs += 1;
+            stats.stored_size += self.file_system.metadata(&blob)?.len;
+        }
+        Ok(stats)
+    }
+
+    /// Opens a file of a deduplicated item from the blob store, or from the item itself
+    /// for files that were left in place.
+    pub(super) fn open_deduplicated(
+        &self,
+        item_path: &Path,
+        file: &Path,
+    ) -> Result<Box<dyn io::Read>> {
+        let relative = file.strip_prefix(item_path).unwrap_or(file);
+        let id = item_path.file_name().unwrap_or_default();
+        match self
+            .read_manifest(id)?
+            .into_iter()
+            .find(|entry| entry.path == relative)
+        {
+            Some(entry) => Ok(Box::new(fs::File::open(self.blob_path(&entry.hash))?)),
+            None => Ok(Box::new(fs::File::open(file)?)),
+        }
+    }
+
+    /// Returns the path each file of a deduplicated item had before being trashed, with
+    /// the path its contents are read from, sorted by original path.
+    pub(super) fn deduplicated_files(
+        &self,
+        item_path: &Path,
+        original_path: &Path,
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let id = item_path.file_name().unwrap_or_default();
+        let entries = self.read_manifest(id)?;
+        let mut files: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    join_relative(original_path, &entry.path),
+                    self.blob_path(&entry.hash),
+                )
+            })
+            .collect();
+        if item_path.symlink_metadata()?.is_dir() {
+            // Hard-linked files stay in the item
+            for (file, original) in matching_files(item_path, original_path, |_| true)? {
+                files.push((original, file));
+            }
+        }
+        files.sort();
+        // Files left in place are listed with their blob too
+        files.dedup_by(|a, b| a.0 == b.0);
+        Ok(files)
+    }
+
+    /// Copies the contents of a file back from the blob store to `destination`, which
+    /// must not exist. A partial copy is removed.
+    fn copy_blob(&self, entry: &ManifestEntry, destination: &Path) -> Result<()> {
+        let file = fs::File::create_new(destination)?;
+        let copied = fs::File::open(self.blob_path(&entry.hash))
+            .and_then(|mut blob| io::copy(&mut blob, &mut &file))
+            .and_then(|_| file.set_permissions(fs::Permissions::from_mode(entry.mode)))
+            .and_then(|_| file.set_modified(entry.modified));
+        if copied.is_err() {
+            let _ = fs::remove_file(destination);
+        }
+        Ok(copied?)
+    }
+
+    /// Deletes the blobs of `entries` that no remaining manifest refers to. With a shred
+    /// policy, the files it selects are shredded, matched against their original path,
+    /// and the warnings of the shredding are returned.
+    fn release_blobs(
+        &self,
+        entries: &[ManifestEntry],
+        shred: Option<(&Path, &ShredPolicy)>,
+    ) -> Result<Vec<ShredWarning>> {
+        let mut warnings = Vec::new();
+        let mut referenced = self.referenced_blobs()?;
+
+        for entry in entries {
+            let blob = self.blob_path(&entry.hash);
+            if !referenced.insert(entry.hash.clone()) || !blob.exists() {
+                // Still used, or already deleted for an identical file of the same item
+                continue;
+            }
+            match shred {
+                Some((original_path, policy)) => {
+                    let original_path = join_relative(original_path, &entry.path);
+                    warnings.extend(shred_path(&blob, &original_path, policy, &self.workers)?);
+                }
+                None => fs::remove_file(&blob)?,
+            }
+            // Fails as long as other blobs share the directory
+            let _ = fs::remove_dir(blob.parent().unwrap_or(&self.trash_dir));
+        }
+        Ok(warnings)
+    }
+
+    /// Returns the hashes of the blobs some manifest refers to.
+    pub(super) fn referenced_blobs(&self) -> Result<HashSet<String>> {
+        let mut referenced = HashSet::new();
+        for id in self.manifest_ids()? {
+            referenced.extend(self.read_manifest(&id)?.into_iter().map(|entry| entry.hash));
+        }
+        Ok(referenced)
+    }
+
+    /// Returns the path of every blob in the blob store.
+    pub(super) fn blob_paths(&self) -> Result<Vec<PathBuf>> {
+        let blobs_dir = self.trash_dir.join(BLOBS_DIR);
+        if !self.file_system.exists(&blobs_dir) {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .walk(&blobs_dir)?
+            .into_iter()
+            .filter(|(_, meta)| meta.kind == FileKind::File)
+            .map(|(blob, _)| blob)
+            .collect())
+    }
+
+    fn read_manifest(&self, id: &OsStr) -> Result<Vec<ManifestEntry>> {
+        let manifest = self.file_system.read(&self.manifest_path(id))?;
+        manifest
+            .split(|&b| b == 0)
+            .filter(|record| !record.is_empty())
+            .map(|record| {
+                ManifestEntry::decode(record).ok_or_else(|| Error::InvalidAttribute {
+                    attr: MANIFESTS_DIR.to_string(),
+                    id: id.to_string_lossy().to_string(),
+                })
+            })
+            .collect()
+    }
+
+    pub(super) fn manifest_ids(&self) -> Result<Vec<std::ffi::OsString>> {
+        let dir = self.trash_dir.join(MANIFESTS_DIR);
+        if !self.file_system.exists(&dir) {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .file_system
+            .read_dir(&dir)?
+            .into_iter()
+            .map(|path| path.file_name().unwrap_or_default().to_os_string())
+            .collect())
+    }
+
+    fn manifest_path(&self, id: &OsStr) -> PathBuf {
+        self.trash_dir.join(MANIFESTS_DIR).join(id)
+    }
+
+    /// Returns where the contents with the given hash are stored, fanned out over
+    /// subdirectories named after the first two hex digits.
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.trash_dir
+            .join(BLOBS_DIR)
+            .join(&hash[..2.min(hash.len())])
+            .join(hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        audit::AUDIT_DIR,
+        trash::{
+            checksum::CHECKSUMS_DIR, fsck::QUARANTINE_DIR, Issue, IssueKind, Repair, TrashItem,
+        },
+        XAttrManager,
+    };
+    use chrono::Utc;
+    use tempfile::{tempdir, TempDir};
+
+    /// Returns a temporary directory with a `work` directory and a manager deduplicating
+    /// the items of the `trash` next to it.
+    fn dedup_trash() -> Result<(TempDir, TrashManager<XAttrManager>)> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("work"))?;
+        fs::create_dir(dir.path().join("trash"))?;
+        let trash_manager =
+            TrashManager::new(dir.path().join("trash"), XAttrManager::new()?).with_dedup(true);
+        Ok((dir, trash_manager))
+    }
+
+    /// Creates a project with two identical files and one distinct file, and trashes it.
+    fn trash_project(trash_manager: &TrashManager<XAttrManager>, path: &Path) -> Result<TrashItem> {
+        fs::create_dir_all(path.join("src"))?;
+        fs::write(path.join("README.md"), "shared")?;
+        fs::write(path.join("src/copy.md"), "shared")?;
+        fs::write(path.join("src/main.rs"), path.to_string_lossy().as_bytes())?;
+        fs::set_permissions(path.join("src/main.rs"), fs::Permissions::from_mode(0o700))?;
+        trash_manager.trash_items(vec![path.to_path_buf()], Utc::now())?;
+        let item = trash_manager
+            .list_items()?
+            .into_iter()
+            .find(|item| item.original_path == path)
+            .unwrap();
+        assert!(item.deduplicated);
+        Ok(item)
+    }
+
+    fn blobs(trash_manager: &TrashManager<XAttrManager>) -> Result<usize> {
+        Ok(trash_manager.dedup_stats()?.blobs)
+    }
+
+    #[test]
+    fn identical_contents_are_stored_once_and_restored() -> Result<()> {
+        let (dir, trash_manager) = dedup_trash()?;
+        let project = dir.path().join("work/project");
+        let item = trash_project(&trash_manager, &project)?;
+        assert!(!project.exists());
+        assert_eq!(blobs(&trash_manager)?, 2);
+        assert!(item.path.join("src").is_dir());
+        assert!(!item.path.join("README.md").exists());
+
+        trash_manager.restore_item_by_id(&item.id, None)?;
+        assert_eq!(fs::read_to_string(project.join("README.md"))?, "shared");
+        assert_eq!(fs::read_to_string(project.join("src/copy.md"))?, "shared");
+        let mode = project.join("src/main.rs").metadata()?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert_eq!(XAttrManager::new()?.get_attr(&project, DEDUP_ATTR)?, None);
+        assert!(trash_manager.list_items()?.is_empty());
+        assert!(trash_manager.manifest_ids()?.is_empty());
+        assert_eq!(blobs(&trash_manager)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn blobs_are_deleted_once_no_item_refers_to_them() -> Result<()> {
+        let (dir, trash_manager) = dedup_trash()?;
+        let first = trash_project(&trash_manager, &dir.path().join("work/first"))?;
+        let second = trash_project(&trash_manager, &dir.path().join("work/second"))?;
+        // The shared contents are stored once, each main.rs has its own
+        assert_eq!(blobs(&trash_manager)?, 3);
+
+        trash_manager.delete_item_by_id(&first.id)?;
+        assert_eq!(blobs(&trash_manager)?, 2);
+        assert_eq!(
+            trash_manager.manifest_ids()?,
+            vec![std::ffi::OsString::from(&second.id)]
+        );
+
+        trash_manager.restore_item_by_id(&second.id, None)?;
+        assert_eq!(blobs(&trash_manager)?, 0);
+        assert!(trash_manager.manifest_ids()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn check_trash_reports_stored_data_of_vanished_items() -> Result<()> {
+        let (dir, trash_manager) = dedup_trash()?;
+        let first = trash_project(&trash_manager, &dir.path().join("work/first"))?;
+        trash_project(&trash_manager, &dir.path().join("work/second"))?;
+        let audit_dir = trash_manager.trash_dir.join(AUDIT_DIR);
+        fs::create_dir(&audit_dir)?;
+        fs::write(audit_dir.join("audit.jsonl"), "")?;
+        assert!(trash_manager.check_trash()?.is_empty());
+        fs::write(audit_dir.join("notes.txt"), "")?;
+
+        // Removing an item behind rrm's back leaves its manifest, checksums and blobs
+        fs::remove_dir_all(&first.path)?;
+        let kinds = |issues: &[Issue]| -> Vec<(String, String)> {
+            issues
+                .iter()
+                .map(|issue| (issue.id.clone(), issue.kind.to_string()))
+                .collect()
+        };
+        let issues = trash_manager.check_trash()?;
+        assert_eq!(
+            kinds(&issues),
+            vec![
+                (first.id.clone(), IssueKind::OrphanManifest.to_string()),
+                (first.id.clone(), IssueKind::StaleChecksums.to_string()),
+                ("notes.txt".to_string(), IssueKind::Leftover.to_string()),
+            ]
+        );
+
+        // The blobs are only unreferenced once the manifest is gone, and those shared with
+        // the other item are still needed
+        assert!(trash_manager.repair_issue(&issues[0], &Repair::Delete)?);
+        assert!(trash_manager.repair_issue(&issues[1], &Repair::Quarantine)?);
+        let issues = trash_manager.check_trash()?;
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].kind, IssueKind::OrphanBlob);
+        let blob = issues[0].path.clone();
+        assert_eq!(
+            fs::read_to_string(&blob)?,
+            first.original_path.to_string_lossy()
+        );
+        assert!(!trash_manager.repair_issue(
+            &issues[0],
+            &Repair::Adopt {
+                original_dir: dir.path().to_path_buf(),
+                deletion_date: Utc::now(),
+            }
+        )?);
+        assert!(trash_manager.repair_issue(&issues[0], &Repair::Delete)?);
+        assert!(!blob.exists());
+        assert_eq!(blobs(&trash_manager)?, 2);
+
+        let quarantine = trash_manager.trash_dir.join(QUARANTINE_DIR);
+        assert!(quarantine.join(CHECKSUMS_DIR).join(&first.id).exists());
+        assert_eq!(
+            kinds(&trash_manager.check_trash()?),
+            vec![("notes.txt".to_string(), IssueKind::Leftover.to_string())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn restore_with_a_missing_blob_leaves_the_item_in_the_trash() -> Result<()> {
+        let (dir, trash_manager) = dedup_trash()?;
+        // Verification would refuse the item before the rebuild even starts
+        let trash_manager = trash_manager.with_verification(false);
+        let project = dir.path().join("work/project");
+        let item = trash_project(&trash_manager, &project)?;
+        let entries = trash_manager.read_manifest(OsStr::new(&item.id))?;
+        fs::remove_file(trash_manager.blob_path(&entries[0].hash))?;
+
+        let result = trash_manager.restore_item_by_id(&item.id, None);
+        assert!(matches!(result, Err(Error::Io(e)) if e.kind() == io::ErrorKind::NotFound));
+        assert!(!project.exists());
+        assert_eq!(trash_manager.list_items()?.len(), 1);
+        assert_eq!(trash_manager.manifest_ids()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_rebuilds_put_the_item_back_in_the_trash() -> Result<()> {
+        let (dir, trash_manager) = dedup_trash()?;
+        let trash_manager = trash_manager.with_verification(false);
+        let project = dir.path().join("work/project");
+        let item = trash_project(&trash_manager, &project)?;
+        // The blob exists, but cannot be read as a file
+        let entries = trash_manager.read_manifest(OsStr::new(&item.id))?;
+        let blob = trash_manager.blob_path(&entries[entries.len() - 1].hash);
+        fs::remove_file(&blob)?;
+        fs::create_dir(&blob)?;
+
+        assert!(trash_manager.restore_item_by_id(&item.id, None).is_err());
+        assert!(!project.exists());
+        let items = trash_manager.list_items()?;
+        assert_eq!(items.len(), 1);
+        assert!(items[0].deduplicated);
+        assert!(!item.path.join("README.md").exists());
+        assert!(!item.path.join("src/copy.md").exists());
+        assert_eq!(fs::read_dir(dir.path().join("work"))?.count(), 0);
+
+        fs::remove_dir(&blob)?;
+        fs::write(&blob, "restored")?;
+        trash_manager.restore_item_by_id(&item.id, None)?;
+        assert!(project.join("README.md").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_links_leave_the_item_untouched() -> Result<()> {
+        let (dir, trash_manager) = dedup_trash()?;
+        let project = dir.path().join("work/project");
+        fs::create_dir_all(project.join("src"))?;
+        fs::write(project.join("README.md"), "shared")?;
+        fs::write(project.join("src/main.rs"), "distinct")?;
+        // Trashed without deduplication, which is then run by hand
+        TrashManager::new(dir.path().join("trash"), XAttrManager::new()?)
+            .trash_items(vec![project.clone()], Utc::now())?;
+        let item = trash_manager.list_items()?.remove(0);
+        // The fan-out directory of one of the contents is taken by a file
+        let blocked = trash_manager.blob_path(&hash_file(&item.path.join("src/main.rs"))?);
+        let fan_out = blocked.parent().unwrap();
+        fs::create_dir_all(fan_out.parent().unwrap())?;
+        fs::write(fan_out, "")?;
+
+        assert!(trash_manager.dedup_item(&item.path).is_err());
+        assert_eq!(fs::read_to_string(item.path.join("README.md"))?, "shared");
+        assert_eq!(
+            fs::read_to_string(item.path.join("src/main.rs"))?,
+            "distinct"
+        );
+        assert!(!trash_manager.list_items()?[0].deduplicated);
+        assert!(trash_manager.manifest_ids()?.is_empty());
+        assert_eq!(
+            fs::read_dir(dir.path().join("trash").join(BLOBS_DIR))?.count(),
+            1
+        );
+
+        fs::remove_file(fan_out)?;
+        trash_manager.dedup_item(&item.path)?;
+        assert!(trash_manager.list_items()?[0].deduplicated);
+        assert_eq!(blobs(&trash_manager)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_entries_round_trip() {
+        let entry = ManifestEntry {
+            path: PathBuf::from(std::ffi::OsString::from_vec(b"src/a b\t\xff.rs".to_vec())),
+            hash: "ab".repeat(32),
+            mode: 0o100644,
+            modified: UNIX_EPOCH + Duration::new(1_700_000_000, 123),
+            size: 42,
+        };
+        let mut encoded = Vec::new();
+        entry.encode(&mut encoded);
+        assert_eq!(encoded.last(), Some(&0));
+        assert_eq!(
+            ManifestEntry::decode(&encoded[..encoded.len() - 1]),
+            Some(entry)
+        );
+    }
+}
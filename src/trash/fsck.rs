@@ -0,0 +1,375 @@
+use super::{
+    checksum::CHECKSUMS_DIR,
+    dedup::{BLOBS_DIR, MANIFESTS_DIR},
+    TrashManager, DEDUP_ATTR, DELETION_DATE_ATTR, ENCRYPTED_ATTR, ENCRYPTED_PATH, METADATA_DIR,
+    ORIGINAL_PATH_ATTR, SIZE_ATTR,
+};
+use crate::{
+    audit::{is_log_name, AUDIT_DIR},
+    xattr::ExtendedAttributes,
+    Result,
+};
+use chrono::{DateTime, Utc};
+use derive_more::Display;
+use log::warn;
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// Hidden directory inside the trash where quarantined entries are moved to. Entries in
+/// there are ignored by every other command.
+pub(super) const QUARANTINE_DIR: &str = ".quarantine";
+
+/// A problem found in the trash directory.
+pub struct Issue {
+    /// Name of the entry in the trash directory (or in its metadata directory).
+    pub id: String,
+    /// Path of the entry with the problem.
+    pub path: PathBuf,
+    pub kind: IssueKind,
+}
+
+#[derive(Debug, Display, PartialEq)]
+#[non_exhaustive]
+pub enum IssueKind {
+    #[display("Orphan: no rrm metadata, maybe it was not deleted by rrm")]
+    Orphan,
+
+    #[display("Missing or unparsable deletion date")]
+    InvalidDeletionDate,
+
+    #[display("Original path is missing or not absolute")]
+    InvalidOriginalPath,
+
+    #[display("Leftover file from rrm's own bookkeeping")]
+    Leftover,
+
+    #[display("Size mismatch: {} bytes recorded, {} bytes on disk", expected, actual)]
+    SizeMismatch { expected: u64, actual: u64 },
+
+    #[display("Manifest of a deduplicated item that is no longer in the trash")]
+    OrphanManifest,
+
+    #[display("Stored contents no deduplicated item refers to")]
+    OrphanBlob,
+
+    #[display("Checksums of an item that is no longer in the trash")]
+    StaleChecksums,
+}
+
+impl IssueKind {
+    /// Checks whether the entry is rrm's own bookkeeping rather than an item, so there is
+    /// no metadata to fill in by adopting it.
+    fn is_bookkeeping(&self) -> bool {
+        matches!(
+            self,
+            IssueKind::Leftover
+                | IssueKind::OrphanManifest
+                | IssueKind::OrphanBlob
+                | IssueKind::StaleChecksums
+        )
+    }
+}
+
+/// How to repair an [`Issue`].
+#[non_exhaustive]
+pub enum Repair {
+    /// Fill in the missing metadata, so the entry becomes a regular trashed item again.
+    /// Entries without an original path get one in `original_dir`.
+    Adopt {
+        original_dir: PathBuf,
+        deletion_date: DateTime<Utc>,
+    },
+    /// Move the entry out of the way, into the quarantine directory of the trash.
+    Quarantine,
+    /// Permanently delete the entry.
+    Delete,
+}
+
+impl<T: ExtendedAttributes> TrashManager<T> {
+    /// Checks every entry of the trash directory for problems that would otherwise make
+    /// it invisible to `list` and `clean`, and the hidden stores of the trash for entries
+    /// no item needs anymore.
+    pub fn check_trash(&self) -> Result<Vec<Issue>> {
+        let _lock = self.lock_shared()?;
+        let mut issues = Vec::new();
+
+        for path in self.file_system.read_dir(&self.trash_dir)? {
+            let name = path.file_name().unwrap_or_default().to_os_string();
+            if name == METADATA_DIR {
+                issues.extend(self.check_sidecars()?);
+                continue;
+            }
+            if [
+                QUARANTINE_DIR,
+                BLOBS_DIR,
+                MANIFESTS_DIR,
+                CHECKSUMS_DIR,
+                AUDIT_DIR,
+            ]
+            .contains(&name.to_str().unwrap_or_default())
+            {
+                continue;
+            }
+
+            let issue = |kind| Issue {
+                id: name.to_string_lossy().to_string(),
+                path: path.clone(),
+                kind,
+            };
+            if name.as_bytes().starts_with(b".") {
+                issues.push(issue(IssueKind::Leftover));
+                continue;
+            }
+
+            let attrs_path = self.attrs_path(&path);
+            let original_path = self.read_original_path_lenient(&attrs_path);
+            if original_path.is_none() && self.read_deletion_date_lenient(&attrs_path).is_none() {
+                issues.push(issue(IssueKind::Orphan));
+                continue;
+            }
+
+            // Without the key, the original path of an encrypted item cannot be checked
+            if !original_path
+                .is_some_and(|path| path.is_absolute() || path == Path::new(ENCRYPTED_PATH))
+            {
+                issues.push(issue(IssueKind::InvalidOriginalPath));
+            }
+            if self.read_deletion_date_lenient(&attrs_path).is_none() {
+                issues.push(issue(IssueKind::InvalidDeletionDate));
+            }
+            // Encrypted, compressed and deduplicated items do not take up their recorded size
+            let transformed = self.read_flag(&attrs_path, ENCRYPTED_ATTR)
+                || self.read_compression(&attrs_path).is_some()
+                || self.read_flag(&attrs_path, DEDUP_ATTR);
+            if let Some(expected) = self.read_size(&attrs_path).filter(|_| !transformed) {
+                let actual = self.item_size(&path).unwrap_or_default();
+                if expected != actual {
+                    issues.push(issue(IssueKind::SizeMismatch { expected, actual }));
+                }
+            }
+        }
+
+        issues.extend(self.check_stores()?);
+        Ok(issues)
+    }
+
+    /// Applies `repair` to `issue`, returning `false` if the repair does not apply to this
+    /// kind of issue (e.g. adopting a leftover bookkeeping file).
+    pub fn repair_issue(&self, issue: &Issue, repair: &Repair) -> Result<bool> {
+        let _lock = self.lock_exclusive()?;
+        if !self.file_system.exists(&issue.path) {
+            // Already gone, e.g. removed together with an earlier issue of the same entry
+            return Ok(true);
+        }
+
+        match repair {
+            Repair::Adopt {
+                original_dir,
+                deletion_date,
+            } => self.adopt(issue, original_dir, *deletion_date),
+            Repair::Quarantine => {
+                let quarantine = self.trash_dir.join(QUARANTINE_DIR);
+                let name = issue.path.file_name().unwrap_or(OsStr::new(&issue.id));
+                // Entries of the hidden stores keep their store, as a manifest and the
+                // checksums of the same item share a name
+                let destination = match issue.path.parent() {
+                    Some(parent) if parent != self.trash_dir => {
+                        let store = parent.strip_prefix(&self.trash_dir).unwrap_or(parent);
+                        quarantine.join(store).join(name)
+                    }
+                    _ => quarantine.join(name),
+                };
+                let sidecar = self.attrs_path(&issue.path);
+                self.file_system
+                    .create_dir_all(destination.parent().unwrap_or(&quarantine))?;
+                self.file_system.rename(&issue.path, &destination)?;
+                if sidecar != issue.path && self.file_system.exists(&sidecar) {
+                    let mut sidecar_name = name.to_os_string();
+                    sidecar_name.push(".meta");
+                    self.file_system
+                        .rename(&sidecar, &quarantine.join(sidecar_name))?;
+                }
+                Ok(true)
+            }
+            Repair::Delete => {
+                let sidecar = self.attrs_path(&issue.path);
+                self.file_system.remove(&issue.path)?;
+                if sidecar != issue.path && self.file_system.exists(&sidecar) {
+                    self.file_system.remove(&sidecar)?;
+                }
+                if issue.kind == IssueKind::OrphanBlob {
+                    // The fan-out directory goes with its last blob
+                    let fan_out = issue.path.parent().unwrap_or(&self.trash_dir);
+                    if self.file_system.read_dir(fan_out)?.is_empty() {
+                        self.file_system.remove(fan_out)?;
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    fn adopt(
+        &self,
+        issue: &Issue,
+        original_dir: &Path,
+        deletion_date: DateTime<Utc>,
+    ) -> Result<bool> {
+        if issue.kind.is_bookkeeping() {
+            return Ok(false);
+        }
+        let attrs_path = self.attrs_path(&issue.path);
+        if attrs_path != issue.path && !self.file_system.exists(&attrs_path) {
+            // An orphaned symlink needs a sidecar to carry its metadata
+            self.file_system
+                .create_dir_all(&self.trash_dir.join(METADATA_DIR))?;
+            self.file_system.write(&attrs_path, b"")?;
+        }
+
+        match issue.kind {
+            IssueKind::Orphan | IssueKind::InvalidOriginalPath => {
+                let file_name = match self.read_original_path_lenient(&attrs_path) {
+                    Some(path) => path.file_name().map(|name| name.to_os_string()),
+                    None => None,
+                }
+                .or_else(|| issue.path.file_name().map(|name| name.to_os_string()))
+                .unwrap_or_else(|| OsString::from(&issue.id));
+                let original_path = original_dir.join(file_name);
+                self.xattr_manager.set_attr(
+                    &attrs_path,
+                    ORIGINAL_PATH_ATTR,
+                    original_path.as_os_str().as_bytes(),
+                )?;
+                if self.read_deletion_date_lenient(&attrs_path).is_none() {
+                    self.set_deletion_date_attr(&attrs_path, deletion_date)?;
+                }
+                self.set_size_attr(&attrs_path, self.item_size(&issue.path)?)?;
+            }
+            IssueKind::InvalidDeletionDate => {
+                self.set_deletion_date_attr(&attrs_path, deletion_date)?;
+            }
+            IssueKind::SizeMismatch { actual, .. } => {
+                self.set_size_attr(&attrs_path, actual)?;
+            }
+            _ => unreachable!(),
+        }
+        Ok(true)
+    }
+
+    /// Reports the manifests and checksums of items that are gone, the blobs no manifest
+    /// refers to, and the files of the audit log directory that are not logs.
+    fn check_stores(&self) -> Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+        let gone = |name: &OsStr| !self.file_system.exists(&self.trash_dir.join(name));
+        for (path, name) in self.store_entries(MANIFESTS_DIR)? {
+            if gone(&name) {
+                issues.push(store_issue(path, &name, IssueKind::OrphanManifest));
+            }
+        }
+        for (path, name) in self.store_entries(CHECKSUMS_DIR)? {
+            if gone(&name) {
+                issues.push(store_issue(path, &name, IssueKind::StaleChecksums));
+            }
+        }
+
+        // An unreadable manifest hides which blobs are still needed, none can be reported
+        match self.referenced_blobs() {
+            Ok(referenced) => {
+                let mut blobs = self.blob_paths()?;
+                blobs.sort();
+                for blob in blobs {
+                    let hash = blob.file_name().unwrap_or_default().to_os_string();
+                    if !referenced.contains(hash.to_string_lossy().as_ref()) {
+                        issues.push(store_issue(blob, &hash, IssueKind::OrphanBlob));
+                    }
+                }
+            }
+            Err(e) => warn!("Skipping the check of the blob store: {}", e),
+        }
+
+        for (path, name) in self.store_entries(AUDIT_DIR)? {
+            if !is_log_name(&name) {
+                issues.push(store_issue(path, &name, IssueKind::Leftover));
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Returns the paths and names of the entries of a hidden store of the trash, sorted
+    /// by name, or nothing if the store was never created.
+    fn store_entries(&self, store: &str) -> Result<Vec<(PathBuf, OsString)>> {
+        let dir = self.trash_dir.join(store);
+        if !self.file_system.exists(&dir) {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .file_system
+            .read_dir(&dir)?
+            .into_iter()
+            .map(|path| {
+                let name = path.file_name().unwrap_or_default().to_os_string();
+                (path, name)
+            })
+            .collect())
+    }
+
+    /// Reports sidecar files whose item is gone or is not a symlink anymore.
+    fn check_sidecars(&self) -> Result<Vec<Issue>> {
+        let mut issues = Vec::new();
+        for path in self
+            .file_system
+            .read_dir(&self.trash_dir.join(METADATA_DIR))?
+        {
+            let name = path.file_name().unwrap_or_default();
+            if !self.is_symlink(&self.trash_dir.join(name)) {
+                issues.push(Issue {
+                    id: name.to_string_lossy().to_string(),
+                    path,
+                    kind: IssueKind::Leftover,
+                });
+            }
+        }
+        Ok(issues)
+    }
+
+    fn read_original_path_lenient(&self, attrs_path: &Path) -> Option<PathBuf> {
+        match self.xattr_manager.get_attr(attrs_path, ORIGINAL_PATH_ATTR) {
+            Ok(Some(val)) if !val.is_empty() => Some(
+                self.decode_original_path(val)
+                    .unwrap_or_else(|_| PathBuf::from(ENCRYPTED_PATH)),
+            ),
+            _ => None,
+        }
+    }
+
+    fn read_deletion_date_lenient(&self, attrs_path: &Path) -> Option<DateTime<Utc>> {
+        match self.xattr_manager.get_attr(attrs_path, DELETION_DATE_ATTR) {
+            Ok(Some(val)) => std::str::from_utf8(&val)
+                .ok()
+                .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+                .map(|date| date.with_timezone(&Utc)),
+            _ => None,
+        }
+    }
+
+    fn set_deletion_date_attr(&self, attrs_path: &Path, date: DateTime<Utc>) -> Result<()> {
+        self.xattr_manager
+            .set_attr(attrs_path, DELETION_DATE_ATTR, date.to_rfc3339().as_bytes())
+    }
+
+    fn set_size_attr(&self, attrs_path: &Path, size: u64) -> Result<()> {
+        self.xattr_manager
+            .set_attr(attrs_path, SIZE_ATTR, size.to_string().as_bytes())
+    }
+}
+
+fn store_issue(path: PathBuf, name: &OsStr, kind: IssueKind) -> Issue {
+    Issue {
+        id: name.to_string_lossy().to_string(),
+        path,
+        kind,
+    }
+}
@@ -8,11 +8,13 @@ use std::{
 /// Namespace for extended attributes (xattrs) on macOS and other operating systems.
 /// On macOS, this is an empty string, while on other operating systems, it is "user.".
 #[cfg(target_os = "macos")]
-const XATTR_NAMESPACE: &str = "";
+pub(crate) const XATTR_NAMESPACE: &str = "";
 #[cfg(not(target_os = "macos"))]
-const XATTR_NAMESPACE: &str = "user.";
+pub(crate) const XATTR_NAMESPACE: &str = "user.";
 
+/// Failure to read or write an extended attribute.
 #[derive(Debug, Display)]
+#[non_exhaustive]
 pub enum XAttrError {
     #[display("Extended attributes are not supported on this platform")]
     UnsupportedPlatform,
@@ -36,25 +38,38 @@ pub enum XAttrError {
         path: PathBuf,
         source: io::Error,
     },
+}
 
-    #[display("Failed to set attribute '{}' on '{}': {}", attr, path.display(), source)]
-    InvalidUtf8 {
-        attr: String,
-        path: PathBuf,
-        source: std::string::FromUtf8Error,
-    },
+impl std::error::Error for XAttrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            XAttrError::UnsupportedPlatform => None,
+            XAttrError::SetAttr { source, .. }
+            | XAttrError::GetAttr { source, .. }
+            | XAttrError::RemoveAttr { source, .. } => Some(source),
+        }
+    }
 }
 
+/// Storage for the metadata of trashed items and of the configuration.
+///
+/// Attribute values are raw bytes, so paths that are not valid UTF-8 can be stored as is.
+/// Keys are given without a namespace, implementations add whatever their platform needs.
 pub trait ExtendedAttributes {
-    fn set_attr(&self, path: &Path, key: &str, value: &str) -> Result<()>;
-    fn get_attr(&self, path: &Path, key: &str) -> Result<Option<String>>;
+    /// Sets the attribute `key` of `path` to `value`, replacing any previous value.
+    fn set_attr(&self, path: &Path, key: &str, value: &[u8]) -> Result<()>;
+    /// Returns the value of the attribute `key` of `path`, or `None` if it is not set.
+    fn get_attr(&self, path: &Path, key: &str) -> Result<Option<Vec<u8>>>;
+    /// Removes the attribute `key` of `path`, failing if it is not set.
     fn remove_attr(&self, path: &Path, key: &str) -> Result<()>;
 }
 
+/// Stores attributes as extended file attributes, in the `user.` namespace on Linux.
 #[derive(Debug, Clone, Copy)]
 pub struct XAttrManager {}
 
 impl XAttrManager {
+    /// Fails on platforms without extended attributes.
     pub fn new() -> Result<Self> {
         if !xattr::SUPPORTED_PLATFORM {
             return Err(XAttrError::UnsupportedPlatform)?;
@@ -66,10 +81,10 @@ impl XAttrManager {
 
 impl ExtendedAttributes for XAttrManager {
     /// Sets an extended attribute on the file or directory.
-    fn set_attr(&self, path: &Path, attr: &str, value: &str) -> Result<()> {
+    fn set_attr(&self, path: &Path, attr: &str, value: &[u8]) -> Result<()> {
         let attr_name = format!("{}{}", XATTR_NAMESPACE, attr);
         Ok(
-            xattr::set(path, &attr_name, value.as_bytes()).map_err(|e| XAttrError::SetAttr {
+            xattr::set(path, &attr_name, value).map_err(|e| XAttrError::SetAttr {
                 attr: attr_name,
                 path: path.to_path_buf(),
                 source: e,
@@ -90,22 +105,14 @@ impl ExtendedAttributes for XAttrManager {
     }
 
     /// Retrieves an extended attribute from the file or directory.
-    fn get_attr(&self, path: &Path, attr: &str) -> Result<Option<String>> {
+    fn get_attr(&self, path: &Path, attr: &str) -> Result<Option<Vec<u8>>> {
         let attr_name = format!("{}{}", XATTR_NAMESPACE, attr);
-        match xattr::get(path, &attr_name) {
-            Ok(Some(value)) => Ok(Some(String::from_utf8(value).map_err(|e| {
-                XAttrError::InvalidUtf8 {
-                    attr: attr_name,
-                    path: path.to_path_buf(),
-                    source: e,
-                }
-            })?)),
-            Ok(None) => Ok(None),
-            Err(e) => Err(XAttrError::GetAttr {
+        Ok(
+            xattr::get(path, &attr_name).map_err(|e| XAttrError::GetAttr {
                 attr: attr_name,
                 path: path.to_path_buf(),
                 source: e,
             })?,
-        }
+        )
     }
 }
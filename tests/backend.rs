@@ -0,0 +1,182 @@
+//! Implements [`TrashBackend`] outside the crate, the way another storage layout would,
+//! and checks the provided methods of the trait work on top of it.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rrm::{trash::MetadataUpdate, Compression, Error, ErrorKind, Result, TrashBackend, TrashItem};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Keeps items in a map, their contents included.
+#[derive(Default)]
+struct MapBackend {
+    items: RefCell<BTreeMap<String, (TrashItem, Vec<u8>)>>,
+}
+
+impl MapBackend {
+    fn not_found(id: &str) -> Error {
+        Error::ItemNotFound(id.to_string())
+    }
+}
+
+impl TrashBackend for MapBackend {
+    fn put(&self, path: &Path, deletion_date: DateTime<Utc>) -> Result<String> {
+        if path.as_os_str().is_empty() {
+            return Err(Error::InvalidOriginalPath(String::new()));
+        }
+        let mut items = self.items.borrow_mut();
+        let id = format!("item-{}", items.len());
+        let contents = path.to_string_lossy().into_owned().into_bytes();
+        let item = TrashItem::new(
+            id.clone(),
+            PathBuf::from("/map").join(&id),
+            path.to_path_buf(),
+            deletion_date,
+        )
+        .with_size(Some(contents.len() as u64))
+        .with_trash_date(Some(deletion_date - Duration::days(30)))
+        .with_compression(Some(Compression::Zstd));
+        items.insert(id.clone(), (item, contents));
+        Ok(id)
+    }
+
+    fn delete_paths(&self, paths: Vec<PathBuf>) -> Result<()> {
+        self.items
+            .borrow_mut()
+            .retain(|_, (item, _)| !paths.iter().any(|path| path == item.original_path()));
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<TrashItem>> {
+        Ok(self
+            .items
+            .borrow()
+            .values()
+            .map(|(item, _)| item.clone())
+            .collect())
+    }
+
+    fn get(&self, id: &str) -> Result<TrashItem> {
+        self.items
+            .borrow()
+            .get(id)
+            .map(|(item, _)| item.clone())
+            .ok_or_else(|| Self::not_found(id))
+    }
+
+    fn restore(&self, id: &str, _destination: Option<&Path>) -> Result<()> {
+        self.purge(id)
+    }
+
+    fn purge(&self, id: &str) -> Result<()> {
+        self.items
+            .borrow_mut()
+            .remove(id)
+            .map(|_| ())
+            .ok_or_else(|| Self::not_found(id))
+    }
+
+    fn update(&self, id: &str, update: MetadataUpdate) -> Result<()> {
+        let mut items = self.items.borrow_mut();
+        let (item, contents) = items.remove(id).ok_or_else(|| Self::not_found(id))?;
+        let item = match update {
+            MetadataUpdate::DeletionDate(deletion_date) => item.with_deletion_date(deletion_date),
+            MetadataUpdate::Pinned(pinned) => item.with_pinned(pinned),
+            MetadataUpdate::Reschedule(deletion_date) => {
+                item.with_deletion_date(deletion_date).with_pinned(false)
+            }
+            _ => return Err(io::Error::from(io::ErrorKind::Unsupported).into()),
+        };
+        items.insert(id.to_string(), (item, contents));
+        Ok(())
+    }
+
+    fn clean(&self, _immediate: bool, force: bool) -> Result<()> {
+        self.items
+            .borrow_mut()
+            .retain(|_, (item, _)| item.pinned() && !force);
+        Ok(())
+    }
+
+    fn read(&self, item: &TrashItem) -> Result<Vec<u8>> {
+        self.items
+            .borrow()
+            .get(item.id())
+            .map(|(_, contents)| contents.clone())
+            .ok_or_else(|| Self::not_found(item.id()))
+    }
+
+    fn for_each_file(
+        &self,
+        item: &TrashItem,
+        f: &mut dyn FnMut(PathBuf, Result<Vec<u8>>),
+    ) -> Result<()> {
+        f(item.original_path().to_path_buf(), self.read(item));
+        Ok(())
+    }
+}
+
+fn date(day: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2030, 1, day, 12, 0, 0).unwrap()
+}
+
+#[test]
+fn items_built_outside_the_crate_keep_their_metadata() -> Result<()> {
+    let backend = MapBackend::default();
+    let id = backend.put(Path::new("/work/notes.txt"), date(10))?;
+
+    let item = backend.get(&id)?;
+    assert_eq!(item.id(), id);
+    assert_eq!(item.original_path(), Path::new("/work/notes.txt"));
+    assert_eq!(item.deletion_date(), date(10));
+    assert_eq!(item.size(), Some(15));
+    assert_eq!(item.compression(), Some(Compression::Zstd));
+    assert_eq!(item.kind(), "File");
+    assert!(!item.pinned());
+    assert_eq!(backend.read(&item)?, b"/work/notes.txt");
+
+    backend.update(&id, MetadataUpdate::Pinned(true))?;
+    backend.update(&id, MetadataUpdate::DeletionDate(date(20)))?;
+    let item = backend.get(&id)?;
+    assert!(item.pinned());
+    assert_eq!(item.deletion_date(), date(20));
+
+    backend.update(&id, MetadataUpdate::Reschedule(date(25)))?;
+    let item = backend.get(&id)?;
+    assert!(!item.pinned());
+    assert_eq!(item.deletion_date(), date(25));
+    Ok(())
+}
+
+#[test]
+fn provided_methods_work_on_an_outside_backend() -> Result<()> {
+    let backend = MapBackend::default();
+    let error = backend
+        .put_all(
+            vec!["/work/a".into(), PathBuf::new(), "/work/b".into()],
+            date(10),
+        )
+        .unwrap_err();
+    assert!(matches!(error, Error::ItemsFailed { ref failures, .. } if failures.len() == 1));
+    assert_eq!(error.kind(), ErrorKind::PartialFailure);
+    assert_eq!(backend.list()?.len(), 2);
+
+    let first = backend.list()?[0].id().to_string();
+    backend.update(&first, MetadataUpdate::DeletionDate(date(5)))?;
+    assert_eq!(backend.next_deletion_date()?, Some(date(5)));
+    backend.update(&first, MetadataUpdate::Pinned(true))?;
+    assert_eq!(backend.next_deletion_date()?, Some(date(10)));
+
+    assert!(backend.check()?.is_empty());
+    let item = backend.get(&first)?;
+    assert!(backend.verify(&item)?.is_none());
+
+    backend.clean(false, false)?;
+    assert_eq!(backend.list()?.len(), 1);
+    backend.clean(false, true)?;
+    assert_eq!(backend.next_deletion_date()?, None);
+    Ok(())
+}
@@ -0,0 +1,918 @@
+//! End-to-end tests running the `rrm` binary in a sandbox: a temporary home directory
+//! holding the trash, and a copy of the binary whose configuration starts out empty.
+
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::OnceLock,
+    thread,
+    time::Duration,
+};
+use tempfile::{tempdir, tempdir_in, TempDir};
+
+const NOW: &str = "2030-01-01T12:00:00Z";
+
+/// Copies the binary under test, so the trash directory configured on it never comes from
+/// the developer's own `rrm config set --key trash-dir`.
+fn copy_binary(dir: &Path) -> PathBuf {
+    let bin = dir.join("rrm");
+    let partial = dir.join(".rrm.partial");
+    // A fresh inode, since copying over an existing file keeps its extended attributes
+    fs::copy(env!("CARGO_BIN_EXE_rrm"), &partial).unwrap();
+    fs::rename(&partial, &bin).unwrap();
+    bin
+}
+
+/// The binary shared by the tests that do not change its configuration.
+fn shared_binary() -> &'static Path {
+    static BINARY: OnceLock<PathBuf> = OnceLock::new();
+    BINARY.get_or_init(|| copy_binary(Path::new(env!("CARGO_TARGET_TMPDIR"))))
+}
+
+struct Sandbox {
+    dir: TempDir,
+    bin: PathBuf,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("home")).unwrap();
+        Self {
+            dir,
+            bin: shared_binary().to_path_buf(),
+        }
+    }
+
+    /// Creates a sandbox with its own copy of the binary, for tests changing the trash
+    /// directory.
+    fn with_own_binary() -> Self {
+        let sandbox = Self::new();
+        let bin = copy_binary(sandbox.dir.path());
+        Self { bin, ..sandbox }
+    }
+
+    fn home(&self) -> PathBuf {
+        self.dir.path().join("home")
+    }
+
+    fn trash(&self) -> PathBuf {
+        self.home().join(".tmp_trash")
+    }
+
+    fn file(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.home().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Runs rrm at [`NOW`].
+    fn rrm(&self, args: &[&str]) -> Output {
+        self.rrm_at(NOW, args)
+    }
+
+    fn rrm_at(&self, now: &str, args: &[&str]) -> Output {
+        self.command(now, Command::new(&self.bin).args(args))
+            .output()
+            .unwrap()
+    }
+
+    /// Runs rrm at [`NOW`] with its output on a terminal, through `script`, returning
+    /// the output, or `None` if `script` is not installed.
+    fn rrm_on_terminal(&self, args: &[&str]) -> Option<Output> {
+        let line: Vec<String> = [self.bin.to_str().unwrap()]
+            .iter()
+            .chain(args)
+            .map(|arg| format!("'{}'", arg.replace('\'', r"'\''")))
+            .collect();
+        // `script` writes everything the command printed on the terminal to its stdout
+        self.command(
+            NOW,
+            Command::new("script").args(["-qec", &line.join(" "), "/dev/null"]),
+        )
+        .output()
+        .ok()
+    }
+
+    fn command<'a>(&self, now: &str, command: &'a mut Command) -> &'a mut Command {
+        command
+            .current_dir(self.home())
+            .env("HOME", self.home())
+            .env("RRM_NOW", now)
+            .env_remove("RRM_PASSPHRASE")
+            .env_remove("RUST_LOG")
+            .env_remove("XDG_CONFIG_HOME")
+    }
+
+    /// Returns the ids of the items in the trash.
+    fn ids(&self) -> Vec<String> {
+        ids(&self.trash())
+    }
+}
+
+fn ids(trash: &Path) -> Vec<String> {
+    let mut ids: Vec<String> = fs::read_dir(trash)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[track_caller]
+fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "rrm failed with {}: {}",
+        output.status,
+        stderr(output)
+    );
+}
+
+#[test]
+fn rm_list_and_restore() {
+    let sandbox = Sandbox::new();
+    let notes = sandbox.file("notes.md", "content");
+
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    assert!(!notes.exists());
+    let ids = sandbox.ids();
+    assert_eq!(ids.len(), 1);
+
+    let list = sandbox.rrm(&["list"]);
+    assert_success(&list);
+    assert!(stdout(&list).contains(&notes.display().to_string()));
+    assert!(stdout(&list).contains(&ids[0]));
+    // Seven days after NOW, in the default grace period
+    assert!(stdout(&list).contains("2030-01-0"));
+
+    assert_success(&sandbox.rrm(&["restore", &ids[0]]));
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "content");
+    assert!(sandbox.ids().is_empty());
+    assert!(stdout(&sandbox.rrm(&["list"])).contains("The trash is empty."));
+}
+
+#[test]
+fn restore_refuses_modified_items_unless_not_verified() {
+    let sandbox = Sandbox::new();
+    let notes = sandbox.file("notes.md", "content");
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    let id = sandbox.ids().remove(0);
+    fs::write(sandbox.trash().join(&id), "tampered").unwrap();
+
+    let restore = sandbox.rrm(&["restore", &id]);
+    assert_eq!(restore.status.code(), Some(1));
+    assert!(stderr(&restore).contains("contents changed"));
+    assert!(!notes.exists());
+    assert_eq!(sandbox.ids(), vec![id.clone()]);
+
+    assert_success(&sandbox.rrm(&["restore", "--no-verify", &id]));
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "tampered");
+}
+
+#[test]
+fn encrypted_items_are_verified_against_their_plaintext() {
+    let sandbox = Sandbox::new();
+    let key = sandbox.home().join("key");
+    assert_success(&sandbox.rrm(&[
+        "config",
+        "set",
+        "--key",
+        "encryption-key-file",
+        "--value",
+        key.to_str().unwrap(),
+    ]));
+    let secrets = sandbox.home().join("secrets");
+    fs::create_dir(&secrets).unwrap();
+    fs::write(secrets.join(".env"), "TOKEN=1").unwrap();
+    fs::write(secrets.join("id.pem"), "KEY").unwrap();
+    assert_success(&sandbox.rrm(&["rm", "--encrypt", "secrets"]));
+    let id = sandbox.ids().remove(0);
+    assert_success(&sandbox.rrm(&["verify", &id]));
+    // The checksums do not give away the names or contents of the files
+    let manifest = fs::read(sandbox.trash().join(".checksums").join(&id)).unwrap();
+    assert!(!String::from_utf8_lossy(&manifest).contains(".env"));
+
+    // Removing a file does not break the encryption of the others
+    fs::remove_file(sandbox.trash().join(&id).join("id.pem")).unwrap();
+    let verify = sandbox.rrm(&["verify", &id]);
+    assert!(!verify.status.success());
+    assert!(stdout(&verify).contains("missing"));
+    let restore = sandbox.rrm(&["restore", &id]);
+    assert_eq!(restore.status.code(), Some(1));
+    assert!(stderr(&restore).contains("missing"));
+    assert!(!secrets.exists());
+
+    assert_success(&sandbox.rrm(&["restore", "--no-verify", &id]));
+    assert_eq!(fs::read_to_string(secrets.join(".env")).unwrap(), "TOKEN=1");
+}
+
+#[test]
+fn restore_over_an_existing_path_fails_unless_renamed() {
+    let sandbox = Sandbox::new();
+    let notes = sandbox.file("notes.md", "old");
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    fs::write(&notes, "new").unwrap();
+    let id = sandbox.ids().remove(0);
+
+    let restore = sandbox.rrm(&["restore", &id]);
+    assert_eq!(restore.status.code(), Some(4));
+    assert!(stderr(&restore).contains("already exists"));
+    assert_eq!(sandbox.ids(), vec![id.clone()]);
+
+    assert_success(&sandbox.rrm(&["restore", &id, "--rename", "notes.old.md"]));
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "new");
+    assert_eq!(
+        fs::read_to_string(sandbox.home().join("notes.old.md")).unwrap(),
+        "old"
+    );
+}
+
+#[test]
+fn trashed_files_can_be_printed_compared_and_searched() {
+    let sandbox = Sandbox::new();
+    let notes = sandbox.file("notes.md", "first\nsecond\n");
+    let project = sandbox.home().join("project");
+    fs::create_dir(&project).unwrap();
+    fs::write(project.join("main.rs"), "fn main() {}\n").unwrap();
+    fs::write(project.join("data.bin"), b"\0needle\0").unwrap();
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    let notes_id = sandbox.ids().remove(0);
+    assert_success(&sandbox.rrm(&["rm", "project"]));
+    let project_id = sandbox
+        .ids()
+        .into_iter()
+        .find(|id| *id != notes_id)
+        .unwrap();
+
+    let cat = sandbox.rrm(&["cat", &notes_id]);
+    assert_success(&cat);
+    assert_eq!(stdout(&cat), "first\nsecond\n");
+    assert_eq!(sandbox.rrm(&["cat", &project_id]).status.code(), Some(1));
+
+    sandbox.file("notes.md", "first\nthird\n");
+    let diff = stdout(&sandbox.rrm(&["diff", &notes_id]));
+    assert!(diff.contains(&format!("--- trash:{} ({})", notes_id, notes.display())));
+    assert!(diff.contains("-second\n+third\n"), "{}", diff);
+    let diff = stdout(&sandbox.rrm(&["diff", &notes_id, "notes.md"]));
+    assert!(diff.contains("+++ notes.md\n"), "{}", diff);
+    assert!(diff.contains("-second\n+third\n"), "{}", diff);
+    fs::remove_file(&notes).unwrap();
+    let diff = sandbox.rrm(&["diff", &notes_id]);
+    assert_success(&diff);
+    assert!(stdout(&diff).contains("+++ /dev/null"));
+    assert!(stdout(&diff).contains("-first\n-second\n"));
+    // A dangling symlink named like an ID is still a path, not the item
+    std::os::unix::fs::symlink("nowhere", sandbox.home().join(&notes_id)).unwrap();
+    let diff = sandbox.rrm(&["diff", &notes_id, &notes_id]);
+    assert_eq!(diff.status.code(), Some(3), "{}", stderr(&diff));
+
+    let grep = stdout(&sandbox.rrm(&["grep", "SECOND", "--ignore-case"]));
+    assert_eq!(grep, format!("{}:{}:2:second\n", notes_id, notes.display()));
+    let data = format!("{}:{}", project_id, project.join("data.bin").display());
+    let grep = stdout(&sandbox.rrm(&["grep", "needle"]));
+    assert_eq!(grep, format!("{}: binary file matches\n", data));
+    let grep = stdout(&sandbox.rrm(&["grep", "-l", "needle|main"]));
+    let main = format!("{}:{}", project_id, project.join("main.rs").display());
+    let mut lines: Vec<&str> = grep.lines().collect();
+    lines.sort();
+    let mut expected = vec![data.as_str(), main.as_str()];
+    expected.sort();
+    assert_eq!(lines, expected);
+}
+
+#[test]
+fn clean_deletes_items_once_their_grace_period_is_over() {
+    let sandbox = Sandbox::new();
+    sandbox.file("a.txt", "a");
+    sandbox.file("b.txt", "b");
+    assert_success(&sandbox.rrm(&["rm", "a.txt"]));
+    assert_success(&sandbox.rrm(&["rm", "b.txt", "--grace-period", "1d"]));
+
+    assert_success(&sandbox.rrm_at("2030-01-02T11:59:59Z", &["clean"]));
+    assert_eq!(sandbox.ids().len(), 2);
+    assert_success(&sandbox.rrm_at("2030-01-02T12:00:00Z", &["clean"]));
+    assert_eq!(sandbox.ids().len(), 1);
+    assert_success(&sandbox.rrm_at("2030-01-08T12:00:00Z", &["clean"]));
+    assert!(sandbox.ids().is_empty());
+}
+
+#[test]
+fn deletion_dates_are_shown_in_local_time_across_dst_changes() {
+    if !Path::new("/usr/share/zoneinfo/Europe/Berlin").exists() {
+        eprintln!("skipped: no time zone database");
+        return;
+    }
+    let sandbox = Sandbox::new();
+    let list_in_berlin = |now: &str| {
+        let mut command = Command::new(&sandbox.bin);
+        let list = sandbox
+            .command(now, command.arg("list").env("TZ", "Europe/Berlin"))
+            .output()
+            .unwrap();
+        assert_success(&list);
+        stdout(&list)
+    };
+
+    // Clocks in Berlin go from 02:00 to 03:00 on 2030-03-31, a day of 23 hours
+    let before = "2030-03-30T22:30:00Z";
+    sandbox.file("short.txt", "a");
+    sandbox.file("long.txt", "b");
+    assert_success(&sandbox.rrm_at(before, &["rm", "short.txt", "--grace-period", "4h"]));
+    assert_success(&sandbox.rrm_at(before, &["rm", "long.txt", "--grace-period", "1d"]));
+
+    let list = list_in_berlin(before);
+    assert!(list.contains("Tomorrow 04:30"), "{}", list);
+    assert!(list.contains("2030-04-01 00:30:00"), "{}", list);
+    let list = list_in_berlin("2030-03-31T00:30:00Z");
+    assert!(list.contains("Today 04:30"), "{}", list);
+    assert!(list.contains("Tomorrow 00:30"), "{}", list);
+}
+
+#[test]
+fn keep_and_expire_change_the_deletion_date() {
+    let sandbox = Sandbox::new();
+    sandbox.file("notes.md", "content");
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    let id = sandbox.ids().remove(0);
+
+    assert_success(&sandbox.rrm(&["keep", &id, "--forever"]));
+    assert!(stdout(&sandbox.rrm(&["list"])).contains("Never (pinned)"));
+    assert_success(&sandbox.rrm_at("2031-01-01T00:00:00Z", &["clean"]));
+    assert_eq!(sandbox.ids(), vec![id.clone()]);
+
+    // Keeping an item for a while unpins it in the same update
+    assert_success(&sandbox.rrm(&["keep", &id, "--for", "1d"]));
+    assert!(!stdout(&sandbox.rrm(&["list"])).contains("Never (pinned)"));
+    assert_eq!(
+        log_entries(&sandbox, &[])
+            .iter()
+            .filter(|(action, _)| action == "update")
+            .count(),
+        2
+    );
+    assert_success(&sandbox.rrm(&["keep", &id, "--forever"]));
+
+    assert_success(&sandbox.rrm(&["expire", &id]));
+    assert_success(&sandbox.rrm(&["clean"]));
+    assert!(sandbox.ids().is_empty());
+}
+
+#[test]
+fn config_is_stored_with_the_trash() {
+    let sandbox = Sandbox::new();
+    let set = sandbox.rrm(&["config", "set", "--key", "grace-period", "--value", "2d"]);
+    assert_success(&set);
+    assert_eq!(stdout(&set), "Set grace period to 2d\n");
+    let get = sandbox.rrm(&["config", "get", "--key", "grace-period"]);
+    assert_eq!(stdout(&get), "Grace period: 2d\n");
+
+    sandbox.file("notes.md", "content");
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    assert_success(&sandbox.rrm_at("2030-01-03T12:00:00Z", &["clean"]));
+    assert!(sandbox.ids().is_empty());
+
+    let invalid = sandbox.rrm(&["config", "set", "--key", "grace-period", "--value", "soon"]);
+    assert_eq!(invalid.status.code(), Some(7));
+    assert!(stderr(&invalid).contains("'soon' is not a valid duration"));
+    let get = sandbox.rrm(&["config", "get", "--key", "grace-period"]);
+    assert_eq!(stdout(&get), "Grace period: 2d\n");
+}
+
+#[test]
+fn trash_dir_is_stored_on_the_binary() {
+    let sandbox = Sandbox::with_own_binary();
+    let trash = sandbox.dir.path().join("elsewhere");
+    assert_success(&sandbox.rrm(&[
+        "config",
+        "set",
+        "--key",
+        "trash-dir",
+        "--value",
+        trash.to_str().unwrap(),
+    ]));
+    let get = sandbox.rrm(&["config", "get", "--key", "trash-dir"]);
+    assert_eq!(
+        stdout(&get),
+        format!("Trash directory: {}\n", trash.display())
+    );
+
+    sandbox.file("notes.md", "content");
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    assert_eq!(ids(&trash).len(), 1);
+    assert!(!sandbox.trash().exists() || sandbox.ids().is_empty());
+}
+
+#[test]
+fn errors_are_reported_on_stderr() {
+    let sandbox = Sandbox::new();
+    let restore = sandbox.rrm(&["restore", "no-such-id"]);
+    assert_eq!(restore.status.code(), Some(3));
+    assert_eq!(stderr(&restore), "Item no-such-id not found in the trash\n");
+
+    let rm = sandbox.rrm(&["rm", "missing.txt"]);
+    assert_eq!(rm.status.code(), Some(3));
+    assert_eq!(
+        stderr(&rm),
+        "missing.txt: No such file or directory (os error 2)\n"
+    );
+
+    let invalid_now = sandbox.rrm_at("tomorrow", &["list"]);
+    assert_eq!(invalid_now.status.code(), Some(7));
+    assert!(stderr(&invalid_now).contains("Invalid RRM_NOW 'tomorrow'"));
+}
+
+#[test]
+fn failed_items_do_not_stop_the_others() {
+    let sandbox = Sandbox::new();
+    sandbox.file("a.txt", "a");
+    let rm = sandbox.rrm(&["rm", "missing.txt", "a.txt", "gone.txt"]);
+    assert_eq!(rm.status.code(), Some(6));
+    assert_eq!(
+        stderr(&rm),
+        "missing.txt: No such file or directory (os error 2)\n\
+         gone.txt: No such file or directory (os error 2)\n\
+         2 of 3 items failed\n"
+    );
+    assert_eq!(sandbox.ids().len(), 1);
+}
+
+#[test]
+fn failing_to_trash_every_item_keeps_the_missing_ones() {
+    let sandbox = Sandbox::new();
+    sandbox.file("a.txt", "a");
+    // The trash vanishes before it can be locked
+    let hook = format!(
+        "mv '{trash}' '{trash}.moved' && ln -s nowhere '{trash}'",
+        trash = sandbox.trash().display()
+    );
+    assert_success(&sandbox.rrm(&["config", "set", "--key", "pre-trash-hook", "--value", &hook]));
+
+    let rm = sandbox.rrm(&["rm", "--json-errors", "missing.txt", "a.txt"]);
+    assert_eq!(rm.status.code(), Some(6));
+    let error: serde_json::Value = serde_json::from_slice(&rm.stderr).unwrap();
+    assert_eq!(error["message"], "2 of 2 items failed");
+    assert_eq!(error["items"][0]["item"], "missing.txt");
+    assert_eq!(error["items"][1]["item"], "a.txt");
+    assert!(sandbox.home().join("a.txt").exists());
+}
+
+#[test]
+fn errors_can_be_printed_as_json() {
+    let sandbox = Sandbox::new();
+    let rm = sandbox.rrm(&["rm", "--json-errors", "missing.txt", "gone.txt"]);
+    assert_eq!(rm.status.code(), Some(6));
+    let error: serde_json::Value = serde_json::from_slice(&rm.stderr).unwrap();
+    assert_eq!(error["error"], "partial_failure");
+    assert_eq!(error["code"], 6);
+    assert_eq!(error["message"], "2 of 2 items failed");
+    assert_eq!(error["items"][1]["item"], "gone.txt");
+    assert_eq!(error["items"][1]["error"], "not_found");
+
+    let restore = sandbox.rrm(&["--json-errors", "restore", "no-such-id"]);
+    assert_eq!(restore.status.code(), Some(3));
+    let error: serde_json::Value = serde_json::from_slice(&restore.stderr).unwrap();
+    assert_eq!(error["error"], "not_found");
+    assert_eq!(error["message"], "Item no-such-id not found in the trash");
+    assert!(error.get("items").is_none());
+}
+
+#[test]
+fn fsck_fails_while_issues_remain() {
+    let sandbox = Sandbox::new();
+    assert_success(&sandbox.rrm(&["fsck"]));
+    fs::write(sandbox.trash().join("orphan"), "orphan").unwrap();
+    fs::write(sandbox.trash().join(".journal.tmp"), "").unwrap();
+
+    let check = sandbox.rrm(&["fsck"]);
+    assert_eq!(check.status.code(), Some(10));
+    assert_eq!(stderr(&check), "2 issue(s) found in the trash\n");
+
+    // Leftovers cannot be adopted
+    let adopt = sandbox.rrm(&["fsck", "--repair", "adopt"]);
+    assert_eq!(adopt.status.code(), Some(10));
+    assert_eq!(stderr(&adopt), "1 issue(s) found in the trash\n");
+
+    fs::write(sandbox.trash().join(".quarantine"), "").unwrap();
+    let quarantine = sandbox.rrm(&["fsck", "--repair", "quarantine"]);
+    assert_eq!(quarantine.status.code(), Some(4));
+    assert!(stderr(&quarantine).starts_with(".journal.tmp: "));
+
+    fs::remove_file(sandbox.trash().join(".quarantine")).unwrap();
+    assert_success(&sandbox.rrm(&["fsck", "--repair", "delete"]));
+    assert_success(&sandbox.rrm(&["fsck"]));
+}
+
+/// Runs `rrm log --json` with `args` and returns the action and path of every entry.
+fn log_entries(sandbox: &Sandbox, args: &[&str]) -> Vec<(String, String)> {
+    let output = sandbox.rrm(&[&["log", "--json"], args].concat());
+    assert_success(&output);
+    stdout(&output)
+        .lines()
+        .map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            let field = |name: &str| entry[name].as_str().unwrap_or_default().to_string();
+            (field("action"), field("path"))
+        })
+        .collect()
+}
+
+#[test]
+fn trash_operations_are_recorded_in_the_audit_log() {
+    let sandbox = Sandbox::new();
+    let a = sandbox.file("a.txt", "a");
+    let b = sandbox.file("b.txt", "b");
+    let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+    assert_success(&sandbox.rrm(&["rm", "a.txt", "b.txt"]));
+    assert_eq!(log_entries(&sandbox, &[]).len(), 2);
+
+    let output = sandbox.rrm(&["log", "--json", "--path", "a.txt"]);
+    let entry: serde_json::Value = serde_json::from_str(stdout(&output).trim_end()).unwrap();
+    assert_eq!(entry["time"], "2030-01-01T12:00:00Z");
+    assert_eq!(entry["size"], 1);
+    assert_eq!(entry["deletion_date"], "2030-01-08T12:00:00Z");
+    let id = entry["id"].as_str().unwrap().to_string();
+    assert!(!entry["user"].as_str().unwrap().is_empty());
+    assert!(!entry["op_id"].as_str().unwrap().is_empty());
+
+    assert_success(&sandbox.rrm(&["keep", &id, "--forever"]));
+    assert_success(&sandbox.rrm(&["restore", &id]));
+    assert_success(&sandbox.rrm_at("2030-02-01T12:00:00Z", &["clean"]));
+    assert_success(&sandbox.rrm(&["config", "set", "--key", "dedup", "--value", "true"]));
+
+    let entries = log_entries(&sandbox, &[]);
+    let actions: Vec<&str> = entries.iter().map(|(action, _)| action.as_str()).collect();
+    assert_eq!(
+        actions,
+        vec!["trash", "trash", "update", "restore", "purge", "config"]
+    );
+    assert_eq!(
+        log_entries(&sandbox, &["--path", "b.txt"]),
+        vec![
+            ("trash".to_string(), b.to_string()),
+            ("purge".to_string(), b.to_string())
+        ]
+    );
+    assert_eq!(
+        log_entries(&sandbox, &["--path", a]),
+        vec![
+            ("trash".to_string(), a.to_string()),
+            ("update".to_string(), String::new()),
+            ("restore".to_string(), a.to_string())
+        ]
+    );
+    assert_eq!(
+        log_entries(&sandbox, &["--since", "2030-01-15T00:00:00Z"]),
+        vec![("purge".to_string(), b.to_string())]
+    );
+    let recent = sandbox.rrm_at("2030-02-01T12:30:00Z", &["log", "--since", "1h"]);
+    assert!(stdout(&recent).contains("purge"));
+    assert!(!stdout(&recent).contains("trash"));
+
+    // The log is part of the trash, not a leftover
+    assert_eq!(
+        stdout(&sandbox.rrm(&["fsck"])),
+        "No issues found in the trash.\n"
+    );
+}
+
+#[test]
+fn hooks_run_around_operations_and_pre_hooks_can_refuse_them() {
+    let sandbox = Sandbox::new();
+    let hooks_dir = sandbox.home().join(".config/rrm/hooks");
+    fs::create_dir_all(&hooks_dir).unwrap();
+    let restored = sandbox.home().join("restored.json");
+    let executable = hooks_dir.join("post-restore");
+    fs::write(
+        &executable,
+        format!("#!/bin/sh\ncat > {}\n", restored.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&executable, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let set_hook = |key: &str, command: &str| {
+        assert_success(&sandbox.rrm(&["config", "set", "--key", key, "--value", command]));
+    };
+    set_hook(
+        "pre-trash-hook",
+        "case \"$RRM_PATH\" in *.sql) exit 1;; esac",
+    );
+    set_hook("post-purge-hook", "echo \"$RRM_ID\" >> purged.txt");
+
+    sandbox.file("dump.sql", "data");
+    let notes = sandbox.file("notes.md", "notes");
+    let rm = sandbox.rrm(&["rm", "dump.sql"]);
+    assert_eq!(rm.status.code(), Some(9));
+    assert!(stderr(&rm).contains("The pre-trash hook"));
+    assert!(sandbox.home().join("dump.sql").exists());
+
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    let id = sandbox.ids().remove(0);
+    assert_success(&sandbox.rrm(&["restore", &id]));
+    let input: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&restored).unwrap()).unwrap();
+    assert_eq!(input["event"], "post-restore");
+    assert_eq!(input["id"], id.as_str());
+    assert_eq!(input["path"], notes.to_str().unwrap());
+
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    let id = sandbox.ids().remove(0);
+    assert_success(&sandbox.rrm_at("2030-02-01T12:00:00Z", &["clean"]));
+    assert_eq!(
+        fs::read_to_string(sandbox.home().join("purged.txt")).unwrap(),
+        format!("{}\n", id)
+    );
+}
+
+/// Hooks run while the trash is not locked, or rrm would wait for itself forever.
+#[test]
+fn hooks_can_run_rrm_on_the_same_trash() {
+    let sandbox = Sandbox::new();
+    let rrm = format!("timeout 10 {}", sandbox.bin.display());
+    let set_hook = |key: &str, command: &str| {
+        assert_success(&sandbox.rrm(&["config", "set", "--key", key, "--value", command]));
+    };
+    set_hook("pre-trash-hook", &format!("{} list > pre-trash.txt", rrm));
+    set_hook(
+        "post-trash-hook",
+        &format!("{} keep --forever \"$RRM_ID\"", rrm),
+    );
+    set_hook(
+        "pre-restore-hook",
+        &format!("{} list > pre-restore.txt", rrm),
+    );
+
+    sandbox.file("notes.md", "notes");
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    assert!(sandbox.home().join("pre-trash.txt").exists());
+    let list = stdout(&sandbox.rrm(&["list"]));
+    assert!(list.contains("Never (pinned)"), "{}", list);
+
+    let id = sandbox.ids().remove(0);
+    assert_success(&sandbox.rrm(&["restore", &id]));
+    let listed = fs::read_to_string(sandbox.home().join("pre-restore.txt")).unwrap();
+    assert!(listed.contains(&id));
+    assert!(sandbox.home().join("notes.md").exists());
+}
+
+#[test]
+fn processes_wait_for_the_trash_lock() {
+    let sandbox = Sandbox::new();
+    sandbox.file("first.txt", "first");
+    assert_success(&sandbox.rrm(&["rm", "first.txt"]));
+    sandbox.file("a.txt", "a");
+    sandbox.file("b.txt", "b");
+
+    // Hold the lock like an rrm in the middle of a change, so the others queue up behind it
+    let lock = fs::File::open(sandbox.trash()).unwrap();
+    lock.lock().unwrap();
+    let spawn = |args: &[&str]| {
+        let mut command = Command::new(&sandbox.bin);
+        sandbox
+            .command(NOW, command.args(args).stdout(Stdio::piped()))
+            .spawn()
+            .unwrap()
+    };
+    let mut children = vec![
+        spawn(&["rm", "a.txt"]),
+        spawn(&["rm", "b.txt"]),
+        spawn(&["list"]),
+    ];
+    thread::sleep(Duration::from_millis(300));
+    for child in &mut children {
+        assert!(child.try_wait().unwrap().is_none());
+    }
+    assert!(sandbox.home().join("a.txt").exists());
+    assert!(sandbox.home().join("b.txt").exists());
+
+    drop(lock);
+    let outputs: Vec<Output> = children
+        .into_iter()
+        .map(|child| child.wait_with_output().unwrap())
+        .collect();
+    for output in &outputs {
+        assert_success(output);
+    }
+    assert_eq!(sandbox.ids().len(), 3);
+    let trashed = log_entries(&sandbox, &[])
+        .into_iter()
+        .filter(|(action, _)| action == "trash")
+        .count();
+    assert_eq!(trashed, 3);
+}
+
+#[test]
+fn stats_can_be_printed_as_json() {
+    let sandbox = Sandbox::new();
+    sandbox.file("a.txt", "aaaa");
+    fs::create_dir(sandbox.home().join("project")).unwrap();
+    sandbox.file("project/b.rs", "bb");
+    assert_success(&sandbox.rrm(&["rm", "a.txt", "project"]));
+
+    let output = sandbox.rrm(&["stats", "--json"]);
+    assert_success(&output);
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats["items"], 2);
+    assert_eq!(stats["size"], 6);
+    assert_eq!(stats["largest"][0]["size"], 4);
+    let extensions: Vec<&str> = stats["by_extension"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|group| group["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(extensions, vec![".txt", "(directory)"]);
+}
+
+#[test]
+fn dry_runs_plan_changes_without_making_them() {
+    let sandbox = Sandbox::new();
+    let notes = sandbox.file("notes.md", "content");
+    // Not even the trash directory is created
+    let rm = sandbox.rrm(&["--dry-run", "rm", "notes.md"]);
+    assert_success(&rm);
+    assert!(stdout(&rm).contains(&format!("create directory {}", sandbox.trash().display())));
+    assert!(!sandbox.trash().exists());
+    let rm = sandbox.rrm(&["--dry-run", "rm", "--immediate", "notes.md", "missing.md"]);
+    assert_eq!(rm.status.code(), Some(6));
+    assert!(stdout(&rm).contains(&format!("remove {} (7 B)", notes.display())));
+    assert!(notes.exists());
+    assert!(!sandbox.trash().exists());
+
+    assert_success(&sandbox.rrm(&["rm", "notes.md"]));
+    let id = sandbox.ids().remove(0);
+    let draft = sandbox.file("draft.md", "draft");
+
+    let rm = sandbox.rrm(&["--dry-run", "rm", "draft.md"]);
+    assert_success(&rm);
+    assert!(draft.exists());
+    assert_eq!(sandbox.ids(), vec![id.clone()]);
+    assert!(stdout(&rm).contains(&format!("move {} to ", draft.display())));
+
+    let clean = sandbox.rrm(&["clean", "--immediate", "--dry-run=json"]);
+    assert_success(&clean);
+    let plan: serde_json::Value = serde_json::from_slice(&clean.stdout).unwrap();
+    assert_eq!(plan["actions"][0]["action"], "remove");
+    assert_eq!(
+        plan["actions"][0]["path"],
+        sandbox.trash().join(&id).display().to_string()
+    );
+    assert_eq!(plan["reclaimed"], 7);
+    assert_eq!(sandbox.ids(), vec![id.clone()]);
+
+    // Conflicts are found like they would be for real
+    fs::write(&notes, "new").unwrap();
+    let restore = sandbox.rrm(&["--dry-run", "restore", &id]);
+    assert_eq!(restore.status.code(), Some(4));
+    assert!(stdout(&restore).is_empty());
+    assert_eq!(log_entries(&sandbox, &[]).len(), 1);
+}
+
+#[test]
+fn trees_are_deleted_with_several_jobs_and_no_progress_when_quiet() {
+    let sandbox = Sandbox::new();
+    for project in ["app", "lib"] {
+        for n in 0..20 {
+            let package = sandbox
+                .home()
+                .join(project)
+                .join("node_modules")
+                .join(format!("package-{}", n % 4));
+            fs::create_dir_all(&package).unwrap();
+            fs::write(package.join(format!("index-{}.js", n)), "module").unwrap();
+        }
+    }
+
+    let Some(rm) = sandbox.rrm_on_terminal(&["--jobs", "4", "--quiet", "rm", "--immediate", "app"])
+    else {
+        eprintln!("skipped: script is not installed to run rrm on a terminal");
+        return;
+    };
+    assert_success(&rm);
+    assert!(!sandbox.home().join("app").exists());
+    assert!(stdout(&rm).is_empty());
+
+    assert_success(&sandbox.rrm(&["rm", "lib"]));
+    let clean = sandbox
+        .rrm_on_terminal(&["-j", "4", "clean", "--immediate"])
+        .unwrap();
+    assert_success(&clean);
+    assert!(stdout(&clean).contains("Purging ["));
+    assert!(sandbox.ids().is_empty());
+}
+
+/// Returns a temporary directory on tmpfs, if it is a different filesystem than the
+/// sandboxes.
+fn tmpfs_dir() -> Option<TempDir> {
+    let shm = Path::new("/dev/shm");
+    let dir = tempdir_in(shm).ok()?;
+    let sandbox = tempdir().ok()?;
+    let device = |path: &Path| fs::metadata(path).map(|meta| meta.dev()).ok();
+    (device(dir.path()) != device(sandbox.path())).then_some(dir)
+}
+
+#[test]
+fn rm_and_restore_across_filesystems() {
+    let Some(tmpfs) = tmpfs_dir() else {
+        eprintln!("skipped: no tmpfs on another device than the temporary directory");
+        return;
+    };
+    if xattr::set(tmpfs.path(), "user.rrm-test", b"").is_err() {
+        eprintln!("skipped: tmpfs does not support user extended attributes");
+        return;
+    }
+
+    let sandbox = Sandbox::new();
+    let project = tmpfs.path().join("project");
+    fs::create_dir(&project).unwrap();
+    fs::write(project.join("main.rs"), "fn main() {}").unwrap();
+    std::os::unix::fs::symlink("main.rs", project.join("link")).unwrap();
+
+    assert_success(&sandbox.rrm(&["rm", project.to_str().unwrap()]));
+    assert!(!project.exists());
+    let id = sandbox.ids().remove(0);
+    assert!(stdout(&sandbox.rrm(&["list"])).contains(&project.display().to_string()));
+
+    assert_success(&sandbox.rrm(&["restore", &id]));
+    assert_eq!(
+        fs::read_to_string(project.join("main.rs")).unwrap(),
+        "fn main() {}"
+    );
+    assert_eq!(
+        fs::read_link(project.join("link")).unwrap(),
+        Path::new("main.rs")
+    );
+    assert!(sandbox.ids().is_empty());
+}
+
+#[test]
+fn deduplicated_items_are_restored_across_filesystems() {
+    let Some(tmpfs) = tmpfs_dir() else {
+        eprintln!("skipped: no tmpfs on another device than the temporary directory");
+        return;
+    };
+    if xattr::set(tmpfs.path(), "user.rrm-test", b"").is_err() {
+        eprintln!("skipped: tmpfs does not support user extended attributes");
+        return;
+    }
+
+    let sandbox = Sandbox::new();
+    assert_success(&sandbox.rrm(&["config", "set", "--key", "dedup", "--value", "true"]));
+    let project = tmpfs.path().join("project");
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::write(project.join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(project.join("copy.rs"), "fn main() {}").unwrap();
+
+    assert_success(&sandbox.rrm(&["rm", project.to_str().unwrap()]));
+    let id = sandbox.ids().remove(0);
+    assert!(!sandbox.trash().join(&id).join("copy.rs").exists());
+
+    assert_success(&sandbox.rrm(&["restore", &id]));
+    for file in ["src/main.rs", "copy.rs"] {
+        assert_eq!(
+            fs::read_to_string(project.join(file)).unwrap(),
+            "fn main() {}"
+        );
+    }
+    assert_eq!(fs::read_dir(tmpfs.path()).unwrap().count(), 1);
+    assert!(sandbox.ids().is_empty());
+}
+
+#[test]
+fn trash_without_extended_attributes_is_an_error() {
+    let Some(tmpfs) = tmpfs_dir() else {
+        eprintln!("skipped: no tmpfs on another device than the temporary directory");
+        return;
+    };
+    if xattr::set(tmpfs.path(), "user.rrm-test", b"").is_ok() {
+        eprintln!("skipped: tmpfs supports user extended attributes");
+        return;
+    }
+
+    let sandbox = Sandbox::with_own_binary();
+    let trash = tmpfs.path().join("trash");
+    assert_success(&sandbox.rrm(&[
+        "config",
+        "set",
+        "--key",
+        "trash-dir",
+        "--value",
+        trash.to_str().unwrap(),
+    ]));
+    let notes = sandbox.file("notes.md", "content");
+    let rm = sandbox.rrm(&["rm", "notes.md"]);
+    assert_eq!(rm.status.code(), Some(8));
+    assert!(stderr(&rm).contains("attribute"));
+    assert_eq!(fs::read_to_string(notes).unwrap(), "content");
+}